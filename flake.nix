@@ -26,8 +26,6 @@
     mkCommonArgs = pkgs: {
       src = (crane.mkLib pkgs).cleanCargoSource self;
 
-      RUSTC_BOOTSTRAP = 1;
-
       nativeBuildInputs = with pkgs; [
         pkg-config
       ];
@@ -56,6 +54,7 @@
       (crane.mkLib pkgs).buildPackage (
         nixpkgs.lib.recursiveUpdate (mkCommonArgs pkgs) {
           cargoArtifacts = mkCargoArtifacts pkgs;
+          CFDDNS_GIT_REV = self.shortRev or self.dirtyShortRev or null;
         }
       );
 
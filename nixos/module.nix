@@ -21,22 +21,76 @@ in {
           a_interface = lib.mkOption {
             default = null;
             description = ''
-              Network interface to obtain IPv4 from.
+              Network interface, or list of interfaces tried in order, to
+              obtain IPv4 from.
+
+              The numerically lowest global address is used if the interface
+              has more than one.
 
               Takes priority over {option}`services.cfddns.settings.a_http` if
               non-null.
             '';
-            example = "bond-wan";
-            type = lib.types.nullOr lib.types.str;
+            example = ["eth0" "wwan0"];
+            type = lib.types.nullOr (lib.types.either lib.types.str (lib.types.listOf lib.types.str));
           };
 
           a_http = lib.mkOption {
             default = null;
-            description = "URL that returns an IPv4 from an HTTP GET.";
+            description = ''
+              URL that returns an IPv4 from an HTTP GET, or a list of URLs
+              that are tried in order until one succeeds.
+
+              Takes priority over {option}`services.cfddns.settings.a_dns` if
+              non-null.
+            '';
             example = "https://ipv4.icanhazip.com";
+            type = lib.types.nullOr (lib.types.either lib.types.str (lib.types.listOf lib.types.str));
+          };
+
+          a_http_json_field = lib.mkOption {
+            default = null;
+            description = ''
+              Field containing the IPv4 if
+              {option}`services.cfddns.settings.a_http` returns JSON.
+
+              Defaults to `ip` if null.
+            '';
+            example = "address";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          a_dns = lib.mkOption {
+            default = null;
+            description = ''
+              OpenDNS resolver to query `myip.opendns.com` against to obtain
+              IPv4 from.
+
+              Takes priority over {option}`services.cfddns.settings.a_stun` if
+              non-null.
+            '';
+            example = "208.67.222.222";
             type = lib.types.nullOr lib.types.str;
           };
 
+          a_stun = lib.mkOption {
+            default = null;
+            description = ''
+              STUN server to obtain IPv4 from.
+
+              Takes priority over {option}`services.cfddns.settings.a_command`
+              if non-null.
+            '';
+            example = "stun.cloudflare.com:3478";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          a_command = lib.mkOption {
+            default = null;
+            description = "Command that prints an IPv4 to stdout.";
+            example = ["/path/to/get-ipv4"];
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+          };
+
           aaaa_interface = lib.mkOption {
             default = null;
             description = "Network interface to obtain IPv6 prefix.";
@@ -46,20 +100,287 @@ in {
 
           aaaa_http = lib.mkOption {
             default = null;
-            description = "URL that returns an IPv6 from an HTTP GET.";
+            description = ''
+              URL that returns an IPv6 from an HTTP GET, or a list of URLs
+              that are tried in order until one succeeds.
+
+              Takes priority over
+              {option}`services.cfddns.settings.aaaa_command` if non-null.
+            '';
             example = "https://ipv6.icanhazip.com";
+            type = lib.types.nullOr (lib.types.either lib.types.str (lib.types.listOf lib.types.str));
+          };
+
+          aaaa_http_json_field = lib.mkOption {
+            default = null;
+            description = ''
+              Field containing the IPv6 if
+              {option}`services.cfddns.settings.aaaa_http` returns JSON.
+
+              Defaults to `ip` if null.
+            '';
+            example = "address";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          aaaa_command = lib.mkOption {
+            default = null;
+            description = "Command that prints an IPv6 to stdout.";
+            example = ["/path/to/get-ipv6"];
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+          };
+
+          prefix_len = lib.mkOption {
+            default = null;
+            description = ''
+              Length of the delegated IPv6 prefix.
+
+              Defaults to 64 if null.
+            '';
+            example = 56;
+            type = lib.types.nullOr (lib.types.ints.between 1 128);
+          };
+
+          ipv6_prefix_significant_len = lib.mkOption {
+            default = null;
+            description = ''
+              Number of leading IPv6 prefix bits that must change to update
+              records.
+
+              Useful when an ISP rotates only the low bits of the delegated
+              prefix. The whole prefix is compared if null.
+            '';
+            example = 48;
+            type = lib.types.nullOr (lib.types.ints.between 1 128);
+          };
+
+          exclude_addresses = lib.mkOption {
+            default = null;
+            description = ''
+              Addresses or subnets to ignore on
+              {option}`services.cfddns.settings.a_interface` and
+              {option}`services.cfddns.settings.aaaa_interface`, such as
+              VPN-assigned addresses.
+            '';
+            example = ["10.8.0.0/16" "2001:db8:1::/48"];
+            type = lib.types.nullOr (lib.types.listOf lib.types.str);
+          };
+
+          fail_on_cgnat = lib.mkOption {
+            default = null;
+            description = ''
+              Fail instead of warning when the detected IPv4 address is in the
+              carrier-grade NAT range 100.64.0.0/10.
+
+              Addresses in this range are not reachable from the internet.
+            '';
+            type = lib.types.nullOr lib.types.bool;
+          };
+
+          detect_mode = lib.mkOption {
+            default = null;
+            description = ''
+              How to choose between multiple configured address sources.
+
+              `precedence` uses the first configured source: interface, HTTP,
+              DNS, STUN, then command. `race` queries every configured source
+              concurrently and uses the first to succeed.
+            '';
+            example = "race";
+            type = lib.types.nullOr (lib.types.enum ["precedence" "race"]);
+          };
+
+          ip_detect_retries = lib.mkOption {
+            default = null;
+            description = ''
+              Number of times to retry address detection after a failure,
+              such as when the WAN interface has no global address yet at
+              boot.
+
+              Defaults to 0 if null.
+            '';
+            example = 5;
+            type = lib.types.nullOr lib.types.ints.unsigned;
+          };
+
+          ip_detect_retry_delay_secs = lib.mkOption {
+            default = null;
+            description = ''
+              Delay in seconds between address detection attempts.
+
+              Defaults to 5 if null.
+            '';
+            type = lib.types.nullOr lib.types.ints.unsigned;
+          };
+
+          require_both = lib.mkOption {
+            default = null;
+            description = ''
+              Fail the update when either IPv4 or IPv6 detection fails.
+
+              By default a detection failure in one address family is logged
+              and records of the other family are still updated.
+            '';
+            type = lib.types.nullOr lib.types.bool;
+          };
+
+          track_records = lib.mkOption {
+            default = null;
+            description = ''
+              Compare the content of every record to the detected addresses on
+              each run, restoring records that were changed outside of cfddns.
+
+              This lists the records of every zone on each run. By default
+              records are only updated when the detected addresses change.
+            '';
+            type = lib.types.nullOr lib.types.bool;
+          };
+
+          quiet_unchanged = lib.mkOption {
+            default = null;
+            description = ''
+              Log unchanged addresses and runs without updates at the debug
+              level instead of info, keeping logs readable when the addresses
+              are stable.
+
+              Changes and errors are logged as usual.
+            '';
+            type = lib.types.nullOr lib.types.bool;
+          };
+
+          verify_via_dns = lib.mkOption {
+            default = null;
+            description = ''
+              Resolve each record before updating it, and skip records that
+              already resolve to the detected addresses.
+
+              This avoids writes when history is lost but DNS is already
+              correct. Proxied and CNAME records are always updated.
+            '';
+            type = lib.types.nullOr lib.types.bool;
+          };
+
+          verify_resolver = lib.mkOption {
+            default = null;
+            description = ''
+              DNS server address and port queried for
+              {option}`services.cfddns.settings.verify_via_dns`.
+
+              A recursive resolver may answer from its cache for up to the
+              record TTL, so a record changed outside of cfddns can appear
+              correct until the cached answer expires. Use an authoritative
+              nameserver of the zone to avoid this.
+
+              Defaults to `1.1.1.1:53` if null.
+            '';
+            example = "8.8.8.8:53";
             type = lib.types.nullOr lib.types.str;
           };
 
+          prefer_stable_ipv6 = lib.mkOption {
+            default = null;
+            description = ''
+              Select addresses on
+              {option}`services.cfddns.settings.aaaa_interface` in order of
+              stable, deprecated, then temporary (privacy extension) addresses,
+              breaking ties by the lowest address.
+
+              The lowest address is selected if null or false.
+            '';
+            type = lib.types.nullOr lib.types.bool;
+          };
+
           zones = lib.mkOption {
             default = [];
             type = lib.types.listOf (lib.types.submodule {
               freeformType = lib.types.attrsOf settingsFormat.type;
               options = {
                 name = lib.mkOption {
-                  description = "Zone name";
+                  default = null;
+                  description = ''
+                    Zone name.
+
+                    Used to look up the zone identifier if
+                    {option}`id` is null.
+                  '';
                   example = "mydomain.com";
-                  type = lib.types.str;
+                  type = lib.types.nullOr lib.types.str;
+                };
+                id = lib.mkOption {
+                  default = null;
+                  description = ''
+                    Zone identifier.
+
+                    Skips the zone name lookup if non-null.
+                  '';
+                  example = "023e105f4ecef8ad9ca31a8372d0c353";
+                  type = lib.types.nullOr lib.types.str;
+                };
+                default_ttl = lib.mkOption {
+                  default = null;
+                  description = ''
+                    TTL for records in this zone without a TTL.
+
+                    Must be 1 or "auto" for automatic, or between 60 and 86400.
+                  '';
+                  example = 300;
+                  type = lib.types.nullOr (lib.types.either (lib.types.enum ["auto"]) lib.types.ints.positive);
+                };
+                default_suffix = lib.mkOption {
+                  default = null;
+                  description = ''
+                    IPv6 suffix for AAAA records in this zone without a
+                    suffix, in the same forms as the record
+                    {option}`suffix`.
+
+                    Not used for records with `eui64_from_interface`,
+                    `full_ipv6`, or a `target`.
+
+                    cfddns also accepts this as `default_eui64` in a
+                    configuration file.
+                  '';
+                  example = "::aaaa:bbbb:cccc:dddd";
+                  type = lib.types.nullOr lib.types.str;
+                };
+                fqdn_from_zone = lib.mkOption {
+                  default = null;
+                  description = ''
+                    Append the zone name to record names that are not within
+                    the zone, allowing short names such as `www`.
+
+                    `@` or an empty name refers to the zone apex.
+                  '';
+                  type = lib.types.nullOr lib.types.bool;
+                };
+                prune = lib.mkOption {
+                  default = null;
+                  description = ''
+                    Delete A and AAAA records previously updated by cfddns
+                    that have been removed from {option}`records`.
+
+                    Records not managed by cfddns are never deleted.
+                  '';
+                  type = lib.types.nullOr lib.types.bool;
+                };
+                token_env = lib.mkOption {
+                  default = null;
+                  description = ''
+                    Environment variable with the API token for this zone,
+                    for zones in a different Cloudflare account.
+
+                    Zones without a token use the global credentials.
+                  '';
+                  example = "CLOUDFLARE_TOKEN_OTHER_ACCOUNT";
+                  type = lib.types.nullOr lib.types.str;
+                };
+                default_proxied = lib.mkOption {
+                  default = null;
+                  description = ''
+                    Proxied setting for records in this zone without a
+                    proxied setting.
+                  '';
+                  example = true;
+                  type = lib.types.nullOr lib.types.bool;
                 };
                 records = lib.mkOption {
                   default = [];
@@ -67,30 +388,128 @@ in {
                     freeformType = lib.types.attrsOf settingsFormat.type;
                     options = {
                       name = lib.mkOption {
-                        description = "Record name";
-                        example = "mysubdomain";
-                        type = lib.types.str;
+                        description = ''
+                          Record name, or `@` for the zone apex.
+
+                          A list of names updates each name with the same
+                          settings.
+                        '';
+                        example = ["www" "mail" "vpn"];
+                        type = lib.types.either lib.types.str (lib.types.listOf lib.types.str);
+                      };
+                      type = lib.mkOption {
+                        default = null;
+                        description = ''
+                          Record type to update.
+
+                          Both A and AAAA records are updated if null, or a
+                          CNAME record if {option}`target` is set. If null and
+                          the name only has records of one family, only that
+                          family is updated unless {option}`create_missing` is
+                          set.
+                        '';
+                        type = lib.types.nullOr (lib.types.enum ["A" "AAAA" "CNAME"]);
                       };
                       ttl = lib.mkOption {
-                        description = "Record TTL";
+                        description = ''
+                          Record TTL.
+
+                          Must be 1 or "auto" for automatic, or between 60 and
+                          86400.
+                        '';
                         default = null;
-                        type = lib.types.nullOr lib.types.int;
+                        type = lib.types.nullOr (lib.types.either (lib.types.enum ["auto"]) lib.types.int);
                       };
                       proxied = lib.mkOption {
                         description = "Record proxy status";
                         default = null;
                         type = lib.types.nullOr lib.types.bool;
                       };
+                      comment = lib.mkOption {
+                        description = ''
+                          Record comment, set when the record is created or
+                          updated.
+
+                          Updating a record without a comment removes any
+                          existing comment.
+                        '';
+                        default = null;
+                        example = "managed by cfddns";
+                        type = lib.types.nullOr lib.types.str;
+                      };
                       suffix = lib.mkOption {
                         default = null;
                         description = ''
                           Record IPv6 suffix.
 
+                          Either an IPv6 address such as `::1234:5678:9abc:def0`,
+                          or a 64-bit interface identifier such as
+                          `1234:5678:9abc:def0` or `0x123456789abcdef0`.
+
                           IPv6 updates are skipped if null.
                         '';
                         example = "::aaaa:aaaa:aaaa:aaaa";
                         type = lib.types.nullOr lib.types.str;
                       };
+                      prefix_len = lib.mkOption {
+                        default = null;
+                        description = ''
+                          IPv6 prefix length for this record, overriding
+                          {option}`services.cfddns.settings.prefix_len`.
+
+                          The detected prefix is masked to this length before
+                          adding the {option}`suffix`, allowing the suffix to
+                          set a subnet ID within a shorter delegated prefix.
+                        '';
+                        example = 48;
+                        type = lib.types.nullOr (lib.types.ints.between 1 128);
+                      };
+                      eui64_from_interface = lib.mkOption {
+                        default = null;
+                        description = ''
+                          Interface to derive the record IPv6 suffix from, using
+                          the modified EUI-64 of the interface MAC address.
+
+                          Mutually exclusive with {option}`suffix`.
+                        '';
+                        example = "eth0";
+                        type = lib.types.nullOr lib.types.str;
+                      };
+                      full_ipv6 = lib.mkOption {
+                        default = null;
+                        description = ''
+                          Update the AAAA record with the full IPv6 address of
+                          {option}`services.cfddns.settings.aaaa_interface`
+                          instead of the prefix and suffix.
+
+                          The address is selected as described in
+                          {option}`services.cfddns.settings.prefer_stable_ipv6`.
+                          Without an interface the address returned by
+                          {option}`services.cfddns.settings.aaaa_http` is used
+                          as-is.
+
+                          Ignored if {option}`suffix` is non-null.
+                        '';
+                        type = lib.types.nullOr lib.types.bool;
+                      };
+                      create_missing = lib.mkOption {
+                        default = null;
+                        description = ''
+                          Create the record if it does not exist.
+
+                          Missing records are an error if null or false.
+                        '';
+                        type = lib.types.nullOr lib.types.bool;
+                      };
+                      target = lib.mkOption {
+                        default = null;
+                        description = ''
+                          Manage this record as a CNAME to the target instead
+                          of A and AAAA records.
+                        '';
+                        example = "host.example.net";
+                        type = lib.types.nullOr lib.types.str;
+                      };
                     };
                   });
                 };
@@ -100,13 +519,246 @@ in {
 
           history_path = lib.mkOption {
             default = "/var/lib/cfddns/history.json";
-            description = "History file for retaining previous IPs";
-            type = lib.types.str;
+            description = ''
+              History file for retaining previous IPs.
+
+              History is only kept in memory if null, for deployments without
+              persistent storage. Records are then compared to the detected
+              addresses on every start.
+            '';
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          report_path = lib.mkOption {
+            default = null;
+            description = ''
+              File to write a JSON report of each run to, including the
+              detected addresses, updated records, and errors.
+            '';
+            example = "/var/lib/cfddns/report.json";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          metrics_path = lib.mkOption {
+            default = null;
+            description = ''
+              File to write Prometheus metrics to after each run, for the
+              node_exporter textfile collector.
+            '';
+            example = "/var/lib/prometheus-node-exporter-text-files/cfddns.prom";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          token_path = lib.mkOption {
+            default = null;
+            description = ''
+              File containing the Cloudflare API token.
+
+              Takes precedence over the `CLOUDFLARE_TOKEN` environment variable.
+            '';
+            example = "/run/secrets/cloudflare-token";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          credential_name = lib.mkOption {
+            default = null;
+            description = ''
+              Name of the systemd credential containing the Cloudflare API
+              token, loaded from {env}`CREDENTIALS_DIRECTORY`.
+
+              Used when {option}`token_path` is null and the credential exists,
+              otherwise the `CLOUDFLARE_TOKEN` environment variable is used.
+
+              The credential is loaded from
+              {option}`services.cfddns.credentialFile`.
+            '';
+            example = "cloudflare-token";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          lock_path = lib.mkOption {
+            default = null;
+            description = ''
+              Lock file to prevent concurrent runs.
+
+              If the lock is held by another instance this run exits without
+              doing anything.
+            '';
+            example = "/var/lib/cfddns/cfddns.lock";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          max_retries = lib.mkOption {
+            default = null;
+            description = ''
+              Maximum number of retries for transient Cloudflare API failures.
+              Record creation is only retried if the request never reached
+              Cloudflare, to avoid creating duplicate records.
+
+              Defaults to 3 if null.
+            '';
+            type = lib.types.nullOr lib.types.ints.unsigned;
+          };
+
+          base_delay_ms = lib.mkOption {
+            default = null;
+            description = ''
+              Initial delay in milliseconds between Cloudflare API retries,
+              doubled on each subsequent retry.
+
+              Defaults to 500 if null.
+            '';
+            type = lib.types.nullOr lib.types.ints.unsigned;
+          };
+
+          max_concurrent_requests = lib.mkOption {
+            default = null;
+            description = ''
+              Maximum number of Cloudflare API requests in flight, shared by
+              all zones including zones with their own token.
+
+              Defaults to 4 if null.
+            '';
+            type = lib.types.nullOr lib.types.ints.positive;
+          };
+
+          http_timeout_secs = lib.mkOption {
+            default = null;
+            description = ''
+              Timeout in seconds for HTTP IP detection requests.
+
+              Defaults to 10 if null.
+            '';
+            type = lib.types.nullOr lib.types.ints.positive;
+          };
+
+          cloudflare_timeout_secs = lib.mkOption {
+            default = null;
+            description = ''
+              Timeout in seconds for each Cloudflare API request.
+
+              Bounds how long a stalled request can block a run.
+              Defaults to 30 if null.
+            '';
+            type = lib.types.nullOr lib.types.ints.positive;
+          };
+
+          proxy = lib.mkOption {
+            default = null;
+            description = ''
+              Proxy URL for HTTP IP detection, notification, and Cloudflare API
+              requests.
+
+              DNS and STUN IP detection are not proxied.
+              The `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY` environment
+              variables are used if null.
+            '';
+            example = "http://proxy.example.com:3128";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          cloudflare_base_url = lib.mkOption {
+            default = null;
+            description = ''
+              Cloudflare API base URL, such as a mock server for testing.
+
+              The production API is used if null.
+            '';
+            example = "http://127.0.0.1:8080/client/v4/";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          user_agent = lib.mkOption {
+            default = null;
+            description = ''
+              User-Agent header for HTTP IP detection and notification
+              requests.
+
+              Defaults to `cfddns/<version>` if null.
+            '';
+            example = "cfddns (admin@example.com)";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          http_source_interface = lib.mkOption {
+            default = null;
+            description = ''
+              Network interface to send HTTP IP detection requests from.
+
+              Useful on multi-homed hosts where the default route does not
+              egress through the interface being published.
+              Only supported on Linux.
+            '';
+            example = "bond-wan";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          notify_webhook = lib.mkOption {
+            default = null;
+            description = ''
+              URL to POST a JSON notification to when the public IPv4 address,
+              IPv6 prefix, or IPv6 address changes.
+
+              The body contains `old_ipv4`, `new_ipv4`, `old_ipv6_prefix`,
+              `new_ipv6_prefix`, `old_ipv6_address`, and `new_ipv6_address`.
+            '';
+            example = "https://example.com/cfddns-webhook";
+            type = lib.types.nullOr lib.types.str;
+          };
+
+          notify = lib.mkOption {
+            default = null;
+            description = ''
+              Slack or Discord incoming webhook to send a message to when the
+              public IPv4 address, IPv6 prefix, or IPv6 address changes.
+
+              `template` may contain the `{old_ipv4}`, `{new_ipv4}`,
+              `{old_ipv6_prefix}`, `{new_ipv6_prefix}`, `{old_ipv6_address}`,
+              and `{new_ipv6_address}` placeholders.
+            '';
+            example = {
+              kind = "discord";
+              url = "https://discord.com/api/webhooks/id/token";
+              template = "IPv4 changed to {new_ipv4}";
+            };
+            type = lib.types.nullOr (lib.types.submodule {
+              options = {
+                kind = lib.mkOption {
+                  description = "Chat provider of the webhook.";
+                  type = lib.types.enum ["slack" "discord"];
+                };
+                url = lib.mkOption {
+                  description = "Incoming webhook URL.";
+                  type = lib.types.str;
+                };
+                template = lib.mkOption {
+                  default = null;
+                  description = "Message template, a default message is used if null.";
+                  type = lib.types.nullOr lib.types.str;
+                };
+              };
+            });
+          };
+
+          healthcheck_url = lib.mkOption {
+            default = null;
+            description = ''
+              Healthchecks.io style URL to GET after each successful run.
+
+              `/fail` is appended to the URL when a run fails.
+            '';
+            example = "https://hc-ping.com/your-uuid-here";
+            type = lib.types.nullOr lib.types.str;
           };
 
           log_level = lib.mkOption {
             default = "info";
-            description = "Logging level.";
+            description = ''
+              Logging level.
+
+              Overridden by the `RUST_LOG` environment variable if set, which
+              accepts a level or env_logger directives such as `cfddns=debug`.
+            '';
             type = lib.types.enum [
               "off"
               "error"
@@ -116,6 +768,22 @@ in {
               "trace"
             ];
           };
+
+          log_format = lib.mkOption {
+            default = "journal";
+            description = ''
+              Logging format.
+
+              `journal` logs to the systemd journal, `json` writes newline
+              delimited JSON to stderr, and `plain` writes human readable lines
+              to stderr.
+            '';
+            type = lib.types.enum [
+              "journal"
+              "json"
+              "plain"
+            ];
+          };
         };
       };
     };
@@ -128,6 +796,8 @@ in {
         cfddns uses the following environment variables for passing secrets:
 
         * `CLOUDFLARE_TOKEN`: Cloudflare API token
+        * `CLOUDFLARE_EMAIL` and `CLOUDFLARE_API_KEY`: Cloudflare account email
+          and global API key, an alternative to `CLOUDFLARE_TOKEN`
 
         Example contents:
 
@@ -138,6 +808,17 @@ in {
       example = ["/run/keys/cfddns.env"];
     };
 
+    credentialFile = lib.mkOption {
+      type = lib.types.nullOr lib.types.path;
+      default = null;
+      description = ''
+        File containing the Cloudflare API token, loaded as the systemd
+        credential named by {option}`services.cfddns.settings.credential_name`
+        with `LoadCredential`, see {manpage}`systemd.exec(5)`.
+      '';
+      example = "/run/keys/cloudflare-token";
+    };
+
     startAt = lib.mkOption {
       type = lib.types.str;
       default = "*:0/10";
@@ -151,6 +832,13 @@ in {
   };
 
   config = lib.mkIf cfg.enable {
+    assertions = [
+      {
+        assertion = cfg.credentialFile != null -> cfg.settings.credential_name != null;
+        message = "services.cfddns.credentialFile requires services.cfddns.settings.credential_name";
+      }
+    ];
+
     systemd.services.cfddns = {
       wants = ["network-online.target"];
       after = ["network-online.target"];
@@ -164,6 +852,7 @@ in {
         KillSignal = "SIGINT";
         ExecStart = "${lib.getExe cfg.package} ${configurationFile}";
         EnvironmentFile = cfg.environmentFiles;
+        LoadCredential = lib.mkIf (cfg.credentialFile != null) "${cfg.settings.credential_name}:${cfg.credentialFile}";
 
         # hardening
         StateDirectory = "cfddns";
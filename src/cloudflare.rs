@@ -1,12 +1,20 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Write};
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
 #[derive(Deserialize, Debug)]
 pub struct Zone {
     pub id: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct DeletedDnsRecord {
+    pub id: String,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename = "status", rename_all = "lowercase")]
 pub enum Status {
@@ -18,7 +26,7 @@ pub enum Status {
     Deactivated,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
 #[serde(tag = "type")]
 #[allow(clippy::upper_case_acronyms)]
 pub enum DnsContent {
@@ -31,12 +39,43 @@ pub enum DnsContent {
     SRV { content: String },
 }
 
-#[derive(Deserialize, Debug)]
+impl DnsContent {
+    /// Record type name, such as `AAAA`
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            DnsContent::A { .. } => "A",
+            DnsContent::AAAA { .. } => "AAAA",
+            DnsContent::CNAME { .. } => "CNAME",
+            DnsContent::NS { .. } => "NS",
+            DnsContent::MX { .. } => "MX",
+            DnsContent::TXT { .. } => "TXT",
+            DnsContent::SRV { .. } => "SRV",
+        }
+    }
+}
+
+impl fmt::Display for DnsContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsContent::A { content } => write!(f, "A {content}"),
+            DnsContent::AAAA { content } => write!(f, "AAAA {content}"),
+            DnsContent::CNAME { content } => write!(f, "CNAME {content}"),
+            DnsContent::NS { content } => write!(f, "NS {content}"),
+            DnsContent::MX { content, priority } => write!(f, "MX {priority} {content}"),
+            DnsContent::TXT { content } => write!(f, "TXT {content}"),
+            DnsContent::SRV { content } => write!(f, "SRV {content}"),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct DnsRecord {
     pub name: String,
     #[serde(flatten)]
     pub content: DnsContent,
     pub id: String,
+    #[serde(default)]
+    pub proxied: Option<bool>,
 }
 
 #[derive(Serialize, Clone, Debug, Default)]
@@ -86,11 +125,49 @@ pub struct UpdateDnsRecordParams<'a> {
     pub ttl: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<&'a str>,
+    pub name: &'a str,
+    #[serde(flatten)]
+    pub content: DnsContent,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CreateDnsRecordParams<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<&'a str>,
     pub name: &'a str,
     #[serde(flatten)]
     pub content: DnsContent,
 }
 
+/// Body of a batch DNS record request
+///
+/// Cloudflare applies the batch atomically, either every record is changed or
+/// none are.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct BatchDnsRecordsParams<'a> {
+    /// Records to overwrite
+    pub puts: Vec<BatchPut<'a>>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchPut<'a> {
+    pub id: &'a str,
+    #[serde(flatten)]
+    pub params: UpdateDnsRecordParams<'a>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchDnsRecords {
+    #[serde(default)]
+    pub puts: Vec<DnsRecord>,
+}
+
 #[derive(Deserialize, Debug, Eq, PartialEq)]
 pub struct ResultInfo {
     pub page: u32,
@@ -112,7 +189,7 @@ pub struct ApiResponse<T> {
 
 #[derive(Deserialize, Debug, Eq, PartialEq)]
 pub struct ApiMessage {
-    pub code: u16,
+    pub code: u32,
     pub message: String,
     #[serde(flatten)]
     pub other: serde_json::Map<String, serde_json::Value>,
@@ -141,6 +218,23 @@ impl fmt::Display for ApiError {
     }
 }
 
+impl ApiError {
+    /// Returns true if the request may succeed when retried
+    ///
+    /// Requests that are not idempotent are only retried on connect errors,
+    /// where the request never reached the server.
+    fn is_transient(&self, idempotent: bool) -> bool {
+        match self {
+            ApiError::Request(e) if e.is_connect() => true,
+            _ if !idempotent => false,
+            ApiError::Http(status, _) => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::Request(e) => e.is_timeout() || e.is_request(),
+        }
+    }
+}
+
 impl From<reqwest::Error> for ApiError {
     fn from(e: reqwest::Error) -> Self {
         ApiError::Request(e)
@@ -149,34 +243,147 @@ impl From<reqwest::Error> for ApiError {
 
 const API_BASE: &str = "https://api.cloudflare.com/client/v4/";
 
+/// Default timeout for Cloudflare API requests
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn http_client(
+    proxy: Option<&url::Url>,
+    timeout: Duration,
+) -> Result<reqwest::Client, reqwest::Error> {
+    let mut builder: reqwest::ClientBuilder = reqwest::Client::builder().timeout(timeout);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+    }
+    builder.build()
+}
+
+/// Default maximum number of requests in flight
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 4;
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Longest delay accepted from a `Retry-After` header
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Exponential backoff delay for a retry attempt
+///
+/// `jitter` is a random value in the range `[0, 1)`, adding up to 50% of the
+/// delay.
+fn backoff_delay(base_delay: Duration, attempt: u32, jitter: f64) -> Duration {
+    let delay: Duration = base_delay.saturating_mul(2_u32.saturating_pow(attempt));
+    delay.saturating_add(delay.mul_f64(jitter / 2.0))
+}
+
+/// Parse the delay from a `Retry-After` header in seconds form
+///
+/// The delay is capped at [`MAX_RETRY_AFTER`].
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|secs| Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
+
+/// Cloudflare API credentials
+pub enum Credentials {
+    /// API token
+    UserAuthToken { token: String },
+    /// Legacy global API key
+    UserAuthKey { email: String, key: String },
+}
+
+impl Credentials {
+    fn authenticate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Credentials::UserAuthToken { token } => request.bearer_auth(token),
+            Credentials::UserAuthKey { email, key } => request
+                .header("X-Auth-Email", email)
+                .header("X-Auth-Key", key),
+        }
+    }
+}
+
 pub struct Client {
     http: reqwest::Client,
-    auth_header: String,
+    proxy: Option<url::Url>,
+    timeout: Duration,
+    api_base: String,
+    credentials: Credentials,
+    max_retries: u32,
+    base_delay: Duration,
+    /// Limits the number of requests in flight, may be shared with other
+    /// clients
+    requests: Arc<Semaphore>,
 }
 
 impl Client {
-    pub fn new(token: String) -> Result<Client, reqwest::Error> {
+    pub fn new(credentials: Credentials) -> Result<Client, reqwest::Error> {
         Ok(Client {
-            http: reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(30))
-                .build()?,
-            auth_header: format!("Bearer {token}"),
+            http: http_client(None, DEFAULT_TIMEOUT)?,
+            proxy: None,
+            timeout: DEFAULT_TIMEOUT,
+            api_base: API_BASE.to_string(),
+            credentials,
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: DEFAULT_BASE_DELAY,
+            requests: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_REQUESTS)),
         })
     }
 
+    /// Set the retry policy for transient failures
+    pub fn with_retries(mut self, max_retries: u32, base_delay: Duration) -> Client {
+        self.max_retries = max_retries;
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Set the maximum number of requests in flight
+    pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Client {
+        self.requests = Arc::new(Semaphore::new(max_concurrent_requests));
+        self
+    }
+
+    /// Share the limit of requests in flight with other clients
+    ///
+    /// Each permit of `requests` allows one request in flight.
+    pub fn with_shared_requests(mut self, requests: Arc<Semaphore>) -> Client {
+        self.requests = requests;
+        self
+    }
+
+    /// Send all requests through `proxy`
+    pub fn with_proxy(mut self, proxy: &url::Url) -> Result<Client, reqwest::Error> {
+        self.proxy = Some(proxy.clone());
+        self.http = http_client(self.proxy.as_ref(), self.timeout)?;
+        Ok(self)
+    }
+
+    /// Set the timeout of each request, including the response body
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Client, reqwest::Error> {
+        self.timeout = timeout;
+        self.http = http_client(self.proxy.as_ref(), self.timeout)?;
+        Ok(self)
+    }
+
+    /// Use a different API base URL, such as a mock server
+    pub fn with_api_base(mut self, api_base: &str) -> Client {
+        self.api_base = api_base.to_string();
+        self
+    }
+
     pub async fn list_zones(
         &self,
         params: &ListZonesParams,
     ) -> Result<ApiResponse<Vec<Zone>>, ApiError> {
-        let resp = self
-            .http
-            .get(format!("{API_BASE}zones"))
-            .header("Authorization", &self.auth_header)
-            .query(params)
-            .send()
-            .await?;
-
-        self.handle_response(resp).await
+        self.request_with_retry(true, || {
+            self.http
+                .get(format!("{}zones", self.api_base))
+                .query(params)
+        })
+        .await
     }
 
     pub async fn list_dns_records(
@@ -184,15 +391,26 @@ impl Client {
         zone_id: &str,
         params: &ListDnsRecordsParams,
     ) -> Result<ApiResponse<Vec<DnsRecord>>, ApiError> {
-        let resp = self
-            .http
-            .get(format!("{API_BASE}zones/{zone_id}/dns_records"))
-            .header("Authorization", &self.auth_header)
-            .query(params)
-            .send()
-            .await?;
+        self.request_with_retry(true, || {
+            self.http
+                .get(format!("{}zones/{zone_id}/dns_records", self.api_base))
+                .query(params)
+        })
+        .await
+    }
 
-        self.handle_response(resp).await
+    pub async fn create_dns_record(
+        &self,
+        zone_id: &str,
+        params: &CreateDnsRecordParams<'_>,
+    ) -> Result<ApiResponse<DnsRecord>, ApiError> {
+        // a retried create may duplicate the record
+        self.request_with_retry(false, || {
+            self.http
+                .post(format!("{}zones/{zone_id}/dns_records", self.api_base))
+                .json(params)
+        })
+        .await
     }
 
     pub async fn update_dns_record(
@@ -201,15 +419,93 @@ impl Client {
         record_id: &str,
         params: &UpdateDnsRecordParams<'_>,
     ) -> Result<ApiResponse<DnsRecord>, ApiError> {
-        let resp = self
-            .http
-            .put(format!("{API_BASE}zones/{zone_id}/dns_records/{record_id}"))
-            .header("Authorization", &self.auth_header)
-            .json(params)
-            .send()
-            .await?;
+        self.request_with_retry(true, || {
+            self.http
+                .put(format!(
+                    "{}zones/{zone_id}/dns_records/{record_id}",
+                    self.api_base
+                ))
+                .json(params)
+        })
+        .await
+    }
+
+    pub async fn batch_dns_records(
+        &self,
+        zone_id: &str,
+        params: &BatchDnsRecordsParams<'_>,
+    ) -> Result<ApiResponse<BatchDnsRecords>, ApiError> {
+        // the batch only contains overwrites, which are safe to repeat
+        self.request_with_retry(true, || {
+            self.http
+                .post(format!(
+                    "{}zones/{zone_id}/dns_records/batch",
+                    self.api_base
+                ))
+                .json(params)
+        })
+        .await
+    }
+
+    pub async fn delete_dns_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+    ) -> Result<ApiResponse<DeletedDnsRecord>, ApiError> {
+        self.request_with_retry(true, || {
+            self.http.delete(format!(
+                "{}zones/{zone_id}/dns_records/{record_id}",
+                self.api_base
+            ))
+        })
+        .await
+    }
+
+    /// Send a request, retrying transient failures with exponential backoff
+    ///
+    /// Requests that are not `idempotent` are only retried if they never
+    /// reached the server.
+    async fn request_with_retry<T: serde::de::DeserializeOwned>(
+        &self,
+        idempotent: bool,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<ApiResponse<T>, ApiError> {
+        let mut attempt: u32 = 0;
+        loop {
+            let mut rate_limit_delay: Option<Duration> = None;
+
+            let permit: SemaphorePermit<'_> = self
+                .requests
+                .acquire()
+                .await
+                .expect("request semaphore is never closed");
 
-        self.handle_response(resp).await
+            let result: Result<ApiResponse<T>, ApiError> =
+                match self.credentials.authenticate(request()).send().await {
+                    Ok(resp) => {
+                        if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                            rate_limit_delay = retry_after(resp.headers());
+                        }
+                        self.handle_response(resp).await
+                    }
+                    Err(e) => Err(e.into()),
+                };
+
+            // do not hold the permit while waiting to retry
+            drop(permit);
+
+            match result {
+                Err(e) if attempt < self.max_retries && e.is_transient(idempotent) => {
+                    let delay: Duration = rate_limit_delay.unwrap_or_else(|| {
+                        backoff_delay(self.base_delay, attempt, fastrand::f64())
+                    });
+                    log::warn!("Cloudflare API request failed, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    attempt = attempt.saturating_add(1);
+                }
+                result => return result,
+            }
+        }
     }
 
     async fn handle_response<T: serde::de::DeserializeOwned>(
@@ -235,3 +531,355 @@ impl Client {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        const BASE: Duration = Duration::from_millis(500);
+
+        assert_eq!(backoff_delay(BASE, 0, 0.0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(BASE, 1, 0.0), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(BASE, 2, 0.0), Duration::from_millis(2000));
+        assert_eq!(backoff_delay(BASE, 2, 0.5), Duration::from_millis(2500));
+        assert!(backoff_delay(BASE, u32::MAX, 0.99) > Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(reqwest::header::RETRY_AFTER, "2".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+
+        headers.insert(reqwest::header::RETRY_AFTER, "86400".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(MAX_RETRY_AFTER));
+
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_requests() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        const DELAY: Duration = Duration::from_millis(200);
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/zones"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({
+                        "result": [],
+                        "result_info": null,
+                    }))
+                    .set_delay(DELAY),
+            )
+            .expect(6)
+            .mount(&server)
+            .await;
+
+        let client: Client = Client::new(Credentials::UserAuthToken {
+            token: "token".to_string(),
+        })
+        .unwrap()
+        .with_max_concurrent_requests(2)
+        .with_api_base(&format!("{}/", server.uri()));
+
+        let params: ListZonesParams = ListZonesParams::default();
+        let start = std::time::Instant::now();
+        let results = futures::future::join_all((0..6).map(|_| client.list_zones(&params))).await;
+        let elapsed: Duration = start.elapsed();
+
+        assert!(results.iter().all(Result::is_ok));
+        // 6 requests, at most 2 in flight
+        assert!(elapsed >= DELAY * 3, "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn shared_max_concurrent_requests() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        const DELAY: Duration = Duration::from_millis(200);
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/zones"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({
+                        "result": [],
+                        "result_info": null,
+                    }))
+                    .set_delay(DELAY),
+            )
+            .expect(6)
+            .mount(&server)
+            .await;
+
+        let requests: Arc<Semaphore> = Arc::new(Semaphore::new(2));
+        let clients: Vec<Client> = ["token1", "token2"]
+            .into_iter()
+            .map(|token| {
+                Client::new(Credentials::UserAuthToken {
+                    token: token.to_string(),
+                })
+                .unwrap()
+                .with_shared_requests(requests.clone())
+                .with_api_base(&format!("{}/", server.uri()))
+            })
+            .collect();
+
+        let params: ListZonesParams = ListZonesParams::default();
+        let start = std::time::Instant::now();
+        let results = futures::future::join_all(
+            clients
+                .iter()
+                .flat_map(|client| (0..3).map(|_| client.list_zones(&params))),
+        )
+        .await;
+        let elapsed: Duration = start.elapsed();
+
+        assert!(results.iter().all(Result::is_ok));
+        // 6 requests from 2 clients, at most 2 in flight
+        assert!(elapsed >= DELAY * 3, "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn proxy() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let proxy: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::header("host", "api.example.invalid"))
+            .and(matchers::path("/zones"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": [],
+                "result_info": null,
+            })))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let client: Client = Client::new(Credentials::UserAuthToken {
+            token: "token".to_string(),
+        })
+        .unwrap()
+        .with_proxy(&proxy.uri().parse().unwrap())
+        .unwrap()
+        .with_api_base("http://api.example.invalid/");
+
+        client
+            .list_zones(&ListZonesParams::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rate_limit_retry_after() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/zones"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "2"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/zones"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": [{"id": "023e105f4ecef8ad9ca31a8372d0c353"}],
+                "result_info": null,
+            })))
+            .mount(&server)
+            .await;
+
+        // base delay is far longer than Retry-After
+        let client: Client = Client::new(Credentials::UserAuthToken {
+            token: "token".to_string(),
+        })
+        .unwrap()
+        .with_retries(1, Duration::from_secs(60))
+        .with_api_base(&format!("{}/", server.uri()));
+
+        let start = std::time::Instant::now();
+        let response = client
+            .list_zones(&ListZonesParams::default())
+            .await
+            .unwrap();
+        let elapsed: Duration = start.elapsed();
+
+        assert_eq!(response.result[0].id, "023e105f4ecef8ad9ca31a8372d0c353");
+        assert!(elapsed >= Duration::from_secs(2), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(10), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn create_not_retried() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/zones/zone-id/dns_records"))
+            .respond_with(ResponseTemplate::new(502))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path("/zones/zone-id/dns_records/record-id"))
+            .respond_with(ResponseTemplate::new(502))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let client: Client = Client::new(Credentials::UserAuthToken {
+            token: "token".to_string(),
+        })
+        .unwrap()
+        .with_retries(2, Duration::ZERO)
+        .with_api_base(&format!("{}/", server.uri()));
+
+        let content = DnsContent::A {
+            content: Ipv4Addr::new(192, 0, 2, 1),
+        };
+
+        let result = client
+            .create_dns_record(
+                "zone-id",
+                &CreateDnsRecordParams {
+                    ttl: None,
+                    proxied: None,
+                    comment: None,
+                    name: "www.example.com",
+                    content: content.clone(),
+                },
+            )
+            .await;
+        assert!(matches!(result, Err(ApiError::Http(status, _)) if status == 502));
+
+        let result = client
+            .update_dns_record(
+                "zone-id",
+                "record-id",
+                &UpdateDnsRecordParams {
+                    ttl: None,
+                    proxied: None,
+                    comment: None,
+                    name: "www.example.com",
+                    content,
+                },
+            )
+            .await;
+        assert!(matches!(result, Err(ApiError::Http(status, _)) if status == 502));
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/zones"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"result": [], "result_info": null}))
+                    .set_delay(Duration::from_secs(10)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client: Client = Client::new(Credentials::UserAuthToken {
+            token: "token".to_string(),
+        })
+        .unwrap()
+        .with_timeout(Duration::from_millis(200))
+        .unwrap()
+        .with_retries(0, Duration::ZERO)
+        .with_api_base(&format!("{}/", server.uri()));
+
+        let start = std::time::Instant::now();
+        let result = client.list_zones(&ListZonesParams::default()).await;
+        let elapsed: Duration = start.elapsed();
+
+        assert!(
+            matches!(&result, Err(ApiError::Request(e)) if e.is_timeout()),
+            "{:?}",
+            result.err()
+        );
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[test]
+    fn batch_dns_records_params_serialize() {
+        let params = BatchDnsRecordsParams {
+            puts: vec![BatchPut {
+                id: "record-id",
+                params: UpdateDnsRecordParams {
+                    ttl: None,
+                    proxied: Some(true),
+                    comment: None,
+                    name: "www.example.com",
+                    content: DnsContent::AAAA {
+                        content: "2001:db8::1".parse().unwrap(),
+                    },
+                },
+            }],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&params).unwrap(),
+            serde_json::json!({
+                "puts": [
+                    {
+                        "id": "record-id",
+                        "proxied": true,
+                        "name": "www.example.com",
+                        "type": "AAAA",
+                        "content": "2001:db8::1",
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn create_dns_record_params_serialize() {
+        let params = CreateDnsRecordParams {
+            ttl: Some(300),
+            proxied: None,
+            comment: Some("managed by cfddns"),
+            name: "www.example.com",
+            content: DnsContent::A {
+                content: Ipv4Addr::new(192, 0, 2, 1),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&params).unwrap(),
+            serde_json::json!({
+                "ttl": 300,
+                "comment": "managed by cfddns",
+                "name": "www.example.com",
+                "type": "A",
+                "content": "192.0.2.1",
+            })
+        );
+    }
+}
@@ -1,49 +1,455 @@
-use crate::cloudflare::Client;
+use crate::{
+    cloudflare::{
+        Client, Credentials, DEFAULT_BASE_DELAY, DEFAULT_MAX_CONCURRENT_REQUESTS,
+        DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT, DnsContent,
+    },
+    ip::{
+        DEFAULT_HTTP_JSON_FIELD, DEFAULT_HTTP_TIMEOUT, DEFAULT_IP_DETECT_RETRY_DELAY,
+        DEFAULT_PREFIX_LEN, DEFAULT_USER_AGENT, DEFAULT_VERIFY_RESOLVER, http_client,
+        interface_mac, list_interfaces, mac_to_eui64, prefix_mask,
+    },
+    logger::{self, LogFormat},
+    notify::NotifyConfig,
+};
 use anyhow::Context as _;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     ffi::OsString,
-    fs::{File, OpenOptions},
-    io::{BufReader, BufWriter},
-    net::{Ipv4Addr, Ipv6Addr},
+    fs::{File, OpenOptions, TryLockError},
+    io::{BufReader, Read, Write as _},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::{Path, PathBuf},
     str::FromStr as _,
+    sync::Arc,
+    time::Duration,
 };
+use tokio::sync::Semaphore;
 
-#[derive(Deserialize)]
+/// How to choose between multiple configured address sources
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectMode {
+    /// Use the first configured source: interface, HTTP, DNS, STUN, then command
+    #[default]
+    Precedence,
+    /// Query every configured source concurrently, using the first success
+    Race,
+}
+
+/// Output format of `--print-ip`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrintIp {
+    /// One address per line, with the IPv6 prefix length
+    Lines,
+    /// A JSON object with `ipv4`, `ipv6_prefix`, and `ipv6_address`
+    Json,
+}
+
+/// DNS record type managed by cfddns
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RecordType {
+    A,
+    AAAA,
+    CNAME,
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct RecordConfig {
-    /// Record name
+    /// Record name, `@` for the zone apex
+    ///
+    /// Set from `names` when the zone is deserialized.
+    #[serde(skip)]
     pub name: String,
-    /// TTL
+    /// Names from the configuration file, a single name or a list
+    ///
+    /// Normalized with [`normalize_name`] to match names from the API.
+    /// [`ZoneConfig`] expands a list to one record per name, sharing the
+    /// rest of the configuration.
+    #[serde(rename = "name", deserialize_with = "deserialize_names")]
+    pub names: Vec<String>,
+    /// Record type to update
+    ///
+    /// Both A and AAAA records are updated if None, or a CNAME record if
+    /// `target` is set. If None and the name only has records of one family,
+    /// only that family is updated unless `create_missing` is set.
+    #[serde(rename = "type")]
+    pub record_type: Option<RecordType>,
+    /// TTL in seconds, 1 for automatic
+    ///
+    /// The string `"auto"` is accepted as 1.
+    #[serde(default, deserialize_with = "deserialize_ttl")]
     pub ttl: Option<u32>,
     /// Whether the record is proxied by Cloudflare
     pub proxied: Option<bool>,
+    /// Comment set on the record when it is created or updated
+    ///
+    /// Records are not updated for comment changes alone, and updating a
+    /// record without a comment removes any existing comment.
+    pub comment: Option<String>,
     /// Static suffix for AAAA record updates
     ///
     /// AAAA records are not updated if None.
+    /// Also accepted as `eui64`.
+    #[serde(default, alias = "eui64", deserialize_with = "deserialize_suffix")]
     pub suffix: Option<Ipv6Addr>,
+    /// Prefix length for this record, overriding the global `prefix_len`
+    ///
+    /// The detected prefix is masked to this length before adding `suffix`,
+    /// so the suffix can set a subnet ID within a shorter delegated prefix.
+    pub prefix_len: Option<u8>,
+    /// Interface to derive the EUI-64 suffix from
+    ///
+    /// Sets `suffix` from the MAC address of this interface.
+    pub eui64_from_interface: Option<String>,
+    /// Update the AAAA record with the full address of `aaaa_interface`
+    ///
+    /// Without `aaaa_interface` the address from `aaaa_http` is used unmasked.
+    /// Only used if `suffix` is None.
+    pub full_ipv6: Option<bool>,
+    /// Create the record if it does not exist
+    ///
+    /// Defaults to false, missing records are an error.
+    pub create_missing: Option<bool>,
+    /// CNAME target
+    ///
+    /// The record is managed as a CNAME to this target instead of A and AAAA
+    /// records if set.
+    pub target: Option<String>,
 }
 
-#[derive(Deserialize)]
+impl RecordConfig {
+    /// Returns true if records of `record_type` are updated for this record
+    pub fn updates(&self, record_type: RecordType) -> bool {
+        match self.record_type {
+            Some(configured) => configured == record_type,
+            None if self.target.is_some() => record_type == RecordType::CNAME,
+            None => record_type != RecordType::CNAME,
+        }
+    }
+
+    /// A record content for an IPv4
+    ///
+    /// Returns None if this record does not update A records.
+    pub fn a_content(&self, ipv4: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        ipv4.filter(|_| self.updates(RecordType::A))
+    }
+
+    /// AAAA record content for an IPv6 prefix or full IPv6 address
+    ///
+    /// Returns None if this record does not update AAAA records, has no suffix
+    /// and does not use the full address, or if the required IPv6 is None.
+    pub fn aaaa_content(
+        &self,
+        prefix: Option<Ipv6Addr>,
+        address: Option<Ipv6Addr>,
+    ) -> Option<Ipv6Addr> {
+        if !self.updates(RecordType::AAAA) {
+            return None;
+        }
+
+        match self.suffix {
+            Some(suffix) => prefix.map(|prefix| match self.prefix_len {
+                Some(prefix_len) => (prefix & prefix_mask(prefix_len)) | suffix,
+                None => prefix | suffix,
+            }),
+            None if self.full_ipv6.unwrap_or(false) => address,
+            None => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct ZoneConfig {
+    /// Zone name for this domain
+    ///
+    /// Used to look up the zone identifier if `id` is None.
+    pub name: Option<String>,
     /// Zone identifier for this domain
-    pub name: String,
+    ///
+    /// Skips the zone lookup if set.
+    pub id: Option<String>,
+    /// TTL for records in this zone that do not set a TTL
+    #[serde(default, deserialize_with = "deserialize_ttl")]
+    pub default_ttl: Option<u32>,
+    /// Proxied setting for records in this zone that do not set proxied
+    pub default_proxied: Option<bool>,
+    /// Suffix for AAAA records in this zone without a suffix
+    ///
+    /// Not used for records with `eui64_from_interface`, `full_ipv6`, or a
+    /// `target`. Also accepted as `default_eui64`.
+    #[serde(
+        default,
+        alias = "default_eui64",
+        deserialize_with = "deserialize_suffix"
+    )]
+    pub default_suffix: Option<Ipv6Addr>,
+    /// Append the zone name to record names outside of the zone
+    ///
+    /// Allows short names such as `www`, with `@` or an empty name for the
+    /// zone apex.
+    pub fqdn_from_zone: Option<bool>,
+    /// Delete records previously managed by cfddns that are no longer configured
+    pub prune: Option<bool>,
+    /// Environment variable with the API token for this zone
+    ///
+    /// Zones without a token use the global credentials.
+    pub token_env: Option<String>,
     /// Records for this zone
+    #[serde(deserialize_with = "deserialize_records")]
     pub records: Vec<RecordConfig>,
 }
 
+impl ZoneConfig {
+    /// Effective TTL of a record, falling back to the zone default
+    pub fn ttl(&self, record: &RecordConfig) -> Option<u32> {
+        record.ttl.or(self.default_ttl)
+    }
+
+    /// Effective proxied setting of a record, falling back to the zone default
+    pub fn proxied(&self, record: &RecordConfig) -> Option<bool> {
+        record.proxied.or(self.default_proxied)
+    }
+
+    /// Zone name for logging, falling back to the zone identifier
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.id.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// TTL that Cloudflare treats as automatic
+pub const AUTO_TTL: u32 = 1;
+
+/// Returns true if Cloudflare accepts `ttl`
+fn valid_ttl(ttl: u32) -> bool {
+    ttl == AUTO_TTL || (60..=86400).contains(&ttl)
+}
+
+fn deserialize_ttl<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Ttl {
+        Seconds(u32),
+        Named(String),
+    }
+
+    match Option::<Ttl>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Ttl::Seconds(seconds)) => Ok(Some(seconds)),
+        Some(Ttl::Named(name)) if name == "auto" => Ok(Some(AUTO_TTL)),
+        Some(Ttl::Named(name)) => Err(serde::de::Error::custom(format!(
+            "invalid TTL '{name}', expected seconds or \"auto\""
+        ))),
+    }
+}
+
+/// Canonical form of a record name, as returned by Cloudflare
+///
+/// DNS names are case insensitive, names are lowercased and a trailing dot is
+/// removed.
+pub fn normalize_name(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_lowercase()
+}
+
+/// Fully qualified `name` within `zone`, `@` or an empty name is the apex
+fn fqdn(name: &str, zone: &str) -> String {
+    if name.is_empty() || name == "@" {
+        zone.to_string()
+    } else if name == zone || name.ends_with(&format!(".{zone}")) {
+        name.to_string()
+    } else {
+        format!("{name}.{zone}")
+    }
+}
+
+fn deserialize_names<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let names: Vec<String> = OneOrMany::deserialize(deserializer)?.into();
+    if names.is_empty() {
+        return Err(serde::de::Error::custom(
+            "record name list must not be empty",
+        ));
+    }
+    Ok(names.iter().map(|name| normalize_name(name)).collect())
+}
+
+/// Records with one entry per name of each configured record
+fn deserialize_records<'de, D>(deserializer: D) -> Result<Vec<RecordConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let records: Vec<RecordConfig> = Vec::deserialize(deserializer)?;
+    Ok(records
+        .into_iter()
+        .flat_map(|record| {
+            record
+                .names
+                .clone()
+                .into_iter()
+                .map(move |name| RecordConfig {
+                    name,
+                    ..record.clone()
+                })
+        })
+        .collect())
+}
+
+/// Deserialize an IPv6 suffix, also accepting a 64-bit interface identifier
+/// such as `"1234:5678:9abc:def0"` or `"0x123456789abcdef0"`
+fn deserialize_suffix<'de, D>(deserializer: D) -> Result<Option<Ipv6Addr>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(suffix) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    match suffix.parse::<Ipv6Addr>() {
+        Ok(suffix) => Ok(Some(suffix)),
+        Err(_) => parse_interface_id(&suffix)
+            .map(|id| Some(Ipv6Addr::from(u128::from(id))))
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "invalid suffix '{suffix}', expected an IPv6 address \
+                    or a 64-bit hex interface identifier"
+                ))
+            }),
+    }
+}
+
+/// Parse a 64-bit interface identifier of four colon separated hex groups,
+/// or hex digits prefixed with `0x`
+fn parse_interface_id(id: &str) -> Option<u64> {
+    let is_hex = |digits: &str| -> bool {
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit())
+    };
+
+    if let Some(digits) = id.strip_prefix("0x") {
+        return is_hex(digits)
+            .then(|| u64::from_str_radix(digits, 16).ok())
+            .flatten();
+    }
+
+    let groups: Vec<u16> = id
+        .split(':')
+        .map(|group| {
+            is_hex(group)
+                .then(|| u16::from_str_radix(group, 16).ok())
+                .flatten()
+        })
+        .collect::<Option<_>>()?;
+
+    (groups.len() == 4).then(|| {
+        groups
+            .iter()
+            .fold(0, |id, group| (id << 16) | u64::from(*group))
+    })
+}
+
+/// Deserialize a list of subnets, accepting plain addresses as single hosts
+fn deserialize_networks<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Network {
+        Net(IpNet),
+        Addr(IpAddr),
+    }
+
+    let networks: Option<Vec<Network>> = Option::deserialize(deserializer)?;
+    Ok(networks
+        .unwrap_or_default()
+        .into_iter()
+        .map(|network| match network {
+            Network::Net(net) => net,
+            Network::Addr(addr) => IpNet::from(addr),
+        })
+        .collect())
+}
+
+/// A single value or a list of values
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Vec<T> {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 struct ConfigFile {
-    a_interface: Option<String>,
-    a_http: Option<url::Url>,
+    a_interface: Option<OneOrMany<String>>,
+    a_http: Option<OneOrMany<url::Url>>,
+    a_http_json_field: Option<String>,
+    a_dns: Option<IpAddr>,
+    a_stun: Option<String>,
+    a_command: Option<Vec<String>>,
     aaaa_interface: Option<String>,
-    aaaa_http: Option<url::Url>,
+    aaaa_http: Option<OneOrMany<url::Url>>,
+    aaaa_http_json_field: Option<String>,
+    aaaa_command: Option<Vec<String>>,
+    prefix_len: Option<u8>,
+    ipv6_prefix_significant_len: Option<u8>,
+    prefer_stable_ipv6: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    exclude_addresses: Vec<IpNet>,
+    #[serde(default)]
+    detect_mode: DetectMode,
+    ip_detect_retries: Option<u32>,
+    ip_detect_retry_delay_secs: Option<u64>,
+    fail_on_cgnat: Option<bool>,
+    require_both: Option<bool>,
+    track_records: Option<bool>,
+    quiet_unchanged: Option<bool>,
+    verify_via_dns: Option<bool>,
+    verify_resolver: Option<SocketAddr>,
     zones: Vec<ZoneConfig>,
-    history_path: PathBuf,
+    history_path: Option<PathBuf>,
+    report_path: Option<PathBuf>,
+    metrics_path: Option<PathBuf>,
+    lock_path: Option<PathBuf>,
+    token_path: Option<PathBuf>,
+    credential_name: Option<String>,
     log_level: String,
+    #[serde(default)]
+    log_format: LogFormat,
+    interval_secs: Option<u64>,
+    interval_jitter_secs: Option<u64>,
+    max_retries: Option<u32>,
+    base_delay_ms: Option<u64>,
+    http_timeout_secs: Option<u64>,
+    cloudflare_timeout_secs: Option<u64>,
+    http_source_interface: Option<String>,
+    proxy: Option<url::Url>,
+    cloudflare_base_url: Option<url::Url>,
+    user_agent: Option<String>,
+    max_concurrent_requests: Option<usize>,
+    notify_webhook: Option<url::Url>,
+    notify: Option<NotifyConfig>,
+    healthcheck_url: Option<url::Url>,
 }
 
 #[derive(Deserialize, Serialize, Default, PartialEq, Eq, Debug)]
@@ -51,184 +457,2316 @@ struct ConfigFile {
 pub struct History {
     pub ipv4: Option<Ipv4Addr>,
     pub ipv6_prefix: Option<Ipv6Addr>,
+    #[serde(default)]
+    pub ipv6_address: Option<Ipv6Addr>,
+    /// Zone identifiers by zone name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub zone_ids: HashMap<String, String>,
+    /// Record identifiers by zone identifier
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub record_ids: HashMap<String, RecordIds>,
+    /// Content last pushed by record name, only kept with `track_records`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub record_content: HashMap<String, Vec<DnsContent>>,
+    /// Unix time in seconds of the last run that changed records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<u64>,
+}
+
+/// Record identifiers by record name
+#[derive(Deserialize, Serialize, Default, Clone, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RecordIds {
+    #[serde(default, deserialize_with = "deserialize_ids")]
+    pub a: HashMap<String, Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_ids")]
+    pub aaaa: HashMap<String, Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_ids")]
+    pub cname: HashMap<String, Vec<String>>,
+}
+
+/// Deserialize record identifiers, accepting the single identifier per name
+/// written by earlier versions
+fn deserialize_ids<'de, D>(deserializer: D) -> Result<HashMap<String, Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let ids: HashMap<String, OneOrMany<String>> = HashMap::deserialize(deserializer)?;
+    Ok(ids
+        .into_iter()
+        .map(|(name, ids)| (name, Vec::from(ids)))
+        .collect())
 }
 
 pub struct Config {
-    pub a_interface: Option<String>,
-    pub a_http: Option<url::Url>,
+    /// Network interfaces to obtain the IPv4 from, tried in order
+    pub a_interface: Vec<String>,
+    /// URLs that return the public IPv4, tried in order
+    pub a_http: Vec<url::Url>,
+    /// JSON field containing the IPv4 for JSON `a_http` responses
+    pub a_http_json_field: String,
+    /// OpenDNS resolver to query for the public IPv4
+    pub a_dns: Option<IpAddr>,
+    /// STUN server to query for the public IPv4
+    pub a_stun: Option<String>,
+    /// Command that prints the public IPv4
+    pub a_command: Option<Vec<String>>,
     pub aaaa_interface: Option<String>,
-    pub aaaa_http: Option<url::Url>,
+    /// URLs that return the public IPv6, tried in order
+    pub aaaa_http: Vec<url::Url>,
+    /// JSON field containing the IPv6 for JSON `aaaa_http` responses
+    pub aaaa_http_json_field: String,
+    /// Command that prints the public IPv6
+    pub aaaa_command: Option<Vec<String>>,
+    /// Length of the IPv6 prefix to update AAAA records with
+    pub prefix_len: u8,
+    /// Leading bits of the IPv6 prefix that must differ to update records
+    ///
+    /// None compares the whole prefix.
+    pub ipv6_prefix_significant_len: Option<u8>,
+    /// Prefer stable IPv6 addresses over temporary addresses on `aaaa_interface`
+    pub prefer_stable_ipv6: bool,
+    /// Addresses and subnets to ignore on `a_interface` and `aaaa_interface`
+    pub exclude_addresses: Vec<IpNet>,
+    /// How to choose between multiple configured address sources
+    pub detect_mode: DetectMode,
+    /// Number of times to retry detecting the addresses after a failure
+    pub ip_detect_retries: u32,
+    /// Delay between address detection attempts
+    pub ip_detect_retry_delay: Duration,
+    /// Fail instead of warning when the detected IPv4 is in the CGNAT range
+    pub fail_on_cgnat: bool,
+    /// Fail when either address family fails detection
+    ///
+    /// By default a failure in one family is logged and the other family is
+    /// still updated.
+    pub require_both: bool,
+    /// Compare live records to the detected addresses on every run
+    ///
+    /// By default records are only updated when the detected addresses change.
+    pub track_records: bool,
+    /// Log unchanged addresses at debug instead of info level
+    pub quiet_unchanged: bool,
+    /// Skip updating records that already resolve to the detected addresses
+    ///
+    /// Avoids writes when history is lost but DNS is already correct.
+    /// Proxied and CNAME records never match, so they are always updated.
+    pub verify_via_dns: bool,
+    /// DNS server queried for `verify_via_dns`
+    ///
+    /// A recursive resolver may answer from its cache for up to the record
+    /// TTL, so a record changed outside of cfddns can appear correct until
+    /// the cached answer expires.
+    pub verify_resolver: SocketAddr,
     pub zones: Vec<ZoneConfig>,
     pub history: History,
-    pub history_path: PathBuf,
-    pub cloudflare_client: Client,
+    /// File to retain history in between runs
+    ///
+    /// History is only kept in memory if None, such as on a read-only
+    /// filesystem. The records are then compared to the detected addresses on
+    /// every start.
+    pub history_path: Option<PathBuf>,
+    /// File to write a JSON report of each run to
+    pub report_path: Option<PathBuf>,
+    /// File to write Prometheus metrics to after each run
+    pub metrics_path: Option<PathBuf>,
+    /// File containing the Cloudflare API token
+    ///
+    /// The `CLOUDFLARE_TOKEN` environment variable is used if None.
+    pub token_path: Option<PathBuf>,
+    /// Name of the systemd credential containing the Cloudflare API token
+    ///
+    /// Used if `token_path` is None and the credential exists.
+    pub credential_name: Option<String>,
+    /// Lock held while this process runs, released on drop
+    lock: Option<File>,
+    /// HTTP client for IP detection and notifications
+    pub http_client: reqwest::Client,
+    /// Proxy for HTTP and Cloudflare API requests
+    ///
+    /// The `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY` environment variables
+    /// are used if None.
+    pub proxy: Option<url::Url>,
+    /// Cloudflare API base URL, such as a mock server for testing
+    ///
+    /// The production API is used if None.
+    pub cloudflare_base_url: Option<url::Url>,
+    /// Timeout for each Cloudflare API request
+    pub cloudflare_timeout: Duration,
+    /// URL to POST public IP changes to
+    pub notify_webhook: Option<url::Url>,
+    /// Slack or Discord webhook to send public IP changes to
+    pub notify: Option<NotifyConfig>,
+    /// URL to GET after each run, with `/fail` appended on failure
+    pub healthcheck_url: Option<url::Url>,
+    /// Maximum number of retries for transient Cloudflare API errors
+    pub max_retries: u32,
+    /// Base delay for exponential backoff between Cloudflare API retries
+    pub base_delay: Duration,
+    /// Maximum number of Cloudflare API requests in flight, across all zones
+    pub max_concurrent_requests: usize,
+    /// Run continuously, updating at this interval
+    ///
+    /// Run once and exit if None.
+    pub interval_secs: Option<u64>,
+    /// Maximum random offset added to or subtracted from each interval
+    ///
+    /// Less than `interval_secs`, so the interval is never zero.
+    pub interval_jitter_secs: u64,
+    /// Log changes without applying them or saving history
+    pub dry_run: bool,
+    /// Update records even if the addresses match history
+    pub force: bool,
+    /// Print the detected addresses and exit without updating records
+    pub print_ip: Option<PrintIp>,
+    /// Query each address source and exit without updating records
+    pub validate_ip_sources: bool,
 }
 
 impl Config {
+    /// Returns true if any record uses the full IPv6 address
+    pub fn full_ipv6_records(&self) -> bool {
+        self.zones
+            .iter()
+            .flat_map(|zone| &zone.records)
+            .any(|record| record.suffix.is_none() && record.full_ipv6.unwrap_or(false))
+    }
+
+    /// Warnings for proxied records with a TTL, which Cloudflare ignores
+    pub fn ignored_ttl_warnings(&self) -> Vec<String> {
+        self.zones
+            .iter()
+            .flat_map(|zone| zone.records.iter().map(move |record| (zone, record)))
+            .filter(|(zone, record)| zone.proxied(record) == Some(true))
+            .filter_map(|(_, record)| {
+                let ttl: u32 = record.ttl.filter(|ttl| *ttl != AUTO_TTL)?;
+                Some(format!(
+                    "Record '{}' is proxied, Cloudflare ignores its TTL of {ttl}",
+                    record.name
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns true if an IPv4 source is configured
+    pub fn ipv4_enabled(&self) -> bool {
+        !self.a_interface.is_empty()
+            || !self.a_http.is_empty()
+            || self.a_dns.is_some()
+            || self.a_stun.is_some()
+            || self.a_command.is_some()
+    }
+
+    /// Returns true if an IPv6 source is configured
+    pub fn ipv6_enabled(&self) -> bool {
+        self.aaaa_interface.is_some() || !self.aaaa_http.is_empty() || self.aaaa_command.is_some()
+    }
+
+    /// Delay before the next update, None if running once
+    ///
+    /// The interval is offset by up to `interval_jitter_secs` in either
+    /// direction to avoid many instances polling in lockstep.
+    pub fn next_interval(&self) -> Option<Duration> {
+        let interval_secs: u64 = self.interval_secs?;
+        let jitter: u64 = self.interval_jitter_secs;
+        let offset: u64 = fastrand::u64(0..=jitter.saturating_mul(2));
+        Some(Duration::from_secs(
+            interval_secs.saturating_add(offset).saturating_sub(jitter),
+        ))
+    }
+
+    /// Create a Cloudflare API client
+    ///
+    /// The API token is read from the first available source:
+    ///
+    /// 1. `token_path`
+    /// 2. The systemd credential `credential_name`
+    /// 3. The `CLOUDFLARE_TOKEN` environment variable
+    pub fn cloudflare_client(&self) -> anyhow::Result<Client> {
+        let credential_path: Option<PathBuf> = self.credential_name.as_deref().and_then(|name| {
+            systemd_credential_path(std::env::var_os(CREDENTIALS_DIRECTORY_ENV_VAR), name)
+        });
+        let token_path: Option<&Path> = self.token_path.as_deref().or(credential_path.as_deref());
+
+        let credentials: Credentials = select_credentials(
+            read_token(token_path),
+            std::env::var(CLOUDFLARE_EMAIL_ENV_VAR).ok(),
+            std::env::var(CLOUDFLARE_API_KEY_ENV_VAR).ok(),
+        )?;
+
+        self.client_with(credentials)
+    }
+
+    /// Create Cloudflare API clients for every zone
+    ///
+    /// Zones with `token_env` share a client per environment variable. The
+    /// global client from [`Config::cloudflare_client`] is only created if a
+    /// zone has no `token_env`. All clients share `max_concurrent_requests`.
+    pub fn cloudflare_clients(&self) -> anyhow::Result<Clients> {
+        let requests: Arc<Semaphore> = Arc::new(Semaphore::new(self.max_concurrent_requests));
+
+        let default: Option<Client> = if self.zones.iter().any(|zone| zone.token_env.is_none()) {
+            Some(
+                self.cloudflare_client()?
+                    .with_shared_requests(requests.clone()),
+            )
+        } else {
+            None
+        };
+
+        let mut zones: HashMap<String, Client> = HashMap::new();
+        for name in self.zones.iter().filter_map(|zone| zone.token_env.as_ref()) {
+            if zones.contains_key(name) {
+                continue;
+            }
+            let token: String = std::env::var(name)
+                .with_context(|| format!("Failed to read API token from '{name}'"))?;
+            let client: Client = self
+                .client_with(Credentials::UserAuthToken { token })?
+                .with_shared_requests(requests.clone());
+            zones.insert(name.clone(), client);
+        }
+
+        Ok(Clients { default, zones })
+    }
+
+    fn client_with(&self, credentials: Credentials) -> anyhow::Result<Client> {
+        let mut client: Client =
+            Client::new(credentials).context("Failed to create Cloudflare API client")?;
+        if let Some(proxy) = &self.proxy {
+            client = client
+                .with_proxy(proxy)
+                .context("Failed to configure the Cloudflare API client proxy")?;
+        }
+        if let Some(base_url) = &self.cloudflare_base_url {
+            client = client.with_api_base(&api_base(base_url));
+        }
+
+        Ok(client
+            .with_timeout(self.cloudflare_timeout)
+            .context("Failed to configure the Cloudflare API client timeout")?
+            .with_retries(self.max_retries, self.base_delay)
+            .with_max_concurrent_requests(self.max_concurrent_requests))
+    }
+
     pub fn from_args_os() -> anyhow::Result<Config> {
-        let config_file_path: OsString = match std::env::args_os().nth(1) {
-            Some(x) => x,
-            None => {
-                eprintln!(
-                    "usage: {} [config-file.json]",
-                    std::env::args_os()
-                        .next()
-                        .unwrap_or_else(|| OsString::from("???"))
-                        .to_string_lossy()
-                );
-                std::process::exit(1);
+        let mut dry_run: bool = false;
+        let mut check: bool = false;
+        let mut force: bool = false;
+        let mut once: bool = false;
+        let mut print_ip: Option<PrintIp> = None;
+        let mut validate_ip_sources: bool = false;
+        let mut config_file_path: Option<OsString> = None;
+
+        for arg in std::env::args_os().skip(1) {
+            if arg == "--dry-run" {
+                dry_run = true;
+            } else if arg == "--check" {
+                check = true;
+            } else if arg == "--force" {
+                force = true;
+            } else if arg == "--once" {
+                once = true;
+            } else if arg == "--print-ip" {
+                print_ip = Some(PrintIp::Lines);
+            } else if arg == "--print-ip=json" {
+                print_ip = Some(PrintIp::Json);
+            } else if arg == "--validate-ip-sources" {
+                validate_ip_sources = true;
+            } else if arg == "--list-interfaces" {
+                print!("{}", list_interfaces()?);
+                std::process::exit(0);
+            } else if arg == "--version" || arg == "-V" {
+                println!("{}", version());
+                std::process::exit(0);
+            } else if config_file_path.is_none() {
+                config_file_path = Some(arg);
+            } else {
+                usage();
             }
+        }
+
+        // the environment variable is used when passing arguments is awkward,
+        // such as in some container runtimes
+        let Some(config_file_path) =
+            config_file_path.or_else(|| std::env::var_os(CFDDNS_CONFIG_ENV_VAR))
+        else {
+            usage();
         };
 
-        let file: File = File::open(&config_file_path).with_context(|| {
-            format!(
-                "Failed to open config file at {}",
-                config_file_path.to_string_lossy()
-            )
-        })?;
-        let reader: BufReader<File> = BufReader::new(file);
-        let config: ConfigFile =
-            serde_json::from_reader(reader).context("Failed to deserialize config file")?;
+        // "-" reads the configuration from stdin, such as from a secret templating tool
+        let config: ConfigFile = if config_file_path == "-" {
+            deserialize_config(std::io::stdin().lock())
+        } else {
+            let file: File = File::open(&config_file_path).with_context(|| {
+                format!(
+                    "Failed to open config file at {}",
+                    config_file_path.to_string_lossy()
+                )
+            })?;
+            deserialize_config(BufReader::new(file))
+        }
+        .context("Failed to deserialize config file")?;
+
+        // fail before detection rather than when history is first written
+        if let Some(history_path) = &config.history_path {
+            validate_history_path(history_path)?;
+        }
+
+        let log_level: String = config.log_level;
+        let log_format: LogFormat = config.log_format;
+        let lock_path: Option<PathBuf> = config.lock_path;
+
+        let mut config: Config = ConfigBuilder {
+            a_interface: config.a_interface.map(Vec::from).unwrap_or_default(),
+            a_http: config.a_http.map(Vec::from).unwrap_or_default(),
+            a_http_json_field: config.a_http_json_field,
+            a_dns: config.a_dns,
+            a_stun: config.a_stun,
+            a_command: config.a_command,
+            aaaa_interface: config.aaaa_interface,
+            aaaa_http: config.aaaa_http.map(Vec::from).unwrap_or_default(),
+            aaaa_http_json_field: config.aaaa_http_json_field,
+            aaaa_command: config.aaaa_command,
+            prefix_len: config.prefix_len,
+            ipv6_prefix_significant_len: config.ipv6_prefix_significant_len,
+            prefer_stable_ipv6: config.prefer_stable_ipv6.unwrap_or(false),
+            exclude_addresses: config.exclude_addresses,
+            detect_mode: config.detect_mode,
+            ip_detect_retries: config.ip_detect_retries,
+            ip_detect_retry_delay: config.ip_detect_retry_delay_secs.map(Duration::from_secs),
+            fail_on_cgnat: config.fail_on_cgnat.unwrap_or(false),
+            require_both: config.require_both.unwrap_or(false),
+            track_records: config.track_records.unwrap_or(false),
+            quiet_unchanged: config.quiet_unchanged.unwrap_or(false),
+            verify_via_dns: config.verify_via_dns.unwrap_or(false),
+            verify_resolver: config.verify_resolver,
+            zones: config.zones,
+            history: History::default(),
+            history_path: config.history_path,
+            report_path: config.report_path,
+            metrics_path: config.metrics_path,
+            token_path: config.token_path,
+            credential_name: config.credential_name,
+            max_retries: config.max_retries,
+            base_delay: config.base_delay_ms.map(Duration::from_millis),
+            http_timeout: config.http_timeout_secs.map(Duration::from_secs),
+            cloudflare_timeout: config.cloudflare_timeout_secs.map(Duration::from_secs),
+            http_source_interface: config.http_source_interface,
+            proxy: config.proxy,
+            cloudflare_base_url: config.cloudflare_base_url,
+            user_agent: config.user_agent,
+            max_concurrent_requests: config.max_concurrent_requests,
+            notify_webhook: config.notify_webhook,
+            notify: config.notify,
+            healthcheck_url: config.healthcheck_url,
+            // --once runs a single update regardless of the configured interval
+            interval_secs: config.interval_secs.filter(|_| !once),
+            interval_jitter_secs: config.interval_jitter_secs,
+            dry_run,
+            force,
+            print_ip,
+            validate_ip_sources,
+        }
+        .build()?;
 
         let level: log::LevelFilter =
-            log::LevelFilter::from_str(&config.log_level).with_context(|| {
+            log::LevelFilter::from_str(&log_level).with_context(|| {
                 format!(
                     "Invalid log_level in configuration file {}",
                     config_file_path.to_string_lossy()
                 )
             })?;
 
+        // RUST_LOG overrides the configuration file for ad-hoc debugging
+        let mut invalid_rust_log: Option<anyhow::Error> = None;
+        let level: log::LevelFilter = match std::env::var(RUST_LOG_ENV_VAR) {
+            Ok(rust_log) => match rust_log_level(&rust_log) {
+                Ok(rust_log_level) => rust_log_level.unwrap_or(level),
+                Err(e) => {
+                    invalid_rust_log = Some(e);
+                    level
+                }
+            },
+            Err(_) => level,
+        };
+
+        if check {
+            if let Some(zone) = config.zones.iter().find(|zone| zone.records.is_empty()) {
+                anyhow::bail!("Zone '{}' has no records", zone.display_name());
+            }
+            // the logger is not installed for a check
+            for warning in config.ignored_ttl_warnings() {
+                eprintln!("warning: {warning}");
+            }
+            println!("config OK");
+            std::process::exit(0);
+        }
+
         if level != log::LevelFilter::Off {
-            systemd_journal_logger::JournalLog::new()
-                .context("Failed to create logger")?
-                .install()
-                .context("Failed to install logger")?;
+            logger::install(log_format)?;
             log::set_max_level(level);
         }
 
-        const CLOUDFLARE_TOKEN_ENV_VAR: &str = "CLOUDFLARE_TOKEN";
+        if let Some(e) = invalid_rust_log {
+            log::warn!(
+                "Ignoring environment variable '{RUST_LOG_ENV_VAR}', \
+                using log_level from the configuration file: {e}"
+            );
+        }
 
-        let cloudflare_token: String =
-            std::env::var(CLOUDFLARE_TOKEN_ENV_VAR).with_context(|| {
-                format!(
-                    "Failed to read cloudflare API token from environment variable '{CLOUDFLARE_TOKEN_ENV_VAR}'"
-                )
-            })?;
+        for warning in config.ignored_ttl_warnings() {
+            log::warn!("{warning}");
+        }
 
-        let cloudflare_client: Client =
-            Client::new(cloudflare_token).context("Failed to create Cloudflare API client")?;
+        // probing addresses must not contend for the lock or touch history
+        if config.print_ip.is_some() || config.validate_ip_sources {
+            return Ok(config);
+        }
 
-        let history: History = restore_history(&config.history_path)?;
+        config.lock = match &lock_path {
+            Some(lock_path) => match acquire_lock(lock_path)? {
+                Some(lock) => Some(lock),
+                None => {
+                    log::info!(
+                        "Lock file '{}' is held by another process, exiting",
+                        lock_path.to_string_lossy()
+                    );
+                    std::process::exit(0);
+                }
+            },
+            None => None,
+        };
 
-        Ok(Config {
-            a_interface: config.a_interface,
-            a_http: config.a_http,
-            aaaa_interface: config.aaaa_interface,
-            aaaa_http: config.aaaa_http,
-            zones: config.zones,
-            history,
-            history_path: config.history_path,
-            cloudflare_client,
-        })
+        if let Some(history_path) = &config.history_path {
+            config.history = restore_history(history_path, config.dry_run)?;
+        }
+
+        Ok(config)
     }
 }
 
-fn restore_history(path: &Path) -> anyhow::Result<History> {
-    match File::open(path) {
-        Ok(file) => {
-            let reader: BufReader<File> = BufReader::new(file);
-
-            let history: History =
-                serde_json::from_reader(reader).context("Failed to deserialize history file")?;
+/// Expand `${NAME}` placeholders with the value of environment variables
+///
+/// `lookup` returns the value of a variable, or None if it is unset. Values
+/// are escaped for use inside JSON strings. `$${` is a literal `${`.
+fn substitute_env(text: &[u8], lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(text.len());
+    let mut rest: &[u8] = text;
 
-            Ok(history)
+    while let Some(start) = rest.windows(2).position(|window| window == b"${") {
+        if let Some(escape) = start.checked_sub(1)
+            && rest[escape] == b'$'
+        {
+            out.extend_from_slice(&rest[..escape]);
+            out.extend_from_slice(b"${");
+            rest = &rest[start.saturating_add(2)..];
+            continue;
         }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            log::warn!(
-                "History file does not exist at '{}' creating new history file",
-                path.to_string_lossy()
-            );
 
-            save_history(path, History::default()).with_context(|| {
-                format!(
-                    "Failed to create initial history file at '{}'",
-                    path.to_string_lossy()
-                )
-            })?;
+        out.extend_from_slice(&rest[..start]);
+        rest = &rest[start.saturating_add(2)..];
 
-            Ok(History::default())
-        }
-        Err(e) => Err(e).with_context(|| {
-            format!(
-                "Failed to open history file at '{}'",
-                path.to_string_lossy()
-            )
-        }),
+        let end: usize = rest
+            .iter()
+            .position(|&byte| byte == b'}')
+            .context("Unterminated '${' in config file")?;
+        let name: &str = std::str::from_utf8(&rest[..end])
+            .context("Invalid environment variable name in config file")?;
+        let value: String = lookup(name).with_context(|| {
+            format!("Environment variable '{name}' referenced in config file is not set")
+        })?;
+
+        // the value is placed inside a JSON string
+        let quoted: String = serde_json::to_string(&value)?;
+        let escaped: &str = quoted
+            .strip_prefix('"')
+            .and_then(|quoted| quoted.strip_suffix('"'))
+            .unwrap_or(&quoted);
+        out.extend_from_slice(escaped.as_bytes());
+        rest = &rest[end.saturating_add(1)..];
     }
+    out.extend_from_slice(rest);
+
+    Ok(out)
 }
 
-pub fn save_history(path: &Path, history: History) -> anyhow::Result<()> {
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(path)
-        .with_context(|| {
-            format!(
-                "Failed to open history file at '{}' for writing",
-                path.to_string_lossy()
-            )
-        })?;
-    let writer = BufWriter::new(file);
+/// Deserialize a configuration file
+///
+/// `${NAME}` placeholders are expanded from the environment before parsing.
+/// Errors name the JSON path of the invalid value, such as
+/// `zones[2].records[0].ttl`.
+fn deserialize_config(mut reader: impl Read) -> anyhow::Result<ConfigFile> {
+    let mut text: Vec<u8> = Vec::new();
+    reader
+        .read_to_end(&mut text)
+        .context("Failed to read config file")?;
+    let text: Vec<u8> = substitute_env(&text, |name| std::env::var(name).ok())?;
 
-    serde_json::to_writer(writer, &history).context("Failed to write history to file")
+    let mut deserializer = serde_json::Deserializer::from_slice(&text);
+    let config: ConfigFile = serde_path_to_error::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(config)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Builder for [`Config`] without a configuration file
+///
+/// Options default to the same values as the configuration file.
+#[derive(Default)]
+pub struct ConfigBuilder {
+    a_interface: Vec<String>,
+    a_http: Vec<url::Url>,
+    a_http_json_field: Option<String>,
+    a_dns: Option<IpAddr>,
+    a_stun: Option<String>,
+    a_command: Option<Vec<String>>,
+    aaaa_interface: Option<String>,
+    aaaa_http: Vec<url::Url>,
+    aaaa_http_json_field: Option<String>,
+    aaaa_command: Option<Vec<String>>,
+    prefix_len: Option<u8>,
+    ipv6_prefix_significant_len: Option<u8>,
+    prefer_stable_ipv6: bool,
+    exclude_addresses: Vec<IpNet>,
+    detect_mode: DetectMode,
+    ip_detect_retries: Option<u32>,
+    ip_detect_retry_delay: Option<Duration>,
+    fail_on_cgnat: bool,
+    require_both: bool,
+    track_records: bool,
+    quiet_unchanged: bool,
+    verify_via_dns: bool,
+    verify_resolver: Option<SocketAddr>,
+    zones: Vec<ZoneConfig>,
+    history: History,
+    history_path: Option<PathBuf>,
+    report_path: Option<PathBuf>,
+    metrics_path: Option<PathBuf>,
+    token_path: Option<PathBuf>,
+    credential_name: Option<String>,
+    max_retries: Option<u32>,
+    base_delay: Option<Duration>,
+    http_timeout: Option<Duration>,
+    cloudflare_timeout: Option<Duration>,
+    http_source_interface: Option<String>,
+    proxy: Option<url::Url>,
+    cloudflare_base_url: Option<url::Url>,
+    user_agent: Option<String>,
+    max_concurrent_requests: Option<usize>,
+    notify_webhook: Option<url::Url>,
+    notify: Option<NotifyConfig>,
+    healthcheck_url: Option<url::Url>,
+    interval_secs: Option<u64>,
+    interval_jitter_secs: Option<u64>,
+    dry_run: bool,
+    force: bool,
+    print_ip: Option<PrintIp>,
+    validate_ip_sources: bool,
+}
 
-    use tempfile::TempDir;
+impl ConfigBuilder {
+    pub fn new() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
 
-    #[test]
-    fn restore_history_file_creation() {
-        let hist_dir: TempDir = TempDir::new().unwrap();
-        let mut hist_file_path = hist_dir.keep();
-        hist_file_path.push("history.json");
+    /// Add a network interface to obtain the IPv4 from, interfaces are tried
+    /// in order
+    pub fn a_interface(mut self, iface: impl Into<String>) -> ConfigBuilder {
+        self.a_interface.push(iface.into());
+        self
+    }
 
-        assert!(!hist_file_path.exists());
+    /// Add a URL that returns the public IPv4, URLs are tried in order
+    pub fn a_http(mut self, url: url::Url) -> ConfigBuilder {
+        self.a_http.push(url);
+        self
+    }
 
-        assert_eq!(
-            restore_history(&hist_file_path).unwrap(),
-            History::default()
-        );
+    pub fn a_http_json_field(mut self, field: impl Into<String>) -> ConfigBuilder {
+        self.a_http_json_field = Some(field.into());
+        self
+    }
 
-        assert!(hist_file_path.exists());
+    pub fn a_dns(mut self, resolver: IpAddr) -> ConfigBuilder {
+        self.a_dns = Some(resolver);
+        self
     }
 
-    #[test]
-    fn save_restore_history() {
-        let hist_dir: TempDir = TempDir::new().unwrap();
-        let mut hist_file_path = hist_dir.keep();
-        hist_file_path.push("history.json");
+    pub fn a_stun(mut self, server: impl Into<String>) -> ConfigBuilder {
+        self.a_stun = Some(server.into());
+        self
+    }
 
-        // file creation
-        save_history(&hist_file_path, History::default()).unwrap();
+    pub fn a_command(mut self, command: Vec<String>) -> ConfigBuilder {
+        self.a_command = Some(command);
+        self
+    }
 
-        // restore history
-        let restored = restore_history(&hist_file_path).unwrap();
-        assert_eq!(restored, History::default());
+    pub fn aaaa_interface(mut self, iface: impl Into<String>) -> ConfigBuilder {
+        self.aaaa_interface = Some(iface.into());
+        self
+    }
 
-        const HISTORY_UNSPECIFIED_ADDR: History = History {
-            ipv4: Some(Ipv4Addr::UNSPECIFIED),
-            ipv6_prefix: Some(Ipv6Addr::UNSPECIFIED),
-        };
+    /// Add a URL that returns the public IPv6, URLs are tried in order
+    pub fn aaaa_http(mut self, url: url::Url) -> ConfigBuilder {
+        self.aaaa_http.push(url);
+        self
+    }
 
-        // file overwrite
-        save_history(&hist_file_path, HISTORY_UNSPECIFIED_ADDR).unwrap();
+    pub fn aaaa_http_json_field(mut self, field: impl Into<String>) -> ConfigBuilder {
+        self.aaaa_http_json_field = Some(field.into());
+        self
+    }
 
-        // restore overwritten history
-        let restored = restore_history(&hist_file_path).unwrap();
-        assert_eq!(restored, HISTORY_UNSPECIFIED_ADDR);
+    pub fn aaaa_command(mut self, command: Vec<String>) -> ConfigBuilder {
+        self.aaaa_command = Some(command);
+        self
+    }
+
+    pub fn prefix_len(mut self, prefix_len: u8) -> ConfigBuilder {
+        self.prefix_len = Some(prefix_len);
+        self
+    }
+
+    /// Leading bits of the IPv6 prefix that must differ to update records
+    pub fn ipv6_prefix_significant_len(mut self, significant_len: u8) -> ConfigBuilder {
+        self.ipv6_prefix_significant_len = Some(significant_len);
+        self
+    }
+
+    pub fn prefer_stable_ipv6(mut self, prefer_stable_ipv6: bool) -> ConfigBuilder {
+        self.prefer_stable_ipv6 = prefer_stable_ipv6;
+        self
+    }
+
+    /// Ignore addresses within `network` on `a_interface` and `aaaa_interface`
+    pub fn exclude_address(mut self, network: IpNet) -> ConfigBuilder {
+        self.exclude_addresses.push(network);
+        self
+    }
+
+    pub fn detect_mode(mut self, mode: DetectMode) -> ConfigBuilder {
+        self.detect_mode = mode;
+        self
+    }
+
+    /// Number of times to retry detecting the addresses after a failure
+    pub fn ip_detect_retries(mut self, retries: u32) -> ConfigBuilder {
+        self.ip_detect_retries = Some(retries);
+        self
+    }
+
+    /// Delay between address detection attempts
+    pub fn ip_detect_retry_delay(mut self, delay: Duration) -> ConfigBuilder {
+        self.ip_detect_retry_delay = Some(delay);
+        self
+    }
+
+    pub fn fail_on_cgnat(mut self, fail_on_cgnat: bool) -> ConfigBuilder {
+        self.fail_on_cgnat = fail_on_cgnat;
+        self
+    }
+
+    pub fn require_both(mut self, require_both: bool) -> ConfigBuilder {
+        self.require_both = require_both;
+        self
+    }
+
+    pub fn track_records(mut self, track_records: bool) -> ConfigBuilder {
+        self.track_records = track_records;
+        self
+    }
+
+    pub fn quiet_unchanged(mut self, quiet_unchanged: bool) -> ConfigBuilder {
+        self.quiet_unchanged = quiet_unchanged;
+        self
+    }
+
+    pub fn verify_via_dns(mut self, verify_via_dns: bool) -> ConfigBuilder {
+        self.verify_via_dns = verify_via_dns;
+        self
+    }
+
+    /// DNS server queried for `verify_via_dns`
+    pub fn verify_resolver(mut self, resolver: SocketAddr) -> ConfigBuilder {
+        self.verify_resolver = Some(resolver);
+        self
+    }
+
+    pub fn zones(mut self, zones: Vec<ZoneConfig>) -> ConfigBuilder {
+        self.zones = zones;
+        self
+    }
+
+    /// Initial history, empty by default
+    pub fn history(mut self, history: History) -> ConfigBuilder {
+        self.history = history;
+        self
+    }
+
+    pub fn history_path(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.history_path = Some(path.into());
+        self
+    }
+
+    pub fn report_path(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.report_path = Some(path.into());
+        self
+    }
+
+    pub fn metrics_path(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.metrics_path = Some(path.into());
+        self
+    }
+
+    pub fn token_path(mut self, path: impl Into<PathBuf>) -> ConfigBuilder {
+        self.token_path = Some(path.into());
+        self
+    }
+
+    pub fn credential_name(mut self, name: impl Into<String>) -> ConfigBuilder {
+        self.credential_name = Some(name.into());
+        self
+    }
+
+    pub fn retries(mut self, max_retries: u32, base_delay: Duration) -> ConfigBuilder {
+        self.max_retries = Some(max_retries);
+        self.base_delay = Some(base_delay);
+        self
+    }
+
+    /// Timeout for HTTP IP detection requests
+    pub fn http_timeout(mut self, timeout: Duration) -> ConfigBuilder {
+        self.http_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for each Cloudflare API request
+    pub fn cloudflare_timeout(mut self, timeout: Duration) -> ConfigBuilder {
+        self.cloudflare_timeout = Some(timeout);
+        self
+    }
+
+    /// Network interface to send HTTP IP detection requests from
+    pub fn http_source_interface(mut self, interface: impl Into<String>) -> ConfigBuilder {
+        self.http_source_interface = Some(interface.into());
+        self
+    }
+
+    /// Maximum number of Cloudflare API requests in flight
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> ConfigBuilder {
+        self.max_concurrent_requests = Some(max_concurrent_requests);
+        self
+    }
+
+    /// Proxy for HTTP and Cloudflare API requests
+    pub fn proxy(mut self, proxy: url::Url) -> ConfigBuilder {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn cloudflare_base_url(mut self, base_url: url::Url) -> ConfigBuilder {
+        self.cloudflare_base_url = Some(base_url);
+        self
+    }
+
+    /// User-Agent for HTTP IP detection and notification requests
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> ConfigBuilder {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// URL to POST public IP changes to
+    pub fn notify_webhook(mut self, url: url::Url) -> ConfigBuilder {
+        self.notify_webhook = Some(url);
+        self
+    }
+
+    /// Slack or Discord webhook to send public IP changes to
+    pub fn notify(mut self, notify: NotifyConfig) -> ConfigBuilder {
+        self.notify = Some(notify);
+        self
+    }
+
+    /// URL to GET after each run, with `/fail` appended on failure
+    pub fn healthcheck_url(mut self, url: url::Url) -> ConfigBuilder {
+        self.healthcheck_url = Some(url);
+        self
+    }
+
+    pub fn interval_secs(mut self, interval_secs: u64) -> ConfigBuilder {
+        self.interval_secs = Some(interval_secs);
+        self
+    }
+
+    pub fn interval_jitter_secs(mut self, interval_jitter_secs: u64) -> ConfigBuilder {
+        self.interval_jitter_secs = Some(interval_jitter_secs);
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> ConfigBuilder {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Update records even if the addresses match history
+    pub fn force(mut self, force: bool) -> ConfigBuilder {
+        self.force = force;
+        self
+    }
+
+    /// Print the detected addresses and exit without updating records
+    pub fn print_ip(mut self, print_ip: Option<PrintIp>) -> ConfigBuilder {
+        self.print_ip = print_ip;
+        self
+    }
+
+    /// Query each address source and exit without updating records
+    pub fn validate_ip_sources(mut self, validate_ip_sources: bool) -> ConfigBuilder {
+        self.validate_ip_sources = validate_ip_sources;
+        self
+    }
+
+    /// Validate the options and create the [`Config`]
+    ///
+    /// Missing zones or IP sources are warnings, not errors.
+    pub fn build(mut self) -> anyhow::Result<Config> {
+        if self
+            .zones
+            .iter()
+            .any(|zone| zone.name.is_none() && zone.id.is_none())
+        {
+            anyhow::bail!("Each zone requires a name or an id");
+        }
+
+        for zone in &self.zones {
+            if let Some(ttl) = zone.default_ttl.filter(|ttl| !valid_ttl(*ttl)) {
+                anyhow::bail!(
+                    "Zone '{}' has invalid default_ttl {ttl}, expected 1 (auto) or 60 to 86400",
+                    zone.display_name()
+                );
+            }
+        }
+
+        // `@` is the zone apex, as in zone files
+        for zone in &mut self.zones {
+            let fqdn_from_zone: bool = zone.fqdn_from_zone.unwrap_or(false);
+            let display_name: String = zone.display_name().to_string();
+            let zone_name: Option<String> = zone.name.as_deref().map(normalize_name);
+
+            if fqdn_from_zone && zone_name.is_none() {
+                anyhow::bail!("Zone '{display_name}' sets fqdn_from_zone without a name");
+            }
+
+            for record in &mut zone.records {
+                if !fqdn_from_zone && record.name != "@" {
+                    continue;
+                }
+
+                let Some(zone_name) = &zone_name else {
+                    anyhow::bail!("Record '@' in zone '{display_name}' requires the zone name");
+                };
+                record.name = fqdn(&record.name, zone_name);
+            }
+        }
+
+        for zone in &self.zones {
+            if let Some(name) = &zone.token_env
+                && std::env::var_os(name).is_none()
+            {
+                anyhow::bail!(
+                    "Zone '{}' has token_env '{name}' which is not set",
+                    zone.display_name()
+                );
+            }
+        }
+
+        for record in self.zones.iter_mut().flat_map(|zone| &mut zone.records) {
+            if let Some(ttl) = record.ttl.filter(|ttl| !valid_ttl(*ttl)) {
+                anyhow::bail!(
+                    "Record '{}' has invalid TTL {ttl}, expected 1 (auto) or 60 to 86400",
+                    record.name
+                );
+            }
+
+            if record.target.is_some()
+                && (record.suffix.is_some()
+                    || record.eui64_from_interface.is_some()
+                    || record.full_ipv6.unwrap_or(false))
+            {
+                anyhow::bail!(
+                    "Record '{}' sets both target and an IPv6 address",
+                    record.name
+                );
+            }
+
+            match (record.record_type, &record.target) {
+                (Some(RecordType::CNAME), None) => {
+                    anyhow::bail!("Record '{}' has type CNAME without a target", record.name)
+                }
+                (Some(record_type @ (RecordType::A | RecordType::AAAA)), Some(_)) => {
+                    anyhow::bail!(
+                        "Record '{}' has type {record_type:?} but sets a CNAME target",
+                        record.name
+                    )
+                }
+                _ => (),
+            }
+
+            if let Some(iface) = &record.eui64_from_interface {
+                if record.suffix.is_some() {
+                    anyhow::bail!(
+                        "Record '{}' sets both suffix and eui64_from_interface",
+                        record.name
+                    );
+                }
+
+                let mac: [u8; 6] = interface_mac(iface).with_context(|| {
+                    format!(
+                        "Failed to derive EUI-64 suffix for record '{}'",
+                        record.name
+                    )
+                })?;
+                record.suffix = Some(Ipv6Addr::from(u128::from(mac_to_eui64(mac))));
+            }
+        }
+
+        for zone in &mut self.zones {
+            let Some(default_suffix) = zone.default_suffix else {
+                continue;
+            };
+            for record in &mut zone.records {
+                if record.suffix.is_none()
+                    && record.target.is_none()
+                    && !record.full_ipv6.unwrap_or(false)
+                {
+                    record.suffix = Some(default_suffix);
+                }
+            }
+        }
+
+        let prefix_len: u8 = self.prefix_len.unwrap_or(DEFAULT_PREFIX_LEN);
+        if !(1..=128).contains(&prefix_len) {
+            anyhow::bail!("prefix_len must be between 1 and 128, got {prefix_len}");
+        }
+
+        if let Some(significant_len) = self.ipv6_prefix_significant_len
+            && !(1..=128).contains(&significant_len)
+        {
+            anyhow::bail!(
+                "ipv6_prefix_significant_len must be between 1 and 128, got {significant_len}"
+            );
+        }
+
+        for record in self.zones.iter().flat_map(|zone| &zone.records) {
+            let prefix_len: u8 = record.prefix_len.unwrap_or(prefix_len);
+            if !(1..=128).contains(&prefix_len) {
+                anyhow::bail!(
+                    "Record '{}' prefix_len must be between 1 and 128, got {prefix_len}",
+                    record.name
+                );
+            }
+
+            if let Some(suffix) = record.suffix
+                && !(suffix & prefix_mask(prefix_len)).is_unspecified()
+            {
+                anyhow::bail!(
+                    "Record '{}' has suffix {suffix} with bits set inside the /{prefix_len} prefix",
+                    record.name
+                );
+            }
+        }
+
+        let max_concurrent_requests: usize = self
+            .max_concurrent_requests
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS);
+        if max_concurrent_requests == 0 {
+            anyhow::bail!("max_concurrent_requests must be at least 1");
+        }
+
+        if self.interval_secs == Some(0) {
+            anyhow::bail!("interval_secs must be at least 1");
+        }
+
+        if let (Some(interval_secs), Some(jitter_secs)) =
+            (self.interval_secs, self.interval_jitter_secs)
+            && jitter_secs >= interval_secs
+        {
+            anyhow::bail!(
+                "interval_jitter_secs must be less than interval_secs, \
+                got {jitter_secs} and {interval_secs}"
+            );
+        }
+
+        let http_client: reqwest::Client = http_client(
+            self.http_timeout.unwrap_or(DEFAULT_HTTP_TIMEOUT),
+            self.http_source_interface.as_deref(),
+            self.proxy.as_ref(),
+            self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT),
+        )
+        .context("Failed to create HTTP client")?;
+
+        let config: Config = Config {
+            a_interface: self.a_interface,
+            a_http: self.a_http,
+            a_http_json_field: self
+                .a_http_json_field
+                .unwrap_or_else(|| DEFAULT_HTTP_JSON_FIELD.to_string()),
+            a_dns: self.a_dns,
+            a_stun: self.a_stun,
+            a_command: self.a_command,
+            aaaa_interface: self.aaaa_interface,
+            aaaa_http: self.aaaa_http,
+            aaaa_http_json_field: self
+                .aaaa_http_json_field
+                .unwrap_or_else(|| DEFAULT_HTTP_JSON_FIELD.to_string()),
+            aaaa_command: self.aaaa_command,
+            prefix_len,
+            ipv6_prefix_significant_len: self.ipv6_prefix_significant_len,
+            prefer_stable_ipv6: self.prefer_stable_ipv6,
+            exclude_addresses: self.exclude_addresses,
+            detect_mode: self.detect_mode,
+            ip_detect_retries: self.ip_detect_retries.unwrap_or(0),
+            ip_detect_retry_delay: self
+                .ip_detect_retry_delay
+                .unwrap_or(DEFAULT_IP_DETECT_RETRY_DELAY),
+            fail_on_cgnat: self.fail_on_cgnat,
+            require_both: self.require_both,
+            track_records: self.track_records,
+            quiet_unchanged: self.quiet_unchanged,
+            verify_via_dns: self.verify_via_dns,
+            verify_resolver: self.verify_resolver.unwrap_or(DEFAULT_VERIFY_RESOLVER),
+            zones: self.zones,
+            history: self.history,
+            history_path: self.history_path,
+            report_path: self.report_path,
+            metrics_path: self.metrics_path,
+            token_path: self.token_path,
+            credential_name: self.credential_name,
+            lock: None,
+            http_client,
+            proxy: self.proxy,
+            cloudflare_base_url: self.cloudflare_base_url,
+            cloudflare_timeout: self.cloudflare_timeout.unwrap_or(DEFAULT_TIMEOUT),
+            notify_webhook: self.notify_webhook,
+            notify: self.notify,
+            healthcheck_url: self.healthcheck_url,
+            max_retries: self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            base_delay: self.base_delay.unwrap_or(DEFAULT_BASE_DELAY),
+            max_concurrent_requests,
+            interval_secs: self.interval_secs,
+            interval_jitter_secs: self.interval_jitter_secs.unwrap_or(0),
+            dry_run: self.dry_run,
+            force: self.force,
+            print_ip: self.print_ip,
+            validate_ip_sources: self.validate_ip_sources,
+        };
+
+        if config.zones.is_empty() {
+            log::warn!("No zones specified in configuration");
+        }
+
+        if !config.ipv4_enabled() && !config.ipv6_enabled() {
+            log::warn!("Both IPv4 and IPv6 disabled in configuration");
+        }
+
+        Ok(config)
+    }
+}
+
+const CFDDNS_CONFIG_ENV_VAR: &str = "CFDDNS_CONFIG";
+const CLOUDFLARE_TOKEN_ENV_VAR: &str = "CLOUDFLARE_TOKEN";
+const CLOUDFLARE_EMAIL_ENV_VAR: &str = "CLOUDFLARE_EMAIL";
+const CLOUDFLARE_API_KEY_ENV_VAR: &str = "CLOUDFLARE_API_KEY";
+const CREDENTIALS_DIRECTORY_ENV_VAR: &str = "CREDENTIALS_DIRECTORY";
+const RUST_LOG_ENV_VAR: &str = "RUST_LOG";
+
+/// Log level for this crate from a `RUST_LOG` value
+///
+/// Accepts a bare level, or env_logger directives such as
+/// `info,reqwest=warn`. A directive for this crate takes precedence over the
+/// default level. Returns None if no directive applies to this crate.
+fn rust_log_level(rust_log: &str) -> anyhow::Result<Option<log::LevelFilter>> {
+    const CRATE_NAME: &str = env!("CARGO_CRATE_NAME");
+
+    // env_logger accepts a regex filter after a slash
+    let directives: &str = rust_log.split_once('/').map_or(rust_log, |(d, _)| d);
+
+    let mut default: Option<log::LevelFilter> = None;
+    let mut crate_level: Option<log::LevelFilter> = None;
+    for directive in directives
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+    {
+        let (target, level): (Option<&str>, Option<log::LevelFilter>) =
+            match directive.split_once('=') {
+                Some((target, level)) => (
+                    Some(target.trim()),
+                    Some(
+                        log::LevelFilter::from_str(level.trim())
+                            .with_context(|| format!("Invalid log level in '{directive}'"))?,
+                    ),
+                ),
+                None => match log::LevelFilter::from_str(directive) {
+                    Ok(level) => (None, Some(level)),
+                    // a target without a level enables every level
+                    Err(_) => (Some(directive), None),
+                },
+            };
+
+        match target {
+            None => default = level,
+            Some(target) if target.split("::").next() == Some(CRATE_NAME) => {
+                crate_level = Some(level.unwrap_or(log::LevelFilter::Trace));
+            }
+            Some(_) => (),
+        }
+    }
+
+    Ok(crate_level.or(default))
+}
+
+/// Path of an existing systemd credential
+///
+/// Returns None if the credentials directory is unset or the credential does
+/// not exist.
+fn systemd_credential_path(credentials_directory: Option<OsString>, name: &str) -> Option<PathBuf> {
+    let path: PathBuf = PathBuf::from(credentials_directory?).join(name);
+    path.exists().then_some(path)
+}
+
+/// Read the Cloudflare API token from a file, or the environment if None
+fn read_token(token_path: Option<&Path>) -> anyhow::Result<String> {
+    match token_path {
+        Some(path) => {
+            let token: String = std::fs::read_to_string(path).with_context(|| {
+                format!(
+                    "Failed to read cloudflare API token from '{}'",
+                    path.to_string_lossy()
+                )
+            })?;
+            let token: &str = token.trim();
+            if token.is_empty() {
+                anyhow::bail!(
+                    "Cloudflare API token file '{}' is empty",
+                    path.to_string_lossy()
+                );
+            }
+            Ok(token.to_string())
+        }
+        None => std::env::var(CLOUDFLARE_TOKEN_ENV_VAR).with_context(|| {
+            format!(
+                "Failed to read cloudflare API token from environment variable '{CLOUDFLARE_TOKEN_ENV_VAR}'"
+            )
+        }),
+    }
+}
+
+/// API base of a Cloudflare base URL, which must end with a slash
+fn api_base(base_url: &url::Url) -> String {
+    let mut api_base: String = base_url.to_string();
+    if !api_base.ends_with('/') {
+        api_base.push('/');
+    }
+    api_base
+}
+
+/// Cloudflare API clients, created by [`Config::cloudflare_clients`]
+pub struct Clients {
+    default: Option<Client>,
+    /// Clients for zones with `token_env`, by environment variable
+    zones: HashMap<String, Client>,
+}
+
+impl Clients {
+    /// Client for a zone, using the zone's token if it has one
+    pub fn zone(&self, zone: &ZoneConfig) -> anyhow::Result<&Client> {
+        match &zone.token_env {
+            Some(name) => self.zones.get(name),
+            None => self.default.as_ref(),
+        }
+        .with_context(|| format!("No API client for zone '{}'", zone.display_name()))
+    }
+}
+
+impl From<Client> for Clients {
+    fn from(client: Client) -> Clients {
+        Clients {
+            default: Some(client),
+            zones: HashMap::new(),
+        }
+    }
+}
+
+/// Select Cloudflare credentials
+///
+/// The global API key is used when both email and key are set, otherwise the
+/// API token is used.
+fn select_credentials(
+    token: anyhow::Result<String>,
+    email: Option<String>,
+    key: Option<String>,
+) -> anyhow::Result<Credentials> {
+    match (email, key) {
+        (Some(email), Some(key)) => {
+            if token.is_ok() {
+                anyhow::bail!(
+                    "Both an API token and {CLOUDFLARE_EMAIL_ENV_VAR}/{CLOUDFLARE_API_KEY_ENV_VAR} are set, only one set of credentials may be used"
+                );
+            }
+            Ok(Credentials::UserAuthKey { email, key })
+        }
+        (None, None) => Ok(Credentials::UserAuthToken { token: token? }),
+        _ => anyhow::bail!(
+            "{CLOUDFLARE_EMAIL_ENV_VAR} and {CLOUDFLARE_API_KEY_ENV_VAR} must be set together"
+        ),
+    }
+}
+
+/// Version string, including the git revision if known at build time
+fn version() -> String {
+    match option_env!("CFDDNS_GIT_REV").filter(|rev| !rev.is_empty()) {
+        Some(rev) => format!("cfddns {} ({rev})", env!("CARGO_PKG_VERSION")),
+        None => format!("cfddns {}", env!("CARGO_PKG_VERSION")),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: {} [--version] [--list-interfaces] [--dry-run] [--check] [--force] [--once] [--print-ip[=json]] [--validate-ip-sources] [config-file.json]",
+        std::env::args_os()
+            .next()
+            .unwrap_or_else(|| OsString::from("???"))
+            .to_string_lossy()
+    );
+    std::process::exit(2);
+}
+
+/// Acquire an exclusive lock on a file
+///
+/// Returns None if the lock is held by another process.
+fn acquire_lock(path: &Path) -> anyhow::Result<Option<File>> {
+    let file: File = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open lock file at '{}'", path.to_string_lossy()))?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("Failed to lock file at '{}'", path.to_string_lossy()))
+        }
+    }
+}
+
+/// Check that the history file can be created at `path`
+///
+/// Relative paths must have an existing parent directory.
+fn validate_history_path(path: &Path) -> anyhow::Result<()> {
+    if path.as_os_str().is_empty() {
+        anyhow::bail!("history_path must not be empty");
+    }
+
+    if path.is_relative()
+        && let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        && !parent.is_dir()
+    {
+        anyhow::bail!(
+            "history_path '{}' is relative and its parent directory does not exist",
+            path.to_string_lossy()
+        );
+    }
+
+    Ok(())
+}
+
+/// Restore history from `path`, creating the file if it does not exist
+///
+/// A corrupt history file is moved aside. With `dry_run` the file is never
+/// created or moved.
+fn restore_history(path: &Path, dry_run: bool) -> anyhow::Result<History> {
+    match File::open(path) {
+        Ok(file) => {
+            let reader: BufReader<File> = BufReader::new(file);
+
+            match serde_json::from_reader(reader) {
+                Ok(history) => Ok(history),
+                Err(e) if dry_run => {
+                    log::warn!(
+                        "Failed to deserialize history file at '{}': {e}",
+                        path.to_string_lossy()
+                    );
+                    Ok(History::default())
+                }
+                Err(e) => {
+                    let backup_path: PathBuf = path.with_added_extension("bak");
+                    log::warn!(
+                        "Failed to deserialize history file at '{}', moving it to '{}': {e}",
+                        path.to_string_lossy(),
+                        backup_path.to_string_lossy()
+                    );
+
+                    std::fs::rename(path, &backup_path).with_context(|| {
+                        format!(
+                            "Failed to back up corrupt history file to '{}'",
+                            backup_path.to_string_lossy()
+                        )
+                    })?;
+
+                    Ok(History::default())
+                }
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && dry_run => {
+            log::info!(
+                "History file does not exist at '{}'",
+                path.to_string_lossy()
+            );
+            Ok(History::default())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::warn!(
+                "History file does not exist at '{}' creating new history file",
+                path.to_string_lossy()
+            );
+
+            save_history(path, &History::default()).with_context(|| {
+                format!(
+                    "Failed to create initial history file at '{}'",
+                    path.to_string_lossy()
+                )
+            })?;
+
+            Ok(History::default())
+        }
+        Err(e) => Err(e).with_context(|| {
+            format!(
+                "Failed to open history file at '{}'",
+                path.to_string_lossy()
+            )
+        }),
+    }
+}
+
+/// Save history to a file
+pub fn save_history(path: &Path, history: &History) -> anyhow::Result<()> {
+    let json: Vec<u8> = serde_json::to_vec(history).context("Failed to serialize history")?;
+    write_atomic(path, &json).context("Failed to write history to file")
+}
+
+/// Write `contents` to `path` atomically
+///
+/// The contents are written and synced to a temporary file next to `path`
+/// which is then renamed into place, so an interrupted write never leaves a
+/// partial file for readers to observe.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let tmp_path: PathBuf = path.with_added_extension("tmp");
+
+    let mut file: File = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&tmp_path)
+        .with_context(|| {
+            format!(
+                "Failed to open '{}' for writing",
+                tmp_path.to_string_lossy()
+            )
+        })?;
+
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write '{}'", tmp_path.to_string_lossy()))?;
+
+    std::fs::rename(&tmp_path, path).with_context(|| {
+        format!(
+            "Failed to rename '{}' to '{}'",
+            tmp_path.to_string_lossy(),
+            path.to_string_lossy()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn record_aaaa_content() {
+        let record: RecordConfig = serde_json::from_str(
+            r#"{
+                "name": "www.example.com",
+                "suffix": "::aaaa:bbbb:cccc:dddd"
+            }"#,
+        )
+        .unwrap();
+
+        let prefix: Ipv6Addr = "2001:db8:1:2::".parse().unwrap();
+        assert_eq!(
+            record.aaaa_content(Some(prefix), None),
+            Some("2001:db8:1:2:aaaa:bbbb:cccc:dddd".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn record_aaaa_content_full_ipv6() {
+        let record: RecordConfig =
+            serde_json::from_str(r#"{"name": "www.example.com", "full_ipv6": true}"#).unwrap();
+
+        let prefix: Ipv6Addr = "2001:db8:1:2::".parse().unwrap();
+        let address: Ipv6Addr = "2001:db8:1:2::1234".parse().unwrap();
+        assert_eq!(
+            record.aaaa_content(Some(prefix), Some(address)),
+            Some(address)
+        );
+        assert_eq!(record.aaaa_content(Some(prefix), None), None);
+    }
+
+    #[test]
+    fn record_aaaa_content_no_suffix() {
+        let record: RecordConfig = serde_json::from_str(r#"{"name": "www.example.com"}"#).unwrap();
+
+        assert_eq!(
+            record.aaaa_content(Some(Ipv6Addr::UNSPECIFIED), Some(Ipv6Addr::LOCALHOST)),
+            None
+        );
+    }
+
+    #[test]
+    fn record_type_explicit() {
+        let ipv4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+        let prefix: Ipv6Addr = "2001:db8::".parse().unwrap();
+        let expected_aaaa: Ipv6Addr = "2001:db8::1".parse().unwrap();
+
+        let record = |record_type: &str| -> RecordConfig {
+            serde_json::from_str(&format!(
+                r#"{{"name": "www.example.com", "type": {record_type}, "suffix": "::1"}}"#
+            ))
+            .unwrap()
+        };
+
+        let both: RecordConfig = record("null");
+        assert_eq!(both.a_content(Some(ipv4)), Some(ipv4));
+        assert_eq!(both.aaaa_content(Some(prefix), None), Some(expected_aaaa));
+        assert!(!both.updates(RecordType::CNAME));
+
+        let a: RecordConfig = record(r#""A""#);
+        assert_eq!(a.a_content(Some(ipv4)), Some(ipv4));
+        assert_eq!(a.aaaa_content(Some(prefix), None), None);
+        assert!(!a.updates(RecordType::CNAME));
+
+        let aaaa: RecordConfig = record(r#""AAAA""#);
+        assert_eq!(aaaa.a_content(Some(ipv4)), None);
+        assert_eq!(aaaa.aaaa_content(Some(prefix), None), Some(expected_aaaa));
+        assert!(!aaaa.updates(RecordType::CNAME));
+
+        let cname: RecordConfig = serde_json::from_str(
+            r#"{"name": "www.example.com", "type": "CNAME", "target": "example.net"}"#,
+        )
+        .unwrap();
+        assert_eq!(cname.a_content(Some(ipv4)), None);
+        assert_eq!(cname.aaaa_content(Some(prefix), None), None);
+        assert!(cname.updates(RecordType::CNAME));
+
+        assert!(serde_json::from_str::<RecordConfig>(r#"{"name": "x", "type": "MX"}"#).is_err());
+    }
+
+    #[test]
+    fn interval_jitter() {
+        let config: Config = ConfigBuilder::new().build().unwrap();
+        assert_eq!(config.next_interval(), None);
+
+        let config: Config = ConfigBuilder::new().interval_secs(300).build().unwrap();
+        assert_eq!(config.next_interval(), Some(Duration::from_secs(300)));
+
+        let config: Config = ConfigBuilder::new()
+            .interval_secs(300)
+            .interval_jitter_secs(30)
+            .build()
+            .unwrap();
+        for _ in 0..100 {
+            let interval: Duration = config.next_interval().unwrap();
+            assert!(
+                (Duration::from_secs(270)..=Duration::from_secs(330)).contains(&interval),
+                "{interval:?}"
+            );
+        }
+
+        assert!(
+            ConfigBuilder::new()
+                .interval_secs(10)
+                .interval_jitter_secs(10)
+                .build()
+                .is_err()
+        );
+
+        let config: Config = ConfigBuilder::new()
+            .interval_secs(10)
+            .interval_jitter_secs(9)
+            .build()
+            .unwrap();
+        for _ in 0..100 {
+            let interval: Duration = config.next_interval().unwrap();
+            assert!(interval > Duration::ZERO, "{interval:?}");
+            assert!(interval <= Duration::from_secs(19), "{interval:?}");
+        }
+    }
+
+    #[test]
+    fn ttl_validation() {
+        let zones = |ttl: &str| -> Vec<ZoneConfig> {
+            serde_json::from_str(&format!(
+                r#"[{{"name": "example.com", "records": [{{"name": "www.example.com", "ttl": {ttl}}}]}}]"#
+            ))
+            .unwrap()
+        };
+
+        for ttl in ["null", "1", "60", "300", "86400", r#""auto""#] {
+            assert!(
+                ConfigBuilder::new().zones(zones(ttl)).build().is_ok(),
+                "TTL {ttl} should be accepted"
+            );
+        }
+
+        for ttl in ["0", "2", "59", "86401"] {
+            assert!(
+                ConfigBuilder::new().zones(zones(ttl)).build().is_err(),
+                "TTL {ttl} should be rejected"
+            );
+        }
+
+        let record: RecordConfig =
+            serde_json::from_str(r#"{"name": "www.example.com", "ttl": "auto"}"#).unwrap();
+        assert_eq!(record.ttl, Some(AUTO_TTL));
+
+        assert!(
+            serde_json::from_str::<RecordConfig>(r#"{"name": "www.example.com", "ttl": "fast"}"#)
+                .is_err()
+        );
+
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"name": "example.com", "default_ttl": 30, "records": [{"name": "www.example.com"}]}]"#,
+        )
+        .unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+    }
+
+    #[test]
+    fn exclude_addresses_networks() {
+        #[derive(Deserialize)]
+        struct Exclude {
+            #[serde(default, deserialize_with = "deserialize_networks")]
+            exclude_addresses: Vec<IpNet>,
+        }
+
+        let exclude: Exclude = serde_json::from_str(
+            r#"{"exclude_addresses": ["10.8.0.0/16", "192.0.2.1", "2001:db8::/32"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            exclude.exclude_addresses,
+            [
+                "10.8.0.0/16".parse::<IpNet>().unwrap(),
+                "192.0.2.1/32".parse::<IpNet>().unwrap(),
+                "2001:db8::/32".parse::<IpNet>().unwrap(),
+            ]
+        );
+
+        let exclude: Exclude = serde_json::from_str("{}").unwrap();
+        assert!(exclude.exclude_addresses.is_empty());
+    }
+
+    #[test]
+    fn http_urls_one_or_many() {
+        let one: OneOrMany<url::Url> =
+            serde_json::from_str(r#""https://ipv4.icanhazip.com""#).unwrap();
+        assert_eq!(
+            Vec::from(one),
+            ["https://ipv4.icanhazip.com".parse::<url::Url>().unwrap()]
+        );
+
+        let many: OneOrMany<url::Url> =
+            serde_json::from_str(r#"["https://ipv4.icanhazip.com", "https://api.ipify.org"]"#)
+                .unwrap();
+        assert_eq!(
+            Vec::from(many),
+            [
+                "https://ipv4.icanhazip.com".parse::<url::Url>().unwrap(),
+                "https://api.ipify.org".parse::<url::Url>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn builder_defaults() {
+        let config: Config = ConfigBuilder::new()
+            .a_interface("eth0")
+            .history_path("/tmp/history.json")
+            .build()
+            .unwrap();
+
+        assert_eq!(config.a_interface, ["eth0"]);
+        assert_eq!(config.a_http_json_field, DEFAULT_HTTP_JSON_FIELD);
+        assert_eq!(config.prefix_len, DEFAULT_PREFIX_LEN);
+        assert_eq!(config.cloudflare_timeout, DEFAULT_TIMEOUT);
+        assert_eq!(config.max_retries, DEFAULT_MAX_RETRIES);
+        assert_eq!(config.base_delay, DEFAULT_BASE_DELAY);
+        assert_eq!(config.history, History::default());
+        assert!(config.zones.is_empty());
+        assert!(config.ipv4_enabled());
+        assert!(!config.ipv6_enabled());
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn suffix_interface_id() {
+        let suffix = |suffix: &str| -> Result<Option<Ipv6Addr>, serde_json::Error> {
+            serde_json::from_str::<RecordConfig>(&format!(
+                r#"{{"name": "www.example.com", "suffix": "{suffix}"}}"#
+            ))
+            .map(|record| record.suffix)
+        };
+
+        let expected: Option<Ipv6Addr> = Some("::1234:5678:9abc:def0".parse().unwrap());
+        assert_eq!(suffix("::1234:5678:9abc:def0").unwrap(), expected);
+        assert_eq!(suffix("1234:5678:9abc:def0").unwrap(), expected);
+        assert_eq!(suffix("0x123456789abcdef0").unwrap(), expected);
+        assert_eq!(suffix("0x1").unwrap(), Some("::1".parse().unwrap()));
+        assert_eq!(suffix("0:0:0:1").unwrap(), Some("::1".parse().unwrap()));
+
+        assert!(suffix("1234:5678:9abc").is_err());
+        assert!(suffix("1234:5678:9abc:def0g").is_err());
+        assert!(suffix("0x").is_err());
+        assert!(suffix("0x+1").is_err());
+        assert!(suffix("0x123456789abcdef01").is_err());
+
+        let zone: ZoneConfig = serde_json::from_str(
+            r#"{"name": "example.com", "default_suffix": "0x1", "records": []}"#,
+        )
+        .unwrap();
+        assert_eq!(zone.default_suffix, Some("::1".parse().unwrap()));
+
+        let zone: ZoneConfig = serde_json::from_str(
+            r#"{"name": "example.com", "default_eui64": "0x2", "records": []}"#,
+        )
+        .unwrap();
+        assert_eq!(zone.default_suffix, Some("::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn suffix_eui64_alias() {
+        let record: RecordConfig =
+            serde_json::from_str(r#"{"name": "www.example.com", "eui64": "0x1"}"#).unwrap();
+        assert_eq!(record.suffix, Some("::1".parse().unwrap()));
+
+        assert!(
+            serde_json::from_str::<RecordConfig>(
+                r#"{"name": "www.example.com", "suffix": "::1", "eui64": "::2"}"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn zone_default_suffix() {
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{
+                "name": "example.com",
+                "default_suffix": "::aaaa:bbbb:cccc:dddd",
+                "records": [
+                    {"name": "www.example.com"},
+                    {"name": "mail.example.com", "suffix": "::1"},
+                    {"name": "vpn.example.com", "full_ipv6": true},
+                    {"name": "blog.example.com", "target": "example.net"}
+                ]
+            }]"#,
+        )
+        .unwrap();
+        let config: Config = ConfigBuilder::new().zones(zones).build().unwrap();
+        let records: &[RecordConfig] = &config.zones[0].records;
+
+        let prefix: Ipv6Addr = "2001:db8:1:2::".parse().unwrap();
+        assert_eq!(
+            records[0].aaaa_content(Some(prefix), None),
+            Some("2001:db8:1:2:aaaa:bbbb:cccc:dddd".parse().unwrap())
+        );
+        assert_eq!(
+            records[1].aaaa_content(Some(prefix), None),
+            Some("2001:db8:1:2::1".parse().unwrap())
+        );
+        assert_eq!(records[2].suffix, None);
+        assert_eq!(records[3].suffix, None);
+    }
+
+    #[test]
+    fn zone_fqdn_from_zone() {
+        let zones = |fqdn_from_zone: bool| -> Vec<ZoneConfig> {
+            serde_json::from_value(serde_json::json!([{
+                "name": "Example.com",
+                "fqdn_from_zone": fqdn_from_zone,
+                "records": [
+                    {"name": "www"},
+                    {"name": "mail.example.com"},
+                    {"name": "@"},
+                    {"name": ""},
+                    {"name": "example.com."},
+                    {"name": "vpn.example.net"}
+                ]
+            }]))
+            .unwrap()
+        };
+        let names = |config: &Config| -> Vec<String> {
+            config.zones[0]
+                .records
+                .iter()
+                .map(|record| record.name.clone())
+                .collect()
+        };
+
+        let config: Config = ConfigBuilder::new().zones(zones(true)).build().unwrap();
+        assert_eq!(
+            names(&config),
+            [
+                "www.example.com",
+                "mail.example.com",
+                "example.com",
+                "example.com",
+                "example.com",
+                "vpn.example.net.example.com"
+            ]
+        );
+
+        let config: Config = ConfigBuilder::new().zones(zones(false)).build().unwrap();
+        assert_eq!(names(&config)[0], "www");
+
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"id": "zone-id", "fqdn_from_zone": true, "records": [{"name": "www"}]}]"#,
+        )
+        .unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+    }
+
+    #[test]
+    fn zone_apex() {
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"name": "Example.com", "records": [{"name": "@"}, {"name": "www"}]}]"#,
+        )
+        .unwrap();
+        let config: Config = ConfigBuilder::new().zones(zones).build().unwrap();
+        assert_eq!(config.zones[0].records[0].name, "example.com");
+        assert_eq!(config.zones[0].records[1].name, "www");
+
+        let zones: Vec<ZoneConfig> =
+            serde_json::from_str(r#"[{"id": "zone-id", "records": [{"name": "@"}]}]"#).unwrap();
+        let e: anyhow::Error = ConfigBuilder::new().zones(zones).build().err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "Record '@' in zone 'zone-id' requires the zone name"
+        );
+    }
+
+    #[test]
+    fn suffix_prefix_overlap() {
+        let zones = |suffix: &str| -> Vec<ZoneConfig> {
+            serde_json::from_str(&format!(
+                r#"[{{"name": "example.com", "records": [{{"name": "www.example.com", "suffix": "{suffix}"}}]}}]"#
+            ))
+            .unwrap()
+        };
+
+        assert!(
+            ConfigBuilder::new()
+                .zones(zones("::aaaa:bbbb:cccc:dddd"))
+                .build()
+                .is_ok()
+        );
+        assert!(
+            ConfigBuilder::new()
+                .zones(zones("1::aaaa:bbbb:cccc:dddd"))
+                .build()
+                .is_err()
+        );
+        assert!(
+            ConfigBuilder::new()
+                .prefix_len(48)
+                .zones(zones("::1:aaaa:bbbb:cccc:dddd"))
+                .build()
+                .is_ok()
+        );
+        assert!(
+            ConfigBuilder::new()
+                .prefix_len(80)
+                .zones(zones("::aaaa:bbbb:cccc:dddd"))
+                .build()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn record_prefix_len() {
+        let zones = |records: &str| -> Vec<ZoneConfig> {
+            serde_json::from_str(&format!(
+                r#"[{{"name": "example.com", "records": {records}}}]"#
+            ))
+            .unwrap()
+        };
+        let build = |records: &str| -> anyhow::Result<Config> {
+            ConfigBuilder::new()
+                .prefix_len(56)
+                .zones(zones(records))
+                .build()
+        };
+
+        // the subnet ID fits the global /56 but not a record-level /64
+        assert!(build(r#"[{"name": "www.example.com", "suffix": "0:0:0:ff::1"}]"#).is_ok());
+        assert!(
+            build(r#"[{"name": "www.example.com", "suffix": "0:0:0:ff::1", "prefix_len": 64}]"#)
+                .is_err()
+        );
+        assert!(build(r#"[{"name": "www.example.com", "prefix_len": 0}]"#).is_err());
+        assert!(build(r#"[{"name": "www.example.com", "prefix_len": 129}]"#).is_err());
+
+        let config: Config = build(
+            r#"[
+                {"name": "www.example.com", "suffix": "::1", "prefix_len": 64},
+                {"name": "lan.example.com", "suffix": "0:0:0:100::1", "prefix_len": 48}
+            ]"#,
+        )
+        .unwrap();
+        let prefix: Option<Ipv6Addr> = Some("2001:db8:0:ab00::".parse().unwrap());
+        let records: &[RecordConfig] = &config.zones[0].records;
+        assert_eq!(
+            records[0].aaaa_content(prefix, None),
+            Some("2001:db8:0:ab00::1".parse().unwrap())
+        );
+        assert_eq!(
+            records[1].aaaa_content(prefix, None),
+            Some("2001:db8:0:100::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn builder_validation() {
+        let zones: Vec<ZoneConfig> =
+            serde_json::from_str(r#"[{"records": [{"name": "www.example.com"}]}]"#).unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+
+        assert!(ConfigBuilder::new().prefix_len(0).build().is_err());
+        assert!(ConfigBuilder::new().prefix_len(129).build().is_err());
+        assert!(ConfigBuilder::new().prefix_len(128).build().is_ok());
+        assert!(
+            ConfigBuilder::new()
+                .ipv6_prefix_significant_len(0)
+                .build()
+                .is_err()
+        );
+        assert!(
+            ConfigBuilder::new()
+                .ipv6_prefix_significant_len(48)
+                .build()
+                .is_ok()
+        );
+
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"name": "example.com", "records": [{"name": "www.example.com", "suffix": "::1", "eui64_from_interface": "eth0"}]}]"#,
+        )
+        .unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"name": "example.com", "records": [{"name": "www.example.com", "suffix": "::1", "target": "example.net"}]}]"#,
+        )
+        .unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"name": "example.com", "records": [{"name": "www.example.com", "type": "CNAME"}]}]"#,
+        )
+        .unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"name": "example.com", "records": [{"name": "www.example.com", "type": "A", "target": "example.net"}]}]"#,
+        )
+        .unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+    }
+
+    #[test]
+    fn zone_clients() {
+        // variables that are always set, the value is only used as a token
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[
+                {"name": "example.com", "token_env": "PATH", "records": []},
+                {"name": "example.org", "token_env": "HOME", "records": []},
+                {"name": "example.net", "token_env": "PATH", "records": []}
+            ]"#,
+        )
+        .unwrap();
+        let config: Config = ConfigBuilder::new().zones(zones).build().unwrap();
+        let clients: Clients = config.cloudflare_clients().unwrap();
+
+        let com: &Client = clients.zone(&config.zones[0]).unwrap();
+        let org: &Client = clients.zone(&config.zones[1]).unwrap();
+        let net: &Client = clients.zone(&config.zones[2]).unwrap();
+        assert!(!std::ptr::eq(com, org));
+        assert!(std::ptr::eq(com, net));
+
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[{"name": "example.com", "token_env": "CFDDNS_UNSET_TOKEN", "records": []}]"#,
+        )
+        .unwrap();
+        assert!(ConfigBuilder::new().zones(zones).build().is_err());
+    }
+
+    #[test]
+    fn credentials_token() {
+        assert!(matches!(
+            select_credentials(Ok("token".to_string()), None, None).unwrap(),
+            Credentials::UserAuthToken { token } if token == "token"
+        ));
+    }
+
+    #[test]
+    fn credentials_key() {
+        assert!(matches!(
+            select_credentials(
+                Err(anyhow::anyhow!("not set")),
+                Some("user@example.com".to_string()),
+                Some("key".to_string())
+            )
+            .unwrap(),
+            Credentials::UserAuthKey { email, key } if email == "user@example.com" && key == "key"
+        ));
+    }
+
+    #[test]
+    fn token_from_file() {
+        let token_dir: TempDir = TempDir::new().unwrap();
+        let token_path: PathBuf = token_dir.path().join("token");
+
+        std::fs::write(&token_path, "secret-token\n").unwrap();
+        assert_eq!(read_token(Some(&token_path)).unwrap(), "secret-token");
+
+        assert!(matches!(
+            select_credentials(read_token(Some(&token_path)), None, None).unwrap(),
+            Credentials::UserAuthToken { token } if token == "secret-token"
+        ));
+
+        std::fs::write(&token_path, " \n").unwrap();
+        assert!(read_token(Some(&token_path)).is_err());
+
+        assert!(read_token(Some(&token_dir.path().join("missing"))).is_err());
+    }
+
+    #[test]
+    fn token_from_systemd_credential() {
+        let credentials_dir: TempDir = TempDir::new().unwrap();
+        std::fs::write(
+            credentials_dir.path().join("cloudflare-token"),
+            "secret-token",
+        )
+        .unwrap();
+        let dir: Option<OsString> = Some(credentials_dir.path().as_os_str().to_owned());
+
+        let path: PathBuf = systemd_credential_path(dir.clone(), "cloudflare-token").unwrap();
+        assert_eq!(read_token(Some(&path)).unwrap(), "secret-token");
+
+        assert_eq!(systemd_credential_path(dir, "missing"), None);
+        assert_eq!(systemd_credential_path(None, "cloudflare-token"), None);
+    }
+
+    #[test]
+    fn credentials_invalid() {
+        // nothing set
+        assert!(select_credentials(Err(anyhow::anyhow!("not set")), None, None).is_err());
+
+        // incomplete key credentials
+        assert!(
+            select_credentials(
+                Err(anyhow::anyhow!("not set")),
+                Some("user@example.com".to_string()),
+                None
+            )
+            .is_err()
+        );
+        assert!(
+            select_credentials(Ok("token".to_string()), None, Some("key".to_string())).is_err()
+        );
+
+        // both token and key credentials
+        assert!(
+            select_credentials(
+                Ok("token".to_string()),
+                Some("user@example.com".to_string()),
+                Some("key".to_string())
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn rust_log_directives() {
+        use log::LevelFilter;
+
+        assert_eq!(rust_log_level("warn").unwrap(), Some(LevelFilter::Warn));
+        assert_eq!(rust_log_level(" DEBUG ").unwrap(), Some(LevelFilter::Debug));
+        assert_eq!(
+            rust_log_level("cfddns=debug").unwrap(),
+            Some(LevelFilter::Debug)
+        );
+        assert_eq!(
+            rust_log_level("info,reqwest=warn").unwrap(),
+            Some(LevelFilter::Info)
+        );
+        assert_eq!(
+            rust_log_level("error,cfddns::ip=trace").unwrap(),
+            Some(LevelFilter::Trace)
+        );
+        assert_eq!(rust_log_level("cfddns").unwrap(), Some(LevelFilter::Trace));
+        assert_eq!(
+            rust_log_level("info/some.*regex").unwrap(),
+            Some(LevelFilter::Info)
+        );
+        assert_eq!(rust_log_level("reqwest=warn").unwrap(), None);
+        assert_eq!(rust_log_level("").unwrap(), None);
+        assert!(rust_log_level("cfddns=loud").is_err());
+    }
+
+    #[test]
+    fn env_substitution() {
+        let lookup = |name: &str| -> Option<String> {
+            match name {
+                "WAN_IFACE" => Some("bond-wan".to_string()),
+                "QUOTED" => Some("a\"b\\c".to_string()),
+                _ => None,
+            }
+        };
+        let substitute = |text: &str| -> anyhow::Result<String> {
+            substitute_env(text.as_bytes(), lookup).map(|out| String::from_utf8(out).unwrap())
+        };
+
+        assert_eq!(
+            substitute(r#"{"a_interface": "${WAN_IFACE}"}"#).unwrap(),
+            r#"{"a_interface": "bond-wan"}"#
+        );
+        assert_eq!(
+            substitute(r#"["${WAN_IFACE}.10", "$WAN_IFACE", "${QUOTED}"]"#).unwrap(),
+            r#"["bond-wan.10", "$WAN_IFACE", "a\"b\\c"]"#
+        );
+        assert_eq!(
+            substitute(r#"{"a_interface": "${UNSET}"}"#)
+                .unwrap_err()
+                .to_string(),
+            "Environment variable 'UNSET' referenced in config file is not set"
+        );
+        assert!(substitute(r#"{"a_interface": "${WAN_IFACE"}"#).is_err());
+
+        // escaped placeholders are kept literally
+        assert_eq!(
+            substitute(r#"{"template": "$${new_ipv4} via ${WAN_IFACE}", "x": "$${UNSET"}"#)
+                .unwrap(),
+            r#"{"template": "${new_ipv4} via bond-wan", "x": "${UNSET"}"#
+        );
+    }
+
+    #[test]
+    fn history_path_validation() {
+        assert_eq!(
+            validate_history_path(Path::new(""))
+                .unwrap_err()
+                .to_string(),
+            "history_path must not be empty"
+        );
+        assert!(validate_history_path(Path::new("nonexistent-dir/history.json")).is_err());
+        assert!(validate_history_path(Path::new("history.json")).is_ok());
+        assert!(validate_history_path(Path::new("src/history.json")).is_ok());
+        assert!(validate_history_path(Path::new("/var/lib/cfddns/history.json")).is_ok());
+    }
+
+    #[test]
+    fn restore_history_file_creation() {
+        let hist_dir: TempDir = TempDir::new().unwrap();
+        let mut hist_file_path = hist_dir.keep();
+        hist_file_path.push("history.json");
+
+        assert!(!hist_file_path.exists());
+
+        assert_eq!(
+            restore_history(&hist_file_path, false).unwrap(),
+            History::default()
+        );
+
+        assert!(hist_file_path.exists());
+    }
+
+    #[test]
+    fn restore_history_dry_run() {
+        let hist_dir: TempDir = TempDir::new().unwrap();
+        let hist_file_path: PathBuf = hist_dir.path().join("history.json");
+
+        assert_eq!(
+            restore_history(&hist_file_path, true).unwrap(),
+            History::default()
+        );
+        assert!(!hist_file_path.exists());
+
+        std::fs::write(&hist_file_path, "not json").unwrap();
+        assert_eq!(
+            restore_history(&hist_file_path, true).unwrap(),
+            History::default()
+        );
+        assert_eq!(
+            std::fs::read_to_string(&hist_file_path).unwrap(),
+            "not json"
+        );
+    }
+
+    #[test]
+    fn save_restore_history() {
+        let hist_dir: TempDir = TempDir::new().unwrap();
+        let mut hist_file_path = hist_dir.keep();
+        hist_file_path.push("history.json");
+
+        // file creation
+        save_history(&hist_file_path, &History::default()).unwrap();
+
+        // restore history
+        let restored = restore_history(&hist_file_path, false).unwrap();
+        assert_eq!(restored, History::default());
+
+        let history_unspecified_addr: History = History {
+            ipv4: Some(Ipv4Addr::UNSPECIFIED),
+            ipv6_prefix: Some(Ipv6Addr::UNSPECIFIED),
+            ..Default::default()
+        };
+
+        // file overwrite
+        save_history(&hist_file_path, &history_unspecified_addr).unwrap();
+
+        // restore overwritten history
+        let restored = restore_history(&hist_file_path, false).unwrap();
+        assert_eq!(restored, history_unspecified_addr);
+    }
+
+    #[test]
+    fn save_restore_history_ids() {
+        let hist_dir: TempDir = TempDir::new().unwrap();
+        let mut hist_file_path = hist_dir.keep();
+        hist_file_path.push("history.json");
+
+        let history: History = History {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ipv6_prefix: None,
+            ipv6_address: None,
+            zone_ids: HashMap::from([("example.com".to_string(), "zone-id".to_string())]),
+            record_ids: HashMap::from([(
+                "zone-id".to_string(),
+                RecordIds {
+                    a: HashMap::from([("www.example.com".to_string(), vec!["a-id".to_string()])]),
+                    aaaa: HashMap::from([(
+                        "www.example.com".to_string(),
+                        vec!["aaaa-id".to_string()],
+                    )]),
+                    cname: HashMap::new(),
+                },
+            )]),
+            record_content: HashMap::from([(
+                "www.example.com".to_string(),
+                vec![DnsContent::A {
+                    content: Ipv4Addr::new(192, 0, 2, 1),
+                }],
+            )]),
+            last_updated: Some(1_700_000_000),
+        };
+
+        save_history(&hist_file_path, &history).unwrap();
+
+        let restored = restore_history(&hist_file_path, false).unwrap();
+        assert_eq!(restored, history);
+    }
+
+    #[test]
+    fn save_history_replaces_existing() {
+        let hist_dir: TempDir = TempDir::new().unwrap();
+        let hist_file_path: PathBuf = hist_dir.path().join("history.json");
+
+        let old_history: History = History {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ..Default::default()
+        };
+        save_history(&hist_file_path, &old_history).unwrap();
+
+        let new_history: History = History {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+            ipv6_prefix: Some("2001:db8::".parse().unwrap()),
+            ..Default::default()
+        };
+        save_history(&hist_file_path, &new_history).unwrap();
+
+        let entries: Vec<PathBuf> = std::fs::read_dir(hist_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries, std::slice::from_ref(&hist_file_path));
+
+        assert_eq!(
+            restore_history(&hist_file_path, false).unwrap(),
+            new_history
+        );
+    }
+
+    #[test]
+    fn record_ids_single_id() {
+        let ids: RecordIds = serde_json::from_str(
+            r#"{"a": {"www.example.com": "a-id"}, "aaaa": {"www.example.com": ["x-id", "y-id"]}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(ids.a["www.example.com"], ["a-id"]);
+        assert_eq!(ids.aaaa["www.example.com"], ["x-id", "y-id"]);
+        assert!(ids.cname.is_empty());
+    }
+
+    #[test]
+    fn restore_corrupt_history() {
+        const GARBAGE: &[u8] = b"{\"ipv4\": \"192.0\x00\xff";
+
+        let hist_dir: TempDir = TempDir::new().unwrap();
+        let hist_file_path: PathBuf = hist_dir.path().join("history.json");
+        std::fs::write(&hist_file_path, GARBAGE).unwrap();
+
+        assert_eq!(
+            restore_history(&hist_file_path, false).unwrap(),
+            History::default()
+        );
+
+        assert!(!hist_file_path.exists());
+        assert_eq!(
+            std::fs::read(hist_dir.path().join("history.json.bak")).unwrap(),
+            GARBAGE
+        );
+    }
+
+    #[test]
+    fn restore_history_without_ids() {
+        let history: History =
+            serde_json::from_str(r#"{"ipv4": "192.0.2.1", "ipv6_prefix": null}"#).unwrap();
+
+        assert_eq!(
+            history,
+            History {
+                ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+                ..Default::default()
+            }
+        );
     }
 }
@@ -1,37 +1,333 @@
 use anyhow::Context as _;
+use hickory_resolver::{
+    Resolver,
+    config::{NameServerConfig, ResolverConfig},
+    net::runtime::TokioRuntimeProvider,
+    proto::rr::RData,
+};
+use ipnet::IpNet;
 use itertools::Itertools as _;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use nix::{ifaddrs::InterfaceAddress, sys::socket::SockaddrStorage};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
 
-pub fn interface_ipv4(iface: &str) -> anyhow::Result<Ipv4Addr> {
-    let addrs: Vec<Ipv4Addr> = nix::ifaddrs::getifaddrs()
+/// Equivalent to the unstable `Ipv4Addr::is_shared`.
+///
+/// Shared address space (100.64.0.0/10) is used by carrier-grade NAT.
+pub fn is_shared_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, _, _] = ip.octets();
+    a == 100 && (b & 0b1100_0000) == 64
+}
+
+/// Equivalent to the unstable `Ipv4Addr::is_global`.
+fn is_global_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, d] = ip.octets();
+
+    let this_network: bool = a == 0;
+    // 192.0.0.9 and 192.0.0.10 are globally reachable
+    let protocol_assignments: bool = a == 192 && b == 0 && c == 0 && d != 9 && d != 10;
+    let benchmarking: bool = a == 198 && (b & 0xFE) == 18;
+    let reserved: bool = (a & 0xF0) == 240;
+
+    !(this_network
+        || ip.is_private()
+        || is_shared_v4(ip)
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || protocol_assignments
+        || ip.is_documentation()
+        || benchmarking
+        || reserved
+        || ip.is_broadcast())
+}
+
+/// Equivalent to the unstable `Ipv6Addr::is_unicast_global`.
+fn is_unicast_global_v6(ip: &Ipv6Addr) -> bool {
+    let segments: [u16; 8] = ip.segments();
+
+    let documentation: bool = segments[0] == 0x2001 && segments[1] == 0xDB8;
+    let benchmarking: bool = segments[0] == 0x2001 && segments[1] == 0x2 && segments[2] == 0;
+
+    !(ip.is_multicast()
+        || ip.is_loopback()
+        || ip.is_unicast_link_local()
+        || ip.is_unique_local()
+        || ip.is_unspecified()
+        || documentation
+        || benchmarking)
+}
+
+/// Returns true for NAT64 and IPv4-mapped addresses
+///
+/// These embed an IPv4 address in a translation prefix, such as
+/// `64:ff9b::/96` on NAT64 and CLAT hosts, and do not belong to the host's
+/// own IPv6 prefix.
+fn is_translated_v6(ip: &Ipv6Addr) -> bool {
+    let segments: [u16; 8] = ip.segments();
+
+    // RFC 6052 well-known prefix
+    let nat64: bool = segments[..6] == [0x64, 0xFF9B, 0, 0, 0, 0];
+    // RFC 8215 local-use prefix
+    let nat64_local: bool = segments[..3] == [0x64, 0xFF9B, 0x1];
+
+    nat64 || nat64_local || ip.to_ipv4_mapped().is_some()
+}
+
+/// Returns a filter rejecting addresses within any of `exclude`
+fn not_excluded<T: Copy + Into<IpAddr>>(exclude: &[IpNet]) -> impl Fn(&T) -> bool {
+    move |ip: &T| {
+        let ip: IpAddr = (*ip).into();
+        !exclude.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Addresses of an interface
+///
+/// Fails listing the available interfaces if `iface` does not exist, so a
+/// typo is not reported as a missing address.
+fn interface_addresses(iface: &str) -> anyhow::Result<Vec<SockaddrStorage>> {
+    let ifaddrs: Vec<InterfaceAddress> = nix::ifaddrs::getifaddrs()
         .context("Failed to obtain network interface information")?
+        .collect();
+
+    if !ifaddrs.iter().any(|i| i.interface_name == iface) {
+        let available: Vec<&str> = ifaddrs
+            .iter()
+            .map(|i| i.interface_name.as_str())
+            .unique()
+            .collect();
+        anyhow::bail!(
+            "Interface '{iface}' not found (available: {})",
+            available.join(", ")
+        );
+    }
+
+    Ok(ifaddrs
+        .into_iter()
         .filter(|i| i.interface_name == iface)
         .filter_map(|ifaddr| ifaddr.address)
+        .collect())
+}
+
+pub fn interface_ipv4(iface: &str, exclude: &[IpNet]) -> anyhow::Result<Ipv4Addr> {
+    let addrs: Vec<Ipv4Addr> = interface_addresses(iface)?
+        .into_iter()
         .filter_map(|sockaddr| sockaddr.as_sockaddr_in().map(|sockaddr4| sockaddr4.ip()))
-        .filter(|ip| ip.is_global())
+        .filter(is_global_v4)
+        .filter(not_excluded(exclude))
         .unique()
         .collect();
 
-    if addrs.len() > 1 {
-        log::warn!("Multiple global IPv4 addresses found on interface '{iface}'")
+    select_ipv4(iface, &addrs)
+}
+
+/// Global IPv4 of the first interface in `ifaces` that has one
+pub fn interface_ipv4_multi(ifaces: &[String], exclude: &[IpNet]) -> anyhow::Result<Ipv4Addr> {
+    first_interface(ifaces, |iface| interface_ipv4(iface, exclude))
+}
+
+fn first_interface<T>(
+    ifaces: &[String],
+    lookup: impl Fn(&str) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut last_error: Option<anyhow::Error> = None;
+
+    for iface in ifaces {
+        match lookup(iface) {
+            Ok(ip) => return Ok(ip),
+            Err(e) => {
+                log::warn!("{e:#}");
+                last_error = Some(e);
+            }
+        }
     }
 
-    addrs
-        .first()
+    match last_error {
+        Some(e) => Err(e).with_context(|| format!("All {} interfaces failed", ifaces.len())),
+        None => anyhow::bail!("No interfaces"),
+    }
+}
+
+/// Select the numerically lowest address so the choice does not depend on
+/// the order reported by `getifaddrs`.
+fn select_ipv4(iface: &str, addrs: &[Ipv4Addr]) -> anyhow::Result<Ipv4Addr> {
+    let selected: Ipv4Addr = addrs
+        .iter()
+        .min()
         .copied()
-        .with_context(|| format!("Interface '{iface}' does not have a global IPv4 address"))
+        .with_context(|| format!("Interface '{iface}' does not have a global IPv4 address"))?;
+
+    if addrs.len() > 1 {
+        log::warn!(
+            "Multiple global IPv4 addresses found on interface '{iface}', selected the lowest {selected}"
+        )
+    }
+
+    Ok(selected)
 }
 
-const PREFIX_MASK: Ipv6Addr = Ipv6Addr::new(0xFFFF, 0xFFFF, 0xFFFF, 0xFFFF, 0, 0, 0, 0);
+pub const DEFAULT_PREFIX_LEN: u8 = 64;
 
-pub fn interface_ipv6_prefix(iface: &str) -> anyhow::Result<Ipv6Addr> {
-    let addrs: Vec<Ipv6Addr> = nix::ifaddrs::getifaddrs()
+/// Names of the network interfaces, in the order reported by the system
+fn interface_names() -> anyhow::Result<Vec<String>> {
+    Ok(nix::ifaddrs::getifaddrs()
         .context("Failed to obtain network interface information")?
-        .filter(|i| i.interface_name == iface)
-        .filter_map(|ifaddr| ifaddr.address)
-        .filter_map(|sockaddr| sockaddr.as_sockaddr_in6().map(|sockaddr6| sockaddr6.ip()))
-        .filter(|ip| ip.is_unicast_global())
-        .map(|ip| ip & PREFIX_MASK)
+        .map(|ifaddr| ifaddr.interface_name)
+        .unique()
+        .collect())
+}
+
+/// Each interface with the global IPv4 and IPv6 prefix cfddns would detect
+/// on it, one per line
+pub fn list_interfaces() -> anyhow::Result<String> {
+    let mut out: String = String::new();
+    for iface in interface_names()? {
+        let ipv4: String =
+            interface_ipv4(&iface, &[]).map_or_else(|_| "none".to_string(), |ip| ip.to_string());
+        let ipv6_prefix: String = interface_ipv6_prefix(&iface, DEFAULT_PREFIX_LEN, false, &[])
+            .map_or_else(
+                |_| "none".to_string(),
+                |prefix| format!("{prefix}/{DEFAULT_PREFIX_LEN}"),
+            );
+        out.push_str(&format!(
+            "{iface}: ipv4 {ipv4}, ipv6 prefix {ipv6_prefix}\n"
+        ));
+    }
+    Ok(out)
+}
+
+/// User-Agent for HTTP requests
+pub const DEFAULT_USER_AGENT: &str = concat!("cfddns/", env!("CARGO_PKG_VERSION"));
+
+/// IPv6 network mask for a prefix length in the range `1..=128`
+pub fn prefix_mask(prefix_len: u8) -> Ipv6Addr {
+    let host_bits: u32 = 128_u32.saturating_sub(prefix_len.into());
+    Ipv6Addr::from(u128::MAX.checked_shl(host_bits).unwrap_or(0))
+}
+
+// /proc/net is hidden by ProcSubset=pid, /proc/self/net is not
+const IF_INET6_PATH: &str = "/proc/self/net/if_inet6";
+const IFA_F_TEMPORARY: u8 = 0x01;
+const IFA_F_DEPRECATED: u8 = 0x20;
+
+/// Parse address flags for an interface from the contents of `if_inet6`
+fn parse_if_inet6(contents: &str, iface: &str) -> HashMap<Ipv6Addr, u8> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [addr, _index, _prefix_len, _scope, flags, name] = fields.as_slice() else {
+                return None;
+            };
+            if *name != iface {
+                return None;
+            }
+            let addr: u128 = u128::from_str_radix(addr, 16).ok()?;
+            let flags: u8 = u8::from_str_radix(flags, 16).ok()?;
+            Some((Ipv6Addr::from(addr), flags))
+        })
+        .collect()
+}
+
+/// Selection rank of an address, lower is preferred
+///
+/// Stable addresses are preferred over deprecated addresses, which are
+/// preferred over temporary (privacy extension) addresses.
+fn stability_rank(flags: u8) -> u8 {
+    if flags & IFA_F_TEMPORARY != 0 {
+        2
+    } else if flags & IFA_F_DEPRECATED != 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Addresses that may belong to the host's global IPv6 prefix
+fn ipv6_candidates(addrs: impl Iterator<Item = Ipv6Addr>, exclude: &[IpNet]) -> Vec<Ipv6Addr> {
+    addrs
+        .filter(is_unicast_global_v6)
+        .filter(|ip| !is_translated_v6(ip))
+        .filter(not_excluded(exclude))
+        .unique()
+        .collect()
+}
+
+/// Sort addresses by stability rank if `flags` are known, then by address
+fn sort_ipv6(addrs: &mut [Ipv6Addr], flags: Option<&HashMap<Ipv6Addr, u8>>) {
+    match flags {
+        Some(flags) => {
+            addrs.sort_by_key(|ip| (stability_rank(flags.get(ip).copied().unwrap_or(0)), *ip))
+        }
+        None => addrs.sort(),
+    }
+}
+
+/// Unicast global IPv6 addresses of an interface, in order of preference
+///
+/// If `prefer_stable` is set addresses are ordered by stability, then by
+/// address, otherwise they are ordered by address.
+fn interface_ipv6_addresses(
+    iface: &str,
+    prefer_stable: bool,
+    exclude: &[IpNet],
+) -> anyhow::Result<Vec<Ipv6Addr>> {
+    let mut addrs: Vec<Ipv6Addr> = ipv6_candidates(
+        interface_addresses(iface)?
+            .into_iter()
+            .filter_map(|sockaddr| sockaddr.as_sockaddr_in6().map(|sockaddr6| sockaddr6.ip())),
+        exclude,
+    );
+
+    let flags: Option<HashMap<Ipv6Addr, u8>> = if prefer_stable {
+        match std::fs::read_to_string(IF_INET6_PATH) {
+            Ok(contents) => Some(parse_if_inet6(&contents, iface)),
+            Err(e) => {
+                log::warn!("Failed to read IPv6 address flags from {IF_INET6_PATH}: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    sort_ipv6(&mut addrs, flags.as_ref());
+
+    Ok(addrs)
+}
+
+/// Full unicast global IPv6 address of an interface
+///
+/// If `prefer_stable` is set the most stable address is selected, as for
+/// [`interface_ipv6_prefix`].
+pub fn interface_ipv6_address(
+    iface: &str,
+    prefer_stable: bool,
+    exclude: &[IpNet],
+) -> anyhow::Result<Ipv6Addr> {
+    let addrs: Vec<Ipv6Addr> = interface_ipv6_addresses(iface, prefer_stable, exclude)?;
+
+    if addrs.len() > 1 {
+        log::warn!("Multiple unicast global IPv6 addresses found on interface '{iface}'")
+    }
+
+    addrs.first().copied().with_context(|| {
+        format!("Interface '{iface}' does not have an unicast global IPv6 address")
+    })
+}
+
+pub fn interface_ipv6_prefix(
+    iface: &str,
+    prefix_len: u8,
+    prefer_stable: bool,
+    exclude: &[IpNet],
+) -> anyhow::Result<Ipv6Addr> {
+    let addrs: Vec<Ipv6Addr> = interface_ipv6_addresses(iface, prefer_stable, exclude)?
+        .into_iter()
+        .map(|ip| ip & prefix_mask(prefix_len))
         .unique()
         .collect();
 
@@ -44,26 +340,1063 @@ pub fn interface_ipv6_prefix(iface: &str) -> anyhow::Result<Ipv6Addr> {
     })
 }
 
-pub async fn http_get_ipv4(url: url::Url) -> anyhow::Result<Ipv4Addr> {
-    let ip: Ipv4Addr = reqwest::get(url.clone())
+/// Modified EUI-64 interface identifier for a MAC address
+///
+/// Inserts `FFFE` into the middle of the MAC and flips the universal/local bit.
+pub fn mac_to_eui64(mac: [u8; 6]) -> u64 {
+    u64::from_be_bytes([
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xFF,
+        0xFE,
+        mac[3],
+        mac[4],
+        mac[5],
+    ])
+}
+
+pub fn interface_mac(iface: &str) -> anyhow::Result<[u8; 6]> {
+    interface_addresses(iface)?
+        .into_iter()
+        .filter_map(|sockaddr| {
+            sockaddr
+                .as_link_addr()
+                .filter(|link| link.halen() == 6)
+                .and_then(|link| link.addr())
+        })
+        .find(|mac| *mac != [0; 6])
+        .with_context(|| format!("Interface '{iface}' does not have a MAC address"))
+}
+
+/// Default JSON field containing the IP for JSON responses
+pub const DEFAULT_HTTP_JSON_FIELD: &str = "ip";
+
+/// Extract the IP from a plain text or JSON response body
+fn parse_http_body<T>(body: &str, is_json: bool, json_field: &str) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let body: &str = body.trim();
+
+    if is_json || body.starts_with('{') {
+        let value: serde_json::Value =
+            serde_json::from_str(body).context("Failed to deserialize JSON response")?;
+        value
+            .get(json_field)
+            .and_then(serde_json::Value::as_str)
+            .with_context(|| format!("JSON response does not have a string field '{json_field}'"))?
+            .trim()
+            .parse::<T>()
+            .map_err(anyhow::Error::from)
+    } else {
+        body.parse::<T>().map_err(anyhow::Error::from)
+    }
+}
+
+/// Default timeout for HTTP IP detection requests
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay between address detection attempts
+pub const DEFAULT_IP_DETECT_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Create the HTTP client used for IP detection
+///
+/// Requests are sent from `interface` if set, using `SO_BINDTODEVICE`.
+/// This is only supported on Linux and similar platforms.
+///
+/// Requests are sent through `proxy` if set, otherwise the proxy environment
+/// variables are used.
+pub fn http_client(
+    timeout: Duration,
+    interface: Option<&str>,
+    proxy: Option<&url::Url>,
+    user_agent: &str,
+) -> reqwest::Result<reqwest::Client> {
+    let mut builder: reqwest::ClientBuilder = reqwest::Client::builder()
+        .timeout(timeout)
+        .user_agent(user_agent);
+    if let Some(interface) = interface {
+        builder = builder.interface(interface);
+    }
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+    }
+    builder.build()
+}
+
+async fn http_get<T>(
+    client: &reqwest::Client,
+    url: &url::Url,
+    json_field: &str,
+) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let response: reqwest::Response = client
+        .get(url.clone())
+        .send()
         .await
-        .with_context(|| format!("Failed to GET {url}"))?
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to GET {url}"))?;
+
+    let is_json: bool = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+
+    let body: String = response
         .text()
-        .await?
+        .await
+        .with_context(|| format!("Failed to read response from {url}"))?;
+
+    parse_http_body(&body, is_json, json_field)
+        .with_context(|| format!("Unexptected data from {url}"))
+}
+
+pub async fn http_get_ipv4(
+    client: &reqwest::Client,
+    url: url::Url,
+    json_field: &str,
+) -> anyhow::Result<Ipv4Addr> {
+    http_get::<Ipv4Addr>(client, &url, json_field).await
+}
+
+pub async fn http_get_ipv6_prefix(
+    client: &reqwest::Client,
+    url: url::Url,
+    json_field: &str,
+    prefix_len: u8,
+) -> anyhow::Result<Ipv6Addr> {
+    let ip: Ipv6Addr = http_get::<Ipv6Addr>(client, &url, json_field).await?;
+    Ok(ip & prefix_mask(prefix_len))
+}
+
+/// GET each URL in order until one returns an address
+async fn http_get_first<T>(
+    client: &reqwest::Client,
+    urls: &[url::Url],
+    json_field: &str,
+) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let mut last_error: Option<anyhow::Error> = None;
+
+    for url in urls {
+        match http_get::<T>(client, url, json_field).await {
+            Ok(ip) => return Ok(ip),
+            Err(e) => {
+                log::warn!("{e:#}");
+                last_error = Some(e);
+            }
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(e).with_context(|| format!("All {} HTTP endpoints failed", urls.len())),
+        None => anyhow::bail!("No HTTP endpoints"),
+    }
+}
+
+pub async fn http_get_ipv4_multi(
+    client: &reqwest::Client,
+    urls: &[url::Url],
+    json_field: &str,
+) -> anyhow::Result<Ipv4Addr> {
+    http_get_first::<Ipv4Addr>(client, urls, json_field).await
+}
+
+pub async fn http_get_ipv6_prefix_multi(
+    client: &reqwest::Client,
+    urls: &[url::Url],
+    json_field: &str,
+    prefix_len: u8,
+) -> anyhow::Result<Ipv6Addr> {
+    let ip: Ipv6Addr = http_get_ipv6_address_multi(client, urls, json_field).await?;
+    Ok(ip & prefix_mask(prefix_len))
+}
+
+/// Full IPv6 address, including the interface identifier
+///
+/// Used for `full_ipv6` records on hosts with a single routable address.
+pub async fn http_get_ipv6_address_multi(
+    client: &reqwest::Client,
+    urls: &[url::Url],
+    json_field: &str,
+) -> anyhow::Result<Ipv6Addr> {
+    http_get_first::<Ipv6Addr>(client, urls, json_field).await
+}
+
+async fn command_stdout(command: &[String]) -> anyhow::Result<String> {
+    let (program, args) = command.split_first().context("Command is empty")?;
+    let display: String = command.join(" ");
+
+    let output: std::process::Output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run '{display}'"))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Command '{display}' failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    String::from_utf8(output.stdout).with_context(|| format!("Non UTF-8 output from '{display}'"))
+}
+
+pub async fn command_get_ipv4(command: &[String]) -> anyhow::Result<Ipv4Addr> {
+    let stdout: String = command_stdout(command).await?;
+    stdout
         .trim()
         .parse::<Ipv4Addr>()
-        .with_context(|| format!("Unexptected data from {url}"))?;
-    Ok(ip)
+        .with_context(|| format!("Unexpected output from '{}'", command.join(" ")))
 }
 
-pub async fn http_get_ipv6_prefix(url: url::Url) -> anyhow::Result<Ipv6Addr> {
-    let ip: Ipv6Addr = reqwest::get(url.clone())
-        .await
-        .with_context(|| format!("Failed to GET {url}"))?
-        .text()
-        .await?
+pub async fn command_get_ipv6_prefix(
+    command: &[String],
+    prefix_len: u8,
+) -> anyhow::Result<Ipv6Addr> {
+    let stdout: String = command_stdout(command).await?;
+    let ip: Ipv6Addr = stdout
         .trim()
         .parse::<Ipv6Addr>()
-        .with_context(|| format!("Unexptected data from {url}"))?;
-    Ok(ip & PREFIX_MASK)
+        .with_context(|| format!("Unexpected output from '{}'", command.join(" ")))?;
+    Ok(ip & prefix_mask(prefix_len))
+}
+
+/// Name that OpenDNS resolvers answer with the address of the querier
+const OPENDNS_MYIP: &str = "myip.opendns.com.";
+
+/// Resolver for `verify_via_dns`
+pub const DEFAULT_VERIFY_RESOLVER: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53);
+
+/// DNS resolver querying only `server`
+fn dns_resolver(server: SocketAddr) -> anyhow::Result<Resolver<TokioRuntimeProvider>> {
+    let mut name_server: NameServerConfig = NameServerConfig::udp_and_tcp(server.ip());
+    for connection in &mut name_server.connections {
+        connection.port = server.port();
+    }
+
+    Resolver::builder_with_config(
+        ResolverConfig::from_name_servers(vec![name_server]),
+        TokioRuntimeProvider::default(),
+    )
+    .build()
+    .context("Failed to create DNS resolver")
+}
+
+pub async fn dns_get_ipv4(resolver: IpAddr) -> anyhow::Result<Ipv4Addr> {
+    let dns: Resolver<TokioRuntimeProvider> = dns_resolver(SocketAddr::new(resolver, 53))?;
+
+    let lookup = dns
+        .ipv4_lookup(OPENDNS_MYIP)
+        .await
+        .with_context(|| format!("Failed to resolve {OPENDNS_MYIP} with {resolver}"))?;
+
+    lookup
+        .answers()
+        .iter()
+        .find_map(|record| match record.data {
+            RData::A(a) => Some(a.0),
+            _ => None,
+        })
+        .with_context(|| format!("Resolver {resolver} returned no A record for {OPENDNS_MYIP}"))
+}
+
+/// A records of `name` resolved through `server`
+pub async fn dns_lookup_ipv4(server: SocketAddr, name: &str) -> anyhow::Result<Vec<Ipv4Addr>> {
+    let lookup = dns_resolver(server)?
+        .ipv4_lookup(format!("{name}."))
+        .await
+        .with_context(|| format!("Failed to resolve {name} A with {server}"))?;
+
+    Ok(lookup
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data {
+            RData::A(a) => Some(a.0),
+            _ => None,
+        })
+        .collect())
+}
+
+/// AAAA records of `name` resolved through `server`
+pub async fn dns_lookup_ipv6(server: SocketAddr, name: &str) -> anyhow::Result<Vec<Ipv6Addr>> {
+    let lookup = dns_resolver(server)?
+        .ipv6_lookup(format!("{name}."))
+        .await
+        .with_context(|| format!("Failed to resolve {name} AAAA with {server}"))?;
+
+    Ok(lookup
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data {
+            RData::AAAA(aaaa) => Some(aaaa.0),
+            _ => None,
+        })
+        .collect())
+}
+
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_SUCCESS: u16 = 0x0101;
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const STUN_ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const STUN_ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const STUN_FAMILY_IPV4: u8 = 0x01;
+const STUN_HEADER_LEN: usize = 20;
+const STUN_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn stun_binding_request(transaction_id: &[u8; 12]) -> [u8; STUN_HEADER_LEN] {
+    let mut request: [u8; STUN_HEADER_LEN] = [0; STUN_HEADER_LEN];
+    request[0..2].copy_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+    // message length is zero, no attributes
+    request[4..8].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    request[8..20].copy_from_slice(transaction_id);
+    request
+}
+
+fn parse_stun_response(response: &[u8], transaction_id: &[u8; 12]) -> anyhow::Result<Ipv4Addr> {
+    anyhow::ensure!(response.len() >= STUN_HEADER_LEN, "STUN response too short");
+
+    let message_type: u16 = u16::from_be_bytes([response[0], response[1]]);
+    anyhow::ensure!(
+        message_type == STUN_BINDING_SUCCESS,
+        "Unexpected STUN message type {message_type:#06x}"
+    );
+    anyhow::ensure!(
+        response[4..8] == STUN_MAGIC_COOKIE.to_be_bytes(),
+        "STUN response has an invalid magic cookie"
+    );
+    anyhow::ensure!(
+        &response[8..20] == transaction_id,
+        "STUN response transaction ID mismatch"
+    );
+
+    let length: usize = u16::from_be_bytes([response[2], response[3]]).into();
+    let mut attributes: &[u8] = response
+        .get(STUN_HEADER_LEN..STUN_HEADER_LEN.saturating_add(length))
+        .context("STUN response truncated")?;
+
+    let mut mapped_address: Option<Ipv4Addr> = None;
+
+    while attributes.len() >= 4 {
+        let attr_type: u16 = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let attr_len: usize = u16::from_be_bytes([attributes[2], attributes[3]]).into();
+        let value: &[u8] = attributes
+            .get(4..4_usize.saturating_add(attr_len))
+            .context("STUN attribute truncated")?;
+
+        if value.len() == 8 && value[1] == STUN_FAMILY_IPV4 {
+            let addr: [u8; 4] = [value[4], value[5], value[6], value[7]];
+            match attr_type {
+                STUN_ATTR_XOR_MAPPED_ADDRESS => {
+                    let xored: u32 = u32::from_be_bytes(addr) ^ STUN_MAGIC_COOKIE;
+                    return Ok(Ipv4Addr::from(xored));
+                }
+                STUN_ATTR_MAPPED_ADDRESS => mapped_address = Some(Ipv4Addr::from(addr)),
+                _ => (),
+            }
+        }
+
+        // attributes are padded to a multiple of 4 bytes
+        let padded_len: usize = attr_len.div_ceil(4).saturating_mul(4);
+        attributes = attributes
+            .get(4_usize.saturating_add(padded_len)..)
+            .unwrap_or_default();
+    }
+
+    mapped_address.context("STUN response does not contain an IPv4 mapped address")
+}
+
+pub async fn stun_get_ipv4(server: String) -> anyhow::Result<Ipv4Addr> {
+    let server_addr: SocketAddr = tokio::net::lookup_host(&server)
+        .await
+        .with_context(|| format!("Failed to resolve STUN server {server}"))?
+        .find(SocketAddr::is_ipv4)
+        .with_context(|| format!("STUN server {server} does not have an IPv4 address"))?;
+
+    let socket: UdpSocket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .context("Failed to bind UDP socket")?;
+    socket
+        .connect(server_addr)
+        .await
+        .with_context(|| format!("Failed to connect to STUN server {server}"))?;
+
+    let transaction_id: [u8; 12] = std::array::from_fn(|_| fastrand::u8(..));
+    socket
+        .send(&stun_binding_request(&transaction_id))
+        .await
+        .with_context(|| format!("Failed to send STUN request to {server}"))?;
+
+    let mut buf: [u8; 512] = [0; 512];
+    let len: usize = tokio::time::timeout(STUN_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .with_context(|| format!("Timeout waiting for STUN response from {server}"))?
+        .with_context(|| format!("Failed to receive STUN response from {server}"))?;
+
+    parse_stun_response(&buf[..len], &transaction_id)
+        .with_context(|| format!("Invalid STUN response from {server}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSACTION_ID: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    fn stun_response(attributes: &[u8]) -> Vec<u8> {
+        let mut response: Vec<u8> = Vec::new();
+        response.extend_from_slice(&STUN_BINDING_SUCCESS.to_be_bytes());
+        response.extend_from_slice(&u16::try_from(attributes.len()).unwrap().to_be_bytes());
+        response.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(&TRANSACTION_ID);
+        response.extend_from_slice(attributes);
+        response
+    }
+
+    #[test]
+    fn stun_xor_mapped_address() {
+        // 192.0.2.1:32853 from RFC 5769 section 2.2
+        let response: Vec<u8> = stun_response(&[
+            0x80, 0x22, 0x00, 0x03, 0x74, 0x65, 0x73, 0x00, // SOFTWARE, padded
+            0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xA1, 0x47, 0xE1, 0x12, 0xA6, 0x43,
+        ]);
+
+        assert_eq!(
+            parse_stun_response(&response, &TRANSACTION_ID).unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+    }
+
+    #[test]
+    fn stun_mapped_address() {
+        let response: Vec<u8> =
+            stun_response(&[0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x80, 0x55, 192, 0, 2, 1]);
+
+        assert_eq!(
+            parse_stun_response(&response, &TRANSACTION_ID).unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+    }
+
+    #[test]
+    fn stun_transaction_id_mismatch() {
+        let response: Vec<u8> = stun_response(&[]);
+
+        assert!(parse_stun_response(&response, &[0; 12]).is_err());
+    }
+
+    #[tokio::test]
+    async fn http_fallback() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let failing: MockServer = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&failing)
+            .await;
+
+        let working: MockServer = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("2001:db8:1:2::3\n"))
+            .expect(1)
+            .mount(&working)
+            .await;
+
+        let client: reqwest::Client =
+            http_client(DEFAULT_HTTP_TIMEOUT, None, None, DEFAULT_USER_AGENT).unwrap();
+        let urls: Vec<url::Url> = vec![
+            failing.uri().parse().unwrap(),
+            working.uri().parse().unwrap(),
+        ];
+
+        assert_eq!(
+            http_get_ipv6_prefix_multi(&client, &urls, DEFAULT_HTTP_JSON_FIELD, 64)
+                .await
+                .unwrap(),
+            "2001:db8:1:2::".parse::<Ipv6Addr>().unwrap()
+        );
+
+        assert!(
+            http_get_ipv4_multi(&client, &urls[..1], DEFAULT_HTTP_JSON_FIELD)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn http_ipv6_full_address() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("2001:db8:1:2:a:b:c:d\n"))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client: reqwest::Client =
+            http_client(DEFAULT_HTTP_TIMEOUT, None, None, DEFAULT_USER_AGENT).unwrap();
+        let urls: Vec<url::Url> = vec![server.uri().parse().unwrap()];
+
+        assert_eq!(
+            http_get_ipv6_prefix_multi(&client, &urls, DEFAULT_HTTP_JSON_FIELD, 64)
+                .await
+                .unwrap(),
+            "2001:db8:1:2::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            http_get_ipv6_address_multi(&client, &urls, DEFAULT_HTTP_JSON_FIELD)
+                .await
+                .unwrap(),
+            "2001:db8:1:2:a:b:c:d".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn http_timeout() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("192.0.2.1")
+                    .set_delay(Duration::from_secs(10)),
+            )
+            .mount(&server)
+            .await;
+
+        let client: reqwest::Client =
+            http_client(Duration::from_millis(100), None, None, DEFAULT_USER_AGENT).unwrap();
+        let url: url::Url = server.uri().parse().unwrap();
+
+        let start = std::time::Instant::now();
+        let err: anyhow::Error = http_get_ipv4(&client, url, DEFAULT_HTTP_JSON_FIELD)
+            .await
+            .unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            err.chain()
+                .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+                .any(reqwest::Error::is_timeout),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn http_source_interface() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url: url::Url = server.uri().parse().unwrap();
+
+        // the mock server listens on loopback
+        let client: reqwest::Client =
+            http_client(DEFAULT_HTTP_TIMEOUT, Some("lo"), None, DEFAULT_USER_AGENT).unwrap();
+        assert_eq!(
+            http_get_ipv4(&client, url.clone(), DEFAULT_HTTP_JSON_FIELD)
+                .await
+                .unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+
+        let client: reqwest::Client = http_client(
+            DEFAULT_HTTP_TIMEOUT,
+            Some("cfddns-none0"),
+            None,
+            DEFAULT_USER_AGENT,
+        )
+        .unwrap();
+        http_get_ipv4(&client, url, DEFAULT_HTTP_JSON_FIELD)
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn http_user_agent() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::header(
+                "user-agent",
+                format!("cfddns/{}", env!("CARGO_PKG_VERSION")),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.1"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::header("user-agent", "custom/1.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.2"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url: url::Url = server.uri().parse().unwrap();
+
+        let client: reqwest::Client =
+            http_client(DEFAULT_HTTP_TIMEOUT, None, None, DEFAULT_USER_AGENT).unwrap();
+        assert_eq!(
+            http_get_ipv4(&client, url.clone(), DEFAULT_HTTP_JSON_FIELD)
+                .await
+                .unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+
+        let client: reqwest::Client =
+            http_client(DEFAULT_HTTP_TIMEOUT, None, None, "custom/1.0").unwrap();
+        assert_eq!(
+            http_get_ipv4(&client, url, DEFAULT_HTTP_JSON_FIELD)
+                .await
+                .unwrap(),
+            Ipv4Addr::new(192, 0, 2, 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn http_proxy() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let proxy: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::header("host", "ip.example.invalid"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.1"))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let proxy_url: url::Url = proxy.uri().parse().unwrap();
+        let client: reqwest::Client = http_client(
+            DEFAULT_HTTP_TIMEOUT,
+            None,
+            Some(&proxy_url),
+            DEFAULT_USER_AGENT,
+        )
+        .unwrap();
+
+        assert_eq!(
+            http_get_ipv4(
+                &client,
+                "http://ip.example.invalid/".parse().unwrap(),
+                DEFAULT_HTTP_JSON_FIELD
+            )
+            .await
+            .unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn stun_server() {
+        let server: UdpSocket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_addr: SocketAddr = server.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf: [u8; 512] = [0; 512];
+            let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, STUN_HEADER_LEN);
+
+            let mut response: Vec<u8> = buf[..STUN_HEADER_LEN].to_vec();
+            response[0..2].copy_from_slice(&STUN_BINDING_SUCCESS.to_be_bytes());
+            response[2..4].copy_from_slice(&12_u16.to_be_bytes());
+            response.extend_from_slice(&[
+                0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x80, 0x55, 203, 0, 113, 7,
+            ]);
+            server.send_to(&response, peer).await.unwrap();
+        });
+
+        assert_eq!(
+            stun_get_ipv4(server_addr.to_string()).await.unwrap(),
+            Ipv4Addr::new(203, 0, 113, 7)
+        );
+    }
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn command_ipv4() {
+        assert_eq!(
+            command_get_ipv4(&command(&["echo", "1.2.3.4"]))
+                .await
+                .unwrap(),
+            Ipv4Addr::new(1, 2, 3, 4)
+        );
+    }
+
+    #[tokio::test]
+    async fn command_ipv6_prefix() {
+        assert_eq!(
+            command_get_ipv6_prefix(&command(&["echo", "2001:db8:1:2:3:4:5:6"]), 64)
+                .await
+                .unwrap(),
+            Ipv6Addr::new(0x2001, 0xDB8, 1, 2, 0, 0, 0, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn command_failure() {
+        let err = command_get_ipv4(&command(&["sh", "-c", "echo oops >&2; exit 3"]))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Command 'sh -c echo oops >&2; exit 3' failed with exit status: 3: oops"
+        );
+    }
+
+    #[tokio::test]
+    async fn command_unparseable() {
+        let err = command_get_ipv4(&command(&["echo", "not an ip"]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected output from 'echo not an ip'");
+    }
+
+    #[test]
+    fn http_body_plain() {
+        assert_eq!(
+            parse_http_body::<Ipv4Addr>("1.2.3.4\n", false, DEFAULT_HTTP_JSON_FIELD).unwrap(),
+            Ipv4Addr::new(1, 2, 3, 4)
+        );
+    }
+
+    #[test]
+    fn http_body_json() {
+        assert_eq!(
+            parse_http_body::<Ipv4Addr>(r#"{"ip":"1.2.3.4"}"#, false, DEFAULT_HTTP_JSON_FIELD)
+                .unwrap(),
+            Ipv4Addr::new(1, 2, 3, 4)
+        );
+        assert_eq!(
+            parse_http_body::<Ipv6Addr>(r#" {"address": "2001:db8::1"}"#, true, "address").unwrap(),
+            Ipv6Addr::new(0x2001, 0xDB8, 0, 0, 0, 0, 0, 1)
+        );
+        assert!(parse_http_body::<Ipv4Addr>(r#"{"ip":"1.2.3.4"}"#, true, "address").is_err());
+    }
+
+    #[test]
+    fn prefix_masks() {
+        let ip: Ipv6Addr = "2001:db8:1234:5678:9abc:def0:1234:5678".parse().unwrap();
+
+        assert_eq!(
+            ip & prefix_mask(64),
+            "2001:db8:1234:5678::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            ip & prefix_mask(56),
+            "2001:db8:1234:5600::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            ip & prefix_mask(48),
+            "2001:db8:1234::".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(ip & prefix_mask(128), ip);
+        assert_eq!(prefix_mask(1), "8000::".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn if_inet6_flags() {
+        const IF_INET6: &str = "\
+00000000000000000000000000000001 01 80 10 80       lo
+20010db8000000010000000000000001 02 40 00 80     eth0
+20010db80000000112345678abcdef01 02 40 00 01     eth0
+20010db8000000020000000000000001 02 40 00 a0     eth0
+fe800000000000000000000000000001 02 40 20 80     eth0
+20010db8000000030000000000000001 03 40 00 80     eth1
+";
+
+        let flags: HashMap<Ipv6Addr, u8> = parse_if_inet6(IF_INET6, "eth0");
+        assert_eq!(flags.len(), 4);
+        assert_eq!(flags[&"2001:db8:0:1::1".parse::<Ipv6Addr>().unwrap()], 0x80);
+        assert_eq!(
+            flags[&"2001:db8:0:1:1234:5678:abcd:ef01"
+                .parse::<Ipv6Addr>()
+                .unwrap()],
+            IFA_F_TEMPORARY
+        );
+
+        assert_eq!(stability_rank(0x80), 0);
+        assert_eq!(stability_rank(0xA0), 1);
+        assert_eq!(stability_rank(0x01), 2);
+        assert_eq!(stability_rank(0x21), 2);
+    }
+
+    #[test]
+    fn sort_ipv6_deterministic() {
+        let stable: Ipv6Addr = "2001:db8:0:1::2".parse().unwrap();
+        let temporary: Ipv6Addr = "2001:db8:0:1::1".parse().unwrap();
+        let other: Ipv6Addr = "2001:db8:0:1::3".parse().unwrap();
+
+        let mut addrs: [Ipv6Addr; 3] = [other, stable, temporary];
+        sort_ipv6(&mut addrs, None);
+        assert_eq!(addrs, [temporary, stable, other]);
+
+        let mut shuffled: [Ipv6Addr; 3] = [stable, other, temporary];
+        sort_ipv6(&mut shuffled, None);
+        assert_eq!(shuffled, addrs);
+
+        let flags: HashMap<Ipv6Addr, u8> = HashMap::from([(temporary, IFA_F_TEMPORARY)]);
+        let mut addrs: [Ipv6Addr; 3] = [temporary, other, stable];
+        sort_ipv6(&mut addrs, Some(&flags));
+        assert_eq!(addrs, [stable, other, temporary]);
+
+        let mut shuffled: [Ipv6Addr; 3] = [other, temporary, stable];
+        sort_ipv6(&mut shuffled, Some(&flags));
+        assert_eq!(shuffled, addrs);
+    }
+
+    #[test]
+    fn eui64() {
+        assert_eq!(
+            mac_to_eui64([0x00, 0x25, 0x96, 0x12, 0x34, 0x56]),
+            0x0225_96FF_FE12_3456
+        );
+        assert_eq!(
+            mac_to_eui64([0x52, 0x54, 0x00, 0xAB, 0xCD, 0xEF]),
+            0x5054_00FF_FEAB_CDEF
+        );
+        assert_eq!(
+            mac_to_eui64([0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+            0xFDFF_FFFF_FEFF_FFFF
+        );
+    }
+
+    #[test]
+    fn interface_fallback() {
+        let ifaces: Vec<String> = vec!["eth0".to_string(), "wwan0".to_string()];
+        let lookup = |iface: &str| -> anyhow::Result<Ipv4Addr> {
+            match iface {
+                "wwan0" => Ok(Ipv4Addr::new(192, 0, 2, 1)),
+                _ => anyhow::bail!("Interface '{iface}' does not have a global IPv4 address"),
+            }
+        };
+
+        assert_eq!(
+            first_interface(&ifaces, lookup).unwrap(),
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+        assert!(first_interface(&ifaces[..1], lookup).is_err());
+        assert!(first_interface(&[], lookup).is_err());
+    }
+
+    #[test]
+    fn interface_not_found() {
+        const IFACE: &str = "cfddns-typo0";
+        let expected: String = format!("Interface '{IFACE}' not found (available: ");
+
+        let e: anyhow::Error = interface_ipv4(IFACE, &[]).unwrap_err();
+        assert!(e.to_string().starts_with(&expected), "{e}");
+        let e: anyhow::Error = interface_ipv6_prefix(IFACE, 64, false, &[]).unwrap_err();
+        assert!(e.to_string().starts_with(&expected), "{e}");
+    }
+
+    #[test]
+    fn select_lowest_ipv4() {
+        let addrs: [Ipv4Addr; 3] = [
+            Ipv4Addr::new(203, 0, 114, 7),
+            Ipv4Addr::new(8, 8, 8, 8),
+            Ipv4Addr::new(198, 51, 101, 1),
+        ];
+        assert_eq!(
+            select_ipv4("eth0", &addrs).unwrap(),
+            Ipv4Addr::new(8, 8, 8, 8)
+        );
+
+        let mut reversed: [Ipv4Addr; 3] = addrs;
+        reversed.reverse();
+        assert_eq!(
+            select_ipv4("eth0", &reversed).unwrap(),
+            Ipv4Addr::new(8, 8, 8, 8)
+        );
+
+        assert!(select_ipv4("eth0", &[]).is_err());
+    }
+
+    #[test]
+    fn exclude_addresses() {
+        let exclude: Vec<IpNet> = vec![
+            "8.8.8.0/24".parse().unwrap(),
+            "2001:db8:1::/48".parse().unwrap(),
+        ];
+
+        let addrs: Vec<Ipv4Addr> = [Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(9, 9, 9, 9)]
+            .into_iter()
+            .filter(not_excluded(&exclude))
+            .collect();
+        assert_eq!(
+            select_ipv4("eth0", &addrs).unwrap(),
+            Ipv4Addr::new(9, 9, 9, 9)
+        );
+
+        let addrs: Vec<Ipv6Addr> = ["2001:db8:1::1", "2001:db8:2::1"]
+            .into_iter()
+            .map(|ip| ip.parse().unwrap())
+            .filter(not_excluded(&exclude))
+            .collect();
+        assert_eq!(addrs, ["2001:db8:2::1".parse::<Ipv6Addr>().unwrap()]);
+    }
+
+    #[test]
+    fn shared_v4() {
+        const SHARED: &[Ipv4Addr] = &[
+            Ipv4Addr::new(100, 64, 0, 0),
+            Ipv4Addr::new(100, 100, 1, 1),
+            Ipv4Addr::new(100, 127, 255, 255),
+        ];
+        const NOT_SHARED: &[Ipv4Addr] = &[
+            Ipv4Addr::new(100, 63, 255, 255),
+            Ipv4Addr::new(100, 128, 0, 0),
+            Ipv4Addr::new(1, 1, 1, 1),
+            Ipv4Addr::new(192, 168, 1, 1),
+        ];
+
+        for ip in SHARED {
+            assert!(is_shared_v4(ip), "{ip} should be shared");
+        }
+        for ip in NOT_SHARED {
+            assert!(!is_shared_v4(ip), "{ip} should not be shared");
+        }
+    }
+
+    #[test]
+    fn global_v4() {
+        const GLOBAL: &[Ipv4Addr] = &[
+            Ipv4Addr::new(1, 1, 1, 1),
+            Ipv4Addr::new(8, 8, 8, 8),
+            Ipv4Addr::new(100, 63, 255, 255),
+            Ipv4Addr::new(100, 128, 0, 0),
+            Ipv4Addr::new(192, 0, 0, 9),
+            Ipv4Addr::new(192, 0, 0, 10),
+            Ipv4Addr::new(198, 20, 0, 0),
+            Ipv4Addr::new(223, 255, 255, 255),
+        ];
+
+        for ip in GLOBAL {
+            assert!(is_global_v4(ip), "{ip} should be global");
+        }
+    }
+
+    #[test]
+    fn not_global_v4() {
+        const NOT_GLOBAL: &[Ipv4Addr] = &[
+            // this network
+            Ipv4Addr::new(0, 1, 2, 3),
+            // private
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(172, 16, 0, 1),
+            Ipv4Addr::new(192, 168, 1, 1),
+            // shared (CGNAT)
+            Ipv4Addr::new(100, 64, 0, 1),
+            Ipv4Addr::new(100, 127, 255, 255),
+            // loopback
+            Ipv4Addr::new(127, 0, 0, 1),
+            // link local
+            Ipv4Addr::new(169, 254, 1, 1),
+            // protocol assignments
+            Ipv4Addr::new(192, 0, 0, 1),
+            // documentation
+            Ipv4Addr::new(192, 0, 2, 1),
+            Ipv4Addr::new(198, 51, 100, 1),
+            Ipv4Addr::new(203, 0, 113, 1),
+            // benchmarking
+            Ipv4Addr::new(198, 18, 0, 1),
+            Ipv4Addr::new(198, 19, 255, 255),
+            // reserved
+            Ipv4Addr::new(240, 0, 0, 1),
+            // broadcast
+            Ipv4Addr::BROADCAST,
+        ];
+
+        for ip in NOT_GLOBAL {
+            assert!(!is_global_v4(ip), "{ip} should not be global");
+        }
+    }
+
+    #[test]
+    fn unicast_global_v6() {
+        const GLOBAL: &[Ipv6Addr] = &[
+            Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111),
+            Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888),
+            Ipv6Addr::new(0x2001, 0x2, 0x1, 0, 0, 0, 0, 0x1),
+        ];
+
+        for ip in GLOBAL {
+            assert!(is_unicast_global_v6(ip), "{ip} should be unicast global");
+        }
+    }
+
+    #[test]
+    fn ipv6_candidates_translated() {
+        let global: Ipv6Addr = Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111);
+        let candidates: [Ipv6Addr; 5] = [
+            // NAT64 well-known prefix embedding 192.0.2.1
+            Ipv6Addr::new(0x64, 0xFF9B, 0, 0, 0, 0, 0xC000, 0x201),
+            // NAT64 local-use prefix
+            Ipv6Addr::new(0x64, 0xFF9B, 0x1, 0, 0, 0, 0xC000, 0x201),
+            global,
+            // IPv4-mapped
+            Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped(),
+            global,
+        ];
+
+        assert_eq!(ipv6_candidates(candidates.into_iter(), &[]), [global]);
+        assert!(!is_translated_v6(&global));
+        // only the /96 is the well-known prefix
+        assert!(!is_translated_v6(&Ipv6Addr::new(
+            0x64, 0xFF9B, 0, 1, 0, 0, 0, 1
+        )));
+    }
+
+    #[test]
+    fn not_unicast_global_v6() {
+        const NOT_GLOBAL: &[Ipv6Addr] = &[
+            Ipv6Addr::UNSPECIFIED,
+            Ipv6Addr::LOCALHOST,
+            // unique local
+            Ipv6Addr::new(0xFD00, 0, 0, 0, 0, 0, 0, 1),
+            // link local
+            Ipv6Addr::new(0xFE80, 0, 0, 0, 0, 0, 0, 1),
+            // multicast
+            Ipv6Addr::new(0xFF02, 0, 0, 0, 0, 0, 0, 1),
+            // documentation
+            Ipv6Addr::new(0x2001, 0xDB8, 0, 0, 0, 0, 0, 1),
+            // benchmarking
+            Ipv6Addr::new(0x2001, 0x2, 0, 0, 0, 0, 0, 1),
+        ];
+
+        for ip in NOT_GLOBAL {
+            assert!(
+                !is_unicast_global_v6(ip),
+                "{ip} should not be unicast global"
+            );
+        }
+    }
 }
@@ -0,0 +1,2720 @@
+//! Cloudflare dynamic DNS updater
+//!
+//! The `cfddns` binary is a thin wrapper around [`update`].
+
+#![forbid(unsafe_code)]
+
+pub mod cloudflare;
+pub mod config;
+pub mod ip;
+pub mod logger;
+pub mod metrics;
+pub mod notify;
+pub mod report;
+
+use crate::{
+    cloudflare::{
+        ApiError, ApiMessage, BatchDnsRecordsParams, BatchPut, Client, CreateDnsRecordParams,
+        DnsContent, ListDnsRecordsParams, ListZonesParams, OrderDirection, SearchMatch, Status,
+        UpdateDnsRecordParams,
+    },
+    config::{
+        Clients, Config, DetectMode, History, RecordConfig, RecordIds, RecordType, ZoneConfig,
+        normalize_name, save_history,
+    },
+    ip::{
+        command_get_ipv4, command_get_ipv6_prefix, dns_get_ipv4, dns_lookup_ipv4, dns_lookup_ipv6,
+        http_get_ipv4, http_get_ipv4_multi, http_get_ipv6_address_multi, http_get_ipv6_prefix,
+        http_get_ipv6_prefix_multi, interface_ipv4, interface_ipv4_multi, interface_ipv6_address,
+        interface_ipv6_prefix, prefix_mask, stun_get_ipv4,
+    },
+};
+use anyhow::Context as _;
+use futures::future::{FutureExt as _, LocalBoxFuture};
+use notify::IpChange;
+use report::{RecordAction, RecordReport, RunReport, ZoneReport};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Zone name as the API expects it, without a trailing dot and in punycode
+fn zone_lookup_name(name: &str) -> anyhow::Result<String> {
+    idna::domain_to_ascii(name.trim_end_matches('.'))
+        .with_context(|| format!("Invalid zone name {name}"))
+}
+
+async fn zone_id(name: &str, api_client: &Client) -> anyhow::Result<String> {
+    let params = ListZonesParams {
+        name: Some(zone_lookup_name(name)?),
+        status: Some(Status::Active),
+        search_match: Some(SearchMatch::All),
+        ..Default::default()
+    };
+
+    let response = api_client
+        .list_zones(&params)
+        .await
+        .context("Failed to list zones")?;
+
+    if response.result.len() > 1 {
+        anyhow::bail!("Multiple zones matching name {name}");
+    }
+
+    let id: String = response
+        .result
+        .first()
+        .with_context(|| format!("No zones matching name {name}"))?
+        .id
+        .clone();
+
+    Ok(id)
+}
+
+pub async fn zone_identifier(config: &ZoneConfig, api_client: &Client) -> anyhow::Result<String> {
+    match (&config.id, &config.name) {
+        (Some(id), _) => Ok(id.clone()),
+        (None, Some(name)) => zone_id(name, api_client)
+            .await
+            .with_context(|| format!("Failed to get zone identifer from zone name '{name}'")),
+        (None, None) => anyhow::bail!("Zone has neither a name nor an id"),
+    }
+}
+
+/// Addresses to update records with, None if unchanged or disabled
+#[derive(Clone, Copy, Default)]
+pub struct Addresses {
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6_prefix: Option<Ipv6Addr>,
+    pub ipv6_address: Option<Ipv6Addr>,
+}
+
+impl Addresses {
+    fn is_empty(&self) -> bool {
+        self.ipv4.is_none() && self.ipv6_prefix.is_none() && self.ipv6_address.is_none()
+    }
+
+    fn a_content(&self, record_config: &RecordConfig) -> Option<Ipv4Addr> {
+        record_config.a_content(self.ipv4)
+    }
+
+    fn aaaa_content(&self, record_config: &RecordConfig) -> Option<Ipv6Addr> {
+        record_config.aaaa_content(self.ipv6_prefix, self.ipv6_address)
+    }
+}
+
+/// Returns the current prefix if its leading `significant_len` bits differ
+/// from the previous prefix
+///
+/// Compares the whole prefix if `significant_len` is None.
+fn prefix_changed(
+    current: Option<Ipv6Addr>,
+    previous: Option<Ipv6Addr>,
+    significant_len: Option<u8>,
+    unchanged_level: log::Level,
+) -> Option<Ipv6Addr> {
+    if let (Some(ip), Some(prev), Some(significant_len)) = (current, previous, significant_len)
+        && ip != prev
+        && ip & prefix_mask(significant_len) == prev & prefix_mask(significant_len)
+    {
+        log::log!(
+            unchanged_level,
+            "IPv6 prefix changed from {prev} to {ip} below /{significant_len}, skipping update"
+        );
+        return None;
+    }
+
+    changed("IPv6 prefix", current, previous, unchanged_level)
+}
+
+/// Returns the current address if it differs from the previous address
+///
+/// An unchanged address is logged at `unchanged_level`.
+fn changed<T: Copy + PartialEq + fmt::Display>(
+    name: &str,
+    current: Option<T>,
+    previous: Option<T>,
+    unchanged_level: log::Level,
+) -> Option<T> {
+    match (current, previous) {
+        (None, _) => None,
+        (Some(ip), None) => {
+            log::warn!("Previous {name} unknown, updating to {ip}");
+            Some(ip)
+        }
+        (Some(ip), Some(prev)) => {
+            if ip == prev {
+                log::log!(unchanged_level, "{name} unchanged, skipping update");
+                None
+            } else {
+                log::warn!("{name} changed from {prev} to {ip}");
+                Some(ip)
+            }
+        }
+    }
+}
+
+pub struct ExistingRecord {
+    pub id: String,
+    /// Current record content, None if the identifier came from history
+    pub content: Option<DnsContent>,
+    /// Current proxied state, None if unknown
+    pub proxied: Option<bool>,
+}
+
+/// Existing A, AAAA, and CNAME records by name
+///
+/// A name may have multiple records of the same type, such as round-robin
+/// A records.
+pub struct RecordMaps {
+    pub a: HashMap<String, Vec<ExistingRecord>>,
+    pub aaaa: HashMap<String, Vec<ExistingRecord>>,
+    pub cname: HashMap<String, Vec<ExistingRecord>>,
+}
+
+impl RecordMaps {
+    fn from_cache(ids: &RecordIds) -> RecordMaps {
+        let existing =
+            |ids: &HashMap<String, Vec<String>>| -> HashMap<String, Vec<ExistingRecord>> {
+                ids.iter()
+                    .map(|(name, ids)| {
+                        let records: Vec<ExistingRecord> = ids
+                            .iter()
+                            .map(|id| ExistingRecord {
+                                id: id.clone(),
+                                content: None,
+                                proxied: None,
+                            })
+                            .collect();
+                        (name.clone(), records)
+                    })
+                    .collect()
+            };
+
+        RecordMaps {
+            a: existing(&ids.a),
+            aaaa: existing(&ids.aaaa),
+            cname: existing(&ids.cname),
+        }
+    }
+
+    /// Returns true if every record that will be updated has an identifier
+    fn covers(&self, config: &ZoneConfig, addresses: Addresses) -> bool {
+        config.records.iter().all(|record_config| {
+            let name: &str = record_config.name.as_str();
+            if record_config.target.is_some() {
+                return self.cname.contains_key(name);
+            }
+            (addresses.a_content(record_config).is_none() || self.a.contains_key(name))
+                && (addresses.aaaa_content(record_config).is_none() || self.aaaa.contains_key(name))
+        })
+    }
+
+    /// Returns true if a record without a configured type exists only with the
+    /// other address family, in which case `record_type` is not managed
+    ///
+    /// Records with `create_missing` always manage both families.
+    fn other_family_only(&self, record_config: &RecordConfig, record_type: RecordType) -> bool {
+        let name: &str = record_config.name.as_str();
+        if record_config.record_type.is_some() || record_config.create_missing.unwrap_or(false) {
+            return false;
+        }
+        match record_type {
+            RecordType::A => self.a(name).is_empty() && !self.aaaa(name).is_empty(),
+            RecordType::AAAA => self.aaaa(name).is_empty() && !self.a(name).is_empty(),
+            RecordType::CNAME => false,
+        }
+    }
+
+    fn a(&self, name: &str) -> &[ExistingRecord] {
+        self.a.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn aaaa(&self, name: &str) -> &[ExistingRecord] {
+        self.aaaa.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn cname(&self, name: &str) -> &[ExistingRecord] {
+        self.cname.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Identifiers of the configured records
+    fn ids(&self, config: &ZoneConfig) -> RecordIds {
+        let configured =
+            |map: &HashMap<String, Vec<ExistingRecord>>| -> HashMap<String, Vec<String>> {
+                config
+                    .records
+                    .iter()
+                    .filter_map(|record_config| {
+                        map.get(&record_config.name).map(|records| {
+                            let ids: Vec<String> =
+                                records.iter().map(|record| record.id.clone()).collect();
+                            (record_config.name.clone(), ids)
+                        })
+                    })
+                    .collect()
+            };
+
+        RecordIds {
+            a: configured(&self.a),
+            aaaa: configured(&self.aaaa),
+            cname: configured(&self.cname),
+        }
+    }
+}
+
+pub async fn zone_record_map(
+    zone_identifier: &str,
+    api_client: &Client,
+) -> anyhow::Result<RecordMaps> {
+    let mut a_record_map: HashMap<String, Vec<ExistingRecord>> = HashMap::new();
+    let mut aaaa_record_map: HashMap<String, Vec<ExistingRecord>> = HashMap::new();
+    let mut cname_record_map: HashMap<String, Vec<ExistingRecord>> = HashMap::new();
+
+    let mut page: u32 = 1;
+    loop {
+        let params = ListDnsRecordsParams {
+            direction: Some(OrderDirection::Asc),
+            page: Some(page),
+            ..Default::default()
+        };
+
+        let response = api_client
+            .list_dns_records(zone_identifier, &params)
+            .await
+            .context("Failed to list existing DNS records")?;
+
+        for record in response.result {
+            let map: &mut HashMap<String, Vec<ExistingRecord>> = match record.content {
+                DnsContent::A { content: _ } => &mut a_record_map,
+                DnsContent::AAAA { content: _ } => &mut aaaa_record_map,
+                DnsContent::CNAME { content: _ } => &mut cname_record_map,
+                _ => continue,
+            };
+
+            let records: &mut Vec<ExistingRecord> =
+                map.entry(normalize_name(&record.name)).or_default();
+
+            // records can shift between pages while listing
+            if records.iter().any(|existing| existing.id == record.id) {
+                continue;
+            }
+
+            records.push(ExistingRecord {
+                id: record.id,
+                content: Some(record.content),
+                proxied: record.proxied,
+            });
+        }
+
+        if let Some(info) = response.result_info {
+            if info.total_pages == page {
+                break;
+            }
+
+            page = page.checked_add(1).context("Page number wrapped")?;
+        } else {
+            break;
+        }
+    }
+
+    for (record_type, map) in [
+        ("A", &a_record_map),
+        ("AAAA", &aaaa_record_map),
+        ("CNAME", &cname_record_map),
+    ] {
+        for (name, records) in map.iter().filter(|(_, records)| records.len() > 1) {
+            log::warn!(
+                "Found {} {record_type} records named {name}, all of them will be updated",
+                records.len()
+            );
+        }
+    }
+
+    Ok(RecordMaps {
+        a: a_record_map,
+        aaaa: aaaa_record_map,
+        cname: cname_record_map,
+    })
+}
+
+enum RecordChange<'a> {
+    Update {
+        record_id: String,
+        previous: Option<DnsContent>,
+        params: UpdateDnsRecordParams<'a>,
+    },
+    Create {
+        params: CreateDnsRecordParams<'a>,
+    },
+}
+
+impl RecordChange<'_> {
+    /// Apply the change, returning the record identifier
+    async fn apply(&self, api_client: &Client, zone_identifier: &str) -> Result<String, ApiError> {
+        match self {
+            RecordChange::Update {
+                record_id, params, ..
+            } => {
+                api_client
+                    .update_dns_record(zone_identifier, record_id, params)
+                    .await?;
+                Ok(record_id.clone())
+            }
+            RecordChange::Create { params } => {
+                let response = api_client
+                    .create_dns_record(zone_identifier, params)
+                    .await?;
+                log::info!("Created {} with id {}", params.name, response.result.id);
+                Ok(response.result.id)
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            RecordChange::Update { params, .. } => params.name,
+            RecordChange::Create { params } => params.name,
+        }
+    }
+
+    fn content(&self) -> &DnsContent {
+        match self {
+            RecordChange::Update { params, .. } => &params.content,
+            RecordChange::Create { params } => &params.content,
+        }
+    }
+
+    fn report(&self) -> RecordReport {
+        match self {
+            RecordChange::Update {
+                previous, params, ..
+            } => RecordReport {
+                name: params.name.to_string(),
+                action: RecordAction::Update,
+                previous: previous.clone(),
+                content: params.content.clone(),
+            },
+            RecordChange::Create { params } => RecordReport {
+                name: params.name.to_string(),
+                action: RecordAction::Create,
+                previous: None,
+                content: params.content.clone(),
+            },
+        }
+    }
+
+    fn log_dry_run(&self) {
+        match self {
+            RecordChange::Update {
+                previous: Some(previous),
+                params,
+                ..
+            } => log::info!(
+                "Dry run: update {} from {previous} to {}",
+                params.name,
+                params.content
+            ),
+            RecordChange::Update {
+                previous: None,
+                params,
+                ..
+            } => log::info!("Dry run: update {} to {}", params.name, params.content),
+            RecordChange::Create { params } => {
+                log::info!("Dry run: create {} with {}", params.name, params.content)
+            }
+        }
+    }
+}
+
+/// Zone and record identifiers resolved while updating a zone
+pub struct ZoneIds {
+    pub zone: String,
+    pub records: RecordIds,
+    /// Records created or updated by this run
+    pub changes: Vec<RecordReport>,
+}
+
+/// Cached identifiers were rejected by the API
+#[derive(Debug)]
+struct StaleCache;
+
+impl std::error::Error for StaleCache {}
+
+impl fmt::Display for StaleCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cached identifiers do not exist")
+    }
+}
+
+/// Cloudflare error code for a record identifier that does not exist
+const RECORD_NOT_FOUND: u32 = 81044;
+
+/// Returns true if the request referenced a zone or record that does not exist
+fn is_not_found(e: &ApiError) -> bool {
+    matches!(
+        e,
+        ApiError::Http(status, errors)
+            if *status == reqwest::StatusCode::NOT_FOUND
+                || errors.iter().any(|err| err.code == RECORD_NOT_FOUND)
+    )
+}
+
+/// Index of the batch operation an error refers to
+///
+/// Cloudflare identifies the operation with a JSON pointer such as
+/// `/puts/1/content`, or in the message as `puts[1]`.
+fn batch_error_index(message: &ApiMessage) -> Option<usize> {
+    let pointer: Option<&str> = message
+        .other
+        .get("source")
+        .and_then(|source| source.get("pointer"))
+        .and_then(serde_json::Value::as_str);
+
+    let index: &str = match pointer {
+        Some(pointer) => pointer.strip_prefix("/puts/")?,
+        None => message.message.split_once("puts[")?.1,
+    };
+
+    index
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Errors of a failed batch request for each record of `changes`
+///
+/// Errors that do not refer to an operation are returned separately.
+fn batch_errors<'a>(
+    changes: &[&RecordChange<'_>],
+    e: &'a ApiError,
+) -> (Vec<Vec<&'a ApiMessage>>, Vec<&'a ApiMessage>) {
+    let mut per_record: Vec<Vec<&ApiMessage>> = vec![Vec::new(); changes.len()];
+    let mut other: Vec<&ApiMessage> = Vec::new();
+
+    if let ApiError::Http(_, messages) = e {
+        for message in messages {
+            match batch_error_index(message).and_then(|index| per_record.get_mut(index)) {
+                Some(errors) => errors.push(message),
+                None => other.push(message),
+            }
+        }
+    }
+
+    (per_record, other)
+}
+
+/// Returns true if the API does not support batch requests
+fn is_batch_unsupported(e: &ApiError) -> bool {
+    matches!(
+        e,
+        ApiError::Http(status, _)
+            if *status == reqwest::StatusCode::NOT_FOUND
+                || *status == reqwest::StatusCode::METHOD_NOT_ALLOWED
+    )
+}
+
+/// Apply record updates in a single batch request
+async fn apply_batch(
+    api_client: &Client,
+    zone_identifier: &str,
+    changes: &[&RecordChange<'_>],
+) -> Result<(), ApiError> {
+    let puts: Vec<BatchPut<'_>> = changes
+        .iter()
+        .filter_map(|change| match change {
+            RecordChange::Update {
+                record_id, params, ..
+            } => Some(BatchPut {
+                id: record_id,
+                params: params.clone(),
+            }),
+            RecordChange::Create { .. } => None,
+        })
+        .collect();
+
+    api_client
+        .batch_dns_records(zone_identifier, &BatchDnsRecordsParams { puts })
+        .await?;
+
+    Ok(())
+}
+
+/// Plan updating every existing record that differs from `params`, or creating
+/// the record if none exist and `create_missing` is set
+///
+/// Returns false if the record does not exist and is not created.
+fn plan_record<'a>(
+    changes: &mut Vec<RecordChange<'a>>,
+    existing: &[ExistingRecord],
+    create_missing: bool,
+    params: CreateDnsRecordParams<'a>,
+) -> bool {
+    let record_name: &str = params.name;
+
+    if existing.is_empty() {
+        if create_missing {
+            log::info!("Create {record_name} with {}", params.content);
+            changes.push(RecordChange::Create { params });
+            return true;
+        }
+
+        return false;
+    }
+
+    for record in existing {
+        let proxied_changed: bool = matches!(
+            (record.proxied, params.proxied),
+            (Some(live), Some(desired)) if live != desired
+        );
+
+        if record.content.as_ref() == Some(&params.content) && !proxied_changed {
+            log::info!(
+                "{record_name} is already {}, skipping update",
+                params.content
+            );
+            continue;
+        }
+
+        if proxied_changed {
+            log::info!(
+                "Change {record_name} proxied from {} to {}",
+                record.proxied.unwrap_or_default(),
+                params.proxied.unwrap_or_default()
+            );
+        }
+
+        log::debug!("Update {record_name} to {}", params.content);
+
+        changes.push(RecordChange::Update {
+            record_id: record.id.clone(),
+            previous: record.content.clone(),
+            params: UpdateDnsRecordParams {
+                ttl: params.ttl,
+                // an update without proxied would unproxy the record
+                proxied: params.proxied.or(record.proxied),
+                comment: params.comment,
+                name: params.name,
+                content: params.content.clone(),
+            },
+        });
+    }
+
+    true
+}
+
+/// Changes to bring a zone's records up to date
+struct Plan<'a> {
+    changes: Vec<RecordChange<'a>>,
+    /// Type and name of records that do not exist and are not created
+    missing: Vec<(&'static str, &'a str)>,
+    /// Number of records to update, including missing records
+    planned: usize,
+}
+
+fn plan_changes<'a>(
+    config: &'a ZoneConfig,
+    record_maps: &RecordMaps,
+    addresses: Addresses,
+) -> Plan<'a> {
+    let mut changes: Vec<RecordChange<'_>> = Vec::with_capacity(config.records.len());
+    let mut missing: Vec<(&'static str, &'a str)> = Vec::new();
+    let mut planned: usize = 0;
+
+    for record_config in &config.records {
+        let record_name: &str = record_config.name.as_str();
+        let create_missing: bool = record_config.create_missing.unwrap_or(false);
+        let ttl: Option<u32> = config.ttl(record_config);
+        let proxied: Option<bool> = config.proxied(record_config);
+        let comment: Option<&str> = record_config.comment.as_deref();
+
+        let params = |content: DnsContent| -> CreateDnsRecordParams<'a> {
+            CreateDnsRecordParams {
+                ttl,
+                proxied,
+                comment,
+                name: record_name,
+                content,
+            }
+        };
+
+        let mut records: Vec<(&[ExistingRecord], CreateDnsRecordParams<'a>)> = Vec::new();
+
+        if let Some(target) = &record_config.target {
+            records.push((
+                record_maps.cname(record_name),
+                params(DnsContent::CNAME {
+                    content: target.clone(),
+                }),
+            ));
+        } else {
+            if let Some(content) = addresses.a_content(record_config) {
+                if record_maps.other_family_only(record_config, RecordType::A) {
+                    log::debug!("{record_name} only has AAAA records, skipping A update");
+                } else {
+                    records.push((
+                        record_maps.a(record_name),
+                        params(DnsContent::A { content }),
+                    ));
+                }
+            }
+            if let Some(content) = addresses.aaaa_content(record_config) {
+                if record_maps.other_family_only(record_config, RecordType::AAAA) {
+                    log::debug!("{record_name} only has A records, skipping AAAA update");
+                } else {
+                    records.push((
+                        record_maps.aaaa(record_name),
+                        params(DnsContent::AAAA { content }),
+                    ));
+                }
+            }
+        }
+
+        for (existing, params) in records {
+            planned = planned.saturating_add(1);
+            let record_type: &'static str = params.content.record_type();
+            if !plan_record(&mut changes, existing, create_missing, params) {
+                missing.push((record_type, record_name));
+            }
+        }
+    }
+
+    Plan {
+        changes,
+        missing,
+        planned,
+    }
+}
+
+/// Update the records of a zone to `addresses`
+///
+/// Cached record identifiers are used when possible, unless `verify` is set,
+/// in which case the records are always listed to compare their content.
+pub async fn update_zone(
+    api_client: &Client,
+    config: &ZoneConfig,
+    cache: Option<ZoneIds>,
+    addresses: Addresses,
+    dry_run: bool,
+    verify: bool,
+) -> anyhow::Result<Option<ZoneIds>> {
+    let zone_name: &str = config.display_name();
+
+    if config.records.is_empty() {
+        log::warn!("No records for zone '{zone_name}'");
+        return Ok(None);
+    }
+
+    // only records previously managed by cfddns are pruned
+    let mut previous_records: Option<RecordIds> = cache
+        .as_ref()
+        .filter(|_| config.prune.unwrap_or(false))
+        .map(|cache| cache.records.clone());
+
+    let mut ids: ZoneIds = match cache {
+        Some(cache) => {
+            match update_zone_with(api_client, config, Some(cache), addresses, dry_run, verify)
+                .await
+            {
+                Err(e) if e.is::<StaleCache>() => {
+                    log::warn!("Cached identifiers for zone '{zone_name}' are stale, refreshing");
+                    previous_records = None;
+                    update_zone_with(api_client, config, None, addresses, dry_run, verify).await?
+                }
+                result => result?,
+            }
+        }
+        None => update_zone_with(api_client, config, None, addresses, dry_run, verify).await?,
+    };
+
+    if let Some(previous_records) = previous_records {
+        prune_records(
+            api_client,
+            config,
+            &ids.zone,
+            &previous_records,
+            &mut ids.records,
+            dry_run,
+        )
+        .await?;
+    }
+
+    Ok(Some(ids))
+}
+
+/// Delete previously managed records that are no longer configured
+///
+/// Records that fail to delete are kept in `records` to retry on the next run.
+async fn prune_records(
+    api_client: &Client,
+    config: &ZoneConfig,
+    zone_identifier: &str,
+    previous: &RecordIds,
+    records: &mut RecordIds,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let zone_name: &str = config.display_name();
+    let configured: HashSet<&str> = config
+        .records
+        .iter()
+        .map(|record_config| record_config.name.as_str())
+        .collect();
+
+    let stale: Vec<(bool, &String, &String)> = previous
+        .a
+        .iter()
+        .map(|(name, ids)| (false, name, ids))
+        .chain(previous.aaaa.iter().map(|(name, ids)| (true, name, ids)))
+        .filter(|(_, name, _)| !configured.contains(name.as_str()))
+        .flat_map(|(aaaa, name, ids)| ids.iter().map(move |id| (aaaa, name, id)))
+        .collect();
+
+    if dry_run {
+        for (aaaa, name, _) in stale {
+            let record_type: &str = if aaaa { "AAAA" } else { "A" };
+            log::info!("Dry run: delete {name} {record_type}");
+        }
+        return Ok(());
+    }
+
+    let requests: Vec<_> = stale
+        .iter()
+        .map(|(_, _, id)| api_client.delete_dns_record(zone_identifier, id))
+        .collect();
+
+    let results: Vec<_> = futures::future::join_all(requests).await;
+
+    let mut errors: u32 = 0;
+    for ((aaaa, name, id), result) in stale.into_iter().zip(results) {
+        let record_type: &str = if aaaa { "AAAA" } else { "A" };
+        match result {
+            Ok(_) => log::info!("Deleted {name} {record_type}"),
+            Err(e) if is_not_found(&e) => {
+                log::info!("{name} {record_type} was already deleted");
+            }
+            Err(e) => {
+                log::error!("Failed to delete {name} {record_type} for zone '{zone_name}': {e:?}");
+                let ids: &mut HashMap<String, Vec<String>> = if aaaa {
+                    &mut records.aaaa
+                } else {
+                    &mut records.a
+                };
+                ids.entry(name.clone()).or_default().push(id.clone());
+                errors = errors.saturating_add(1);
+            }
+        }
+    }
+
+    if errors > 0 {
+        anyhow::bail!("Failed to delete {errors} records");
+    }
+
+    Ok(())
+}
+
+async fn update_zone_with(
+    api_client: &Client,
+    config: &ZoneConfig,
+    cache: Option<ZoneIds>,
+    addresses: Addresses,
+    dry_run: bool,
+    verify: bool,
+) -> anyhow::Result<ZoneIds> {
+    let zone_name: &str = config.display_name();
+    let from_cache: bool = cache.is_some();
+
+    let (zone_identifier, cached_records): (String, Option<RecordIds>) = match cache {
+        Some(cache) => (cache.zone, Some(cache.records)),
+        None => (zone_identifier(config, api_client).await?, None),
+    };
+
+    let record_maps: RecordMaps = match cached_records
+        .map(|ids| RecordMaps::from_cache(&ids))
+        .filter(|maps| !verify && maps.covers(config, addresses))
+    {
+        Some(maps) => maps,
+        None => zone_record_map(zone_identifier.as_str(), api_client)
+            .await
+            .map_err(|e| match e.downcast_ref::<ApiError>() {
+                Some(api_error) if from_cache && is_not_found(api_error) => StaleCache.into(),
+                _ => e,
+            })
+            .with_context(|| {
+                format!("Failed to list records for zone '{zone_name}' id '{zone_identifier}'")
+            })?,
+    };
+
+    let mut record_ids: RecordIds = record_maps.ids(config);
+
+    let Plan {
+        mut changes,
+        missing,
+        planned,
+    } = plan_changes(config, &record_maps, addresses);
+
+    // one error instead of one per record, such as for a new zone
+    if !missing.is_empty() && missing.len() == planned {
+        anyhow::bail!(
+            "Zone '{zone_name}' has none of the {planned} records to update, \
+            set create_missing to create them"
+        );
+    }
+
+    for (record_type, record_name) in &missing {
+        log::error!("No {record_type} record exists for {record_name}");
+    }
+    let mut errors: u32 = u32::try_from(missing.len()).unwrap_or(u32::MAX);
+
+    if dry_run {
+        changes.iter().for_each(RecordChange::log_dry_run);
+        changes.clear();
+    }
+
+    // several updates are sent in one batch request, creates are sent individually
+    let (mut batch, mut single): (Vec<&RecordChange<'_>>, Vec<&RecordChange<'_>>) = changes
+        .iter()
+        .partition(|change| matches!(change, RecordChange::Update { .. }));
+    if batch.len() < 2 {
+        single.append(&mut batch);
+    }
+
+    let mut outcomes: Vec<(&RecordChange<'_>, Result<String, ApiError>)> =
+        Vec::with_capacity(changes.len());
+
+    if !batch.is_empty() {
+        match apply_batch(api_client, zone_identifier.as_str(), &batch).await {
+            Ok(()) => outcomes.extend(batch.into_iter().map(|change| {
+                let id: String = match change {
+                    RecordChange::Update { record_id, .. } => record_id.clone(),
+                    RecordChange::Create { .. } => unreachable!("only updates are batched"),
+                };
+                (change, Ok(id))
+            })),
+            Err(e) if is_batch_unsupported(&e) => {
+                log::warn!(
+                    "Batch DNS API unavailable for zone '{zone_name}', updating records individually"
+                );
+                single.append(&mut batch);
+            }
+            // the batch references stale identifiers, retry with fresh ones
+            Err(e) if from_cache && is_not_found(&e) => return Err(StaleCache.into()),
+            Err(e) => {
+                // batches are atomic, none of the records were updated
+                let (per_record, other) = batch_errors(&batch, &e);
+                if !other.is_empty() || per_record.iter().all(Vec::is_empty) {
+                    log::error!(
+                        "Failed to update {} records in a batch for zone '{zone_name}': {e:?}",
+                        batch.len()
+                    );
+                }
+                for (change, messages) in batch.iter().zip(per_record) {
+                    for message in messages {
+                        log::error!(
+                            "Failed to update {} {} for zone '{zone_name}': {}: {}",
+                            change.name(),
+                            change.content().record_type(),
+                            message.code,
+                            message.message
+                        );
+                    }
+                }
+                errors = errors.saturating_add(u32::try_from(batch.len()).unwrap_or(u32::MAX));
+            }
+        }
+    }
+
+    let requests: Vec<_> = single
+        .iter()
+        .map(|change| change.apply(api_client, zone_identifier.as_str()))
+        .collect();
+
+    let results: Vec<_> = futures::future::join_all(requests).await;
+    outcomes.extend(single.into_iter().zip(results));
+
+    if from_cache
+        && outcomes
+            .iter()
+            .any(|(_, result)| result.as_ref().is_err_and(is_not_found))
+    {
+        return Err(StaleCache.into());
+    }
+
+    let mut applied: Vec<RecordReport> = Vec::with_capacity(changes.len());
+
+    for (change, result) in outcomes {
+        match result {
+            Ok(id) => {
+                applied.push(change.report());
+                let ids: &mut HashMap<String, Vec<String>> = match change.content() {
+                    DnsContent::AAAA { content: _ } => &mut record_ids.aaaa,
+                    DnsContent::CNAME { content: _ } => &mut record_ids.cname,
+                    _ => &mut record_ids.a,
+                };
+                let ids: &mut Vec<String> = ids.entry(change.name().to_string()).or_default();
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to update record for zone '{zone_name}': {e:?}");
+                errors = errors.saturating_add(1);
+            }
+        }
+    }
+
+    if errors > 0 {
+        anyhow::bail!("Failed to update {errors} records");
+    }
+
+    Ok(ZoneIds {
+        zone: zone_identifier,
+        records: record_ids,
+        changes: applied,
+    })
+}
+
+pub fn cached_zone_ids(zone: &ZoneConfig, history: &History) -> Option<ZoneIds> {
+    let zone_identifier: String = match (&zone.id, &zone.name) {
+        (Some(id), _) => id.clone(),
+        (None, Some(name)) => history.zone_ids.get(name)?.clone(),
+        (None, None) => return None,
+    };
+
+    let records: RecordIds = history
+        .record_ids
+        .get(&zone_identifier)
+        .cloned()
+        .unwrap_or_default();
+
+    Some(ZoneIds {
+        zone: zone_identifier,
+        records,
+        changes: Vec::new(),
+    })
+}
+
+/// Record the results of zone updates in history
+///
+/// Zone and record IDs are cached for every successful zone. The detected
+/// addresses are only recorded once all zones updated successfully, so failed
+/// zones are retried on the next run. Addresses are not recorded if
+/// `detected` is None, and the update time is only recorded if a record was
+/// changed.
+fn record_results(
+    history: &mut History,
+    zones: &[ZoneConfig],
+    results: &[anyhow::Result<Option<ZoneIds>>],
+    detected: Option<Addresses>,
+) -> anyhow::Result<()> {
+    let mut errors: u32 = 0;
+    let mut changed_records: bool = false;
+    for (zone, result) in zones.iter().zip(results) {
+        match result {
+            Ok(Some(ids)) => {
+                if let Some(name) = &zone.name {
+                    history.zone_ids.insert(name.clone(), ids.zone.clone());
+                }
+                history
+                    .record_ids
+                    .insert(ids.zone.clone(), ids.records.clone());
+                changed_records |= !ids.changes.is_empty();
+            }
+            Ok(None) => (),
+            Err(e) => {
+                log::error!("Failed to update zone: {e:?}");
+                errors = errors.saturating_add(1);
+            }
+        }
+    }
+
+    if errors > 0 {
+        anyhow::bail!("Failed to update {errors} zones");
+    }
+
+    if let Some(detected) = detected {
+        history.ipv4 = detected.ipv4;
+        history.ipv6_prefix = detected.ipv6_prefix;
+        history.ipv6_address = detected.ipv6_address;
+        if changed_records {
+            history.last_updated = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|since_epoch| since_epoch.as_secs());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if `record` resolves to its content for `addresses` through
+/// `resolver`
+///
+/// CNAME records are not verified and never match.
+async fn resolves_to(resolver: SocketAddr, record: &RecordConfig, addresses: Addresses) -> bool {
+    if record.target.is_some() {
+        return false;
+    }
+
+    if let Some(ipv4) = record.a_content(addresses.ipv4) {
+        match dns_lookup_ipv4(resolver, &record.name).await {
+            Ok(resolved) if resolved == [ipv4] => (),
+            Ok(_) => return false,
+            Err(e) => {
+                log::debug!("{e:#}");
+                return false;
+            }
+        }
+    }
+
+    if let Some(ipv6) = record.aaaa_content(addresses.ipv6_prefix, addresses.ipv6_address) {
+        match dns_lookup_ipv6(resolver, &record.name).await {
+            Ok(resolved) if resolved == [ipv6] => (),
+            Ok(_) => return false,
+            Err(e) => {
+                log::debug!("{e:#}");
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Records of `zone` that do not resolve to their content for `addresses`
+async fn unresolved_records(
+    resolver: SocketAddr,
+    zone: &ZoneConfig,
+    addresses: Addresses,
+) -> Vec<RecordConfig> {
+    let resolved: Vec<bool> = futures::future::join_all(
+        zone.records
+            .iter()
+            .map(|record| resolves_to(resolver, record, addresses)),
+    )
+    .await;
+
+    zone.records
+        .iter()
+        .zip(resolved)
+        .filter(|(_, resolved)| !resolved)
+        .map(|(record, _)| record.clone())
+        .collect()
+}
+
+/// Update a zone, skipping records that already resolve to `addresses` if
+/// `verify_via_dns` is set
+async fn verify_and_update_zone(
+    config: &Config,
+    clients: &Clients,
+    zone: &ZoneConfig,
+    cache: Option<ZoneIds>,
+    addresses: Addresses,
+) -> anyhow::Result<Option<ZoneIds>> {
+    let zone_name: &str = zone.display_name();
+
+    if !config.verify_via_dns {
+        return update_zone(
+            clients.zone(zone)?,
+            zone,
+            cache,
+            addresses,
+            config.dry_run,
+            config.track_records,
+        )
+        .await;
+    }
+
+    let pending: Vec<RecordConfig> =
+        unresolved_records(config.verify_resolver, zone, addresses).await;
+
+    if pending.is_empty() {
+        log::info!(
+            "Records of zone '{zone_name}' already resolve to the detected addresses, \
+            skipping update"
+        );
+        return Ok(None);
+    }
+
+    if pending.len() == zone.records.len() {
+        return update_zone(
+            clients.zone(zone)?,
+            zone,
+            cache,
+            addresses,
+            config.dry_run,
+            config.track_records,
+        )
+        .await;
+    }
+
+    log::info!(
+        "{} of {} records of zone '{zone_name}' already resolve to the detected addresses",
+        zone.records.len() - pending.len(),
+        zone.records.len()
+    );
+
+    // records that are not updated must not be pruned
+    let mut pending_zone: ZoneConfig = zone.clone();
+    pending_zone.records = pending;
+    pending_zone.prune = Some(false);
+
+    let cached_records: Option<RecordIds> = cache.as_ref().map(|cache| cache.records.clone());
+
+    let mut ids: Option<ZoneIds> = update_zone(
+        clients.zone(zone)?,
+        &pending_zone,
+        cache,
+        addresses,
+        config.dry_run,
+        config.track_records,
+    )
+    .await?;
+
+    // keep the cached identifiers of the skipped records
+    if let (Some(ids), Some(cached_records)) = (&mut ids, cached_records) {
+        for (ids, cached) in [
+            (&mut ids.records.a, cached_records.a),
+            (&mut ids.records.aaaa, cached_records.aaaa),
+            (&mut ids.records.cname, cached_records.cname),
+        ] {
+            for (name, cached_ids) in cached {
+                ids.entry(name).or_insert(cached_ids);
+            }
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Remember the content pushed to each record
+///
+/// A record whose previous content differs from the content last pushed by
+/// cfddns was changed outside of cfddns, such as in the dashboard.
+fn track_changes(record_content: &mut HashMap<String, Vec<DnsContent>>, changes: &[RecordReport]) {
+    for change in changes {
+        let pushed: &mut Vec<DnsContent> = record_content.entry(change.name.clone()).or_default();
+        let last: Option<&mut DnsContent> = pushed
+            .iter_mut()
+            .find(|content| content.record_type() == change.content.record_type());
+
+        match last {
+            Some(last) => {
+                if let Some(previous) = &change.previous
+                    && previous != last
+                {
+                    log::warn!(
+                        "Record '{}' was changed outside of cfddns from {last} to {previous}, \
+                        restoring {}",
+                        change.name,
+                        change.content
+                    );
+                }
+                *last = change.content.clone();
+            }
+            None => pushed.push(change.content.clone()),
+        }
+    }
+}
+
+/// Warn, or fail if `fail` is set, when `ipv4` is behind carrier-grade NAT
+fn check_cgnat(ipv4: Ipv4Addr, fail: bool) -> anyhow::Result<()> {
+    if ip::is_shared_v4(&ipv4) {
+        if fail {
+            anyhow::bail!("Detected IPv4 {ipv4} is in the CGNAT range 100.64.0.0/10");
+        }
+        log::warn!(
+            "Detected IPv4 {ipv4} is in the CGNAT range 100.64.0.0/10 \
+            and is not reachable from the internet"
+        );
+    }
+    Ok(())
+}
+
+/// Resolve the configured sources of one address family
+///
+/// In [`DetectMode::Precedence`] only the first source is used, in
+/// [`DetectMode::Race`] every source is queried concurrently and the first
+/// success is used.
+async fn detect_sources<T>(
+    mode: DetectMode,
+    sources: Vec<LocalBoxFuture<'_, anyhow::Result<T>>>,
+) -> anyhow::Result<Option<T>> {
+    match mode {
+        DetectMode::Precedence => match sources.into_iter().next() {
+            Some(source) => Ok(Some(source.await?)),
+            None => Ok(None),
+        },
+        DetectMode::Race if sources.is_empty() => Ok(None),
+        DetectMode::Race => {
+            let (ip, _) = futures::future::select_ok(sources)
+                .await
+                .context("All address sources failed")?;
+            Ok(Some(ip))
+        }
+    }
+}
+
+/// Detect the current public IPv4 from the configured sources
+async fn detect_ipv4(config: &Config) -> anyhow::Result<Option<Ipv4Addr>> {
+    let mut sources: Vec<LocalBoxFuture<'_, anyhow::Result<Ipv4Addr>>> = Vec::new();
+    if !config.a_interface.is_empty() {
+        sources.push(
+            async { interface_ipv4_multi(&config.a_interface, &config.exclude_addresses) }
+                .boxed_local(),
+        );
+    }
+    if !config.a_http.is_empty() {
+        sources.push(
+            http_get_ipv4_multi(
+                &config.http_client,
+                &config.a_http,
+                &config.a_http_json_field,
+            )
+            .boxed_local(),
+        );
+    }
+    if let Some(resolver) = config.a_dns {
+        sources.push(dns_get_ipv4(resolver).boxed_local());
+    }
+    if let Some(server) = &config.a_stun {
+        sources.push(stun_get_ipv4(server.clone()).boxed_local());
+    }
+    if let Some(command) = &config.a_command {
+        sources.push(command_get_ipv4(command).boxed_local());
+    }
+
+    detect_sources(config.detect_mode, sources).await
+}
+
+/// Detect the current IPv6 prefix and address from the configured sources
+///
+/// The returned `ipv4` is always None.
+async fn detect_ipv6(config: &Config) -> anyhow::Result<Addresses> {
+    let mut sources: Vec<LocalBoxFuture<'_, anyhow::Result<Ipv6Addr>>> = Vec::new();
+    if let Some(iface) = &config.aaaa_interface {
+        sources.push(
+            async {
+                interface_ipv6_prefix(
+                    iface,
+                    config.prefix_len,
+                    config.prefer_stable_ipv6,
+                    &config.exclude_addresses,
+                )
+            }
+            .boxed_local(),
+        );
+    }
+    if !config.aaaa_http.is_empty() {
+        sources.push(
+            http_get_ipv6_prefix_multi(
+                &config.http_client,
+                &config.aaaa_http,
+                &config.aaaa_http_json_field,
+                config.prefix_len,
+            )
+            .boxed_local(),
+        );
+    }
+    if let Some(command) = &config.aaaa_command {
+        sources.push(command_get_ipv6_prefix(command, config.prefix_len).boxed_local());
+    }
+
+    let ipv6_prefix: Option<Ipv6Addr> = detect_sources(config.detect_mode, sources).await?;
+
+    let ipv6_address: Option<Ipv6Addr> = match &config.aaaa_interface {
+        _ if !config.full_ipv6_records() => None,
+        Some(iface) => Some(interface_ipv6_address(
+            iface,
+            config.prefer_stable_ipv6,
+            &config.exclude_addresses,
+        )?),
+        // the HTTP response is used verbatim, without masking to the prefix
+        None if !config.aaaa_http.is_empty() => Some(
+            http_get_ipv6_address_multi(
+                &config.http_client,
+                &config.aaaa_http,
+                &config.aaaa_http_json_field,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    Ok(Addresses {
+        ipv4: None,
+        ipv6_prefix,
+        ipv6_address,
+    })
+}
+
+/// Result of querying a single configured address source
+pub struct SourceProbe {
+    /// Configuration option and value of the source, such as `a_interface eth0`
+    pub source: String,
+    /// Detected IPv4, or IPv6 prefix
+    pub result: anyhow::Result<IpAddr>,
+}
+
+/// Query every configured address source without updating records
+///
+/// Unlike detection every interface and HTTP URL is queried on its own, so
+/// broken fallbacks are reported too.
+pub async fn probe_sources(config: &Config) -> Vec<SourceProbe> {
+    let mut probes: Vec<(String, LocalBoxFuture<'_, anyhow::Result<IpAddr>>)> = Vec::new();
+
+    for iface in &config.a_interface {
+        probes.push((
+            format!("a_interface {iface}"),
+            async { interface_ipv4(iface, &config.exclude_addresses).map(IpAddr::from) }
+                .boxed_local(),
+        ));
+    }
+    for url in &config.a_http {
+        probes.push((
+            format!("a_http {url}"),
+            async {
+                http_get_ipv4(&config.http_client, url.clone(), &config.a_http_json_field)
+                    .await
+                    .map(IpAddr::from)
+            }
+            .boxed_local(),
+        ));
+    }
+    if let Some(resolver) = config.a_dns {
+        probes.push((
+            format!("a_dns {resolver}"),
+            async move { dns_get_ipv4(resolver).await.map(IpAddr::from) }.boxed_local(),
+        ));
+    }
+    if let Some(server) = &config.a_stun {
+        probes.push((
+            format!("a_stun {server}"),
+            async { stun_get_ipv4(server.clone()).await.map(IpAddr::from) }.boxed_local(),
+        ));
+    }
+    if let Some(command) = &config.a_command {
+        probes.push((
+            format!("a_command {}", command.join(" ")),
+            async { command_get_ipv4(command).await.map(IpAddr::from) }.boxed_local(),
+        ));
+    }
+    if let Some(iface) = &config.aaaa_interface {
+        probes.push((
+            format!("aaaa_interface {iface}"),
+            async {
+                interface_ipv6_prefix(
+                    iface,
+                    config.prefix_len,
+                    config.prefer_stable_ipv6,
+                    &config.exclude_addresses,
+                )
+                .map(IpAddr::from)
+            }
+            .boxed_local(),
+        ));
+    }
+    for url in &config.aaaa_http {
+        probes.push((
+            format!("aaaa_http {url}"),
+            async {
+                http_get_ipv6_prefix(
+                    &config.http_client,
+                    url.clone(),
+                    &config.aaaa_http_json_field,
+                    config.prefix_len,
+                )
+                .await
+                .map(IpAddr::from)
+            }
+            .boxed_local(),
+        ));
+    }
+    if let Some(command) = &config.aaaa_command {
+        probes.push((
+            format!("aaaa_command {}", command.join(" ")),
+            async {
+                command_get_ipv6_prefix(command, config.prefix_len)
+                    .await
+                    .map(IpAddr::from)
+            }
+            .boxed_local(),
+        ));
+    }
+
+    let (sources, futures): (Vec<String>, Vec<_>) = probes.into_iter().unzip();
+    let results: Vec<anyhow::Result<IpAddr>> = futures::future::join_all(futures).await;
+    sources
+        .into_iter()
+        .zip(results)
+        .map(|(source, result)| SourceProbe { source, result })
+        .collect()
+}
+
+/// Combine the IPv4 and IPv6 detection results
+///
+/// A failure in one family is logged and that family is skipped when the
+/// other family detected an address, unless `require_both` is set.
+fn combine_families(
+    ipv4: anyhow::Result<Option<Ipv4Addr>>,
+    ipv6: anyhow::Result<Addresses>,
+    require_both: bool,
+) -> anyhow::Result<Addresses> {
+    match (ipv4, ipv6) {
+        (Ok(ipv4), Ok(ipv6)) => Ok(Addresses { ipv4, ..ipv6 }),
+        (Err(e), Ok(ipv6))
+            if !require_both && (ipv6.ipv6_prefix.is_some() || ipv6.ipv6_address.is_some()) =>
+        {
+            log::error!("Failed to detect IPv4, updating IPv6 records only: {e:?}");
+            Ok(ipv6)
+        }
+        (Ok(Some(ipv4)), Err(e)) if !require_both => {
+            log::error!("Failed to detect IPv6, updating IPv4 records only: {e:?}");
+            Ok(Addresses {
+                ipv4: Some(ipv4),
+                ipv6_prefix: None,
+                ipv6_address: None,
+            })
+        }
+        (Err(e), Ok(_)) => Err(e.context("Failed to detect IPv4")),
+        (Ok(_), Err(e)) => Err(e.context("Failed to detect IPv6")),
+        (Err(e), Err(e6)) => {
+            log::error!("Failed to detect IPv6: {e6:?}");
+            Err(e.context("Failed to detect IPv4"))
+        }
+    }
+}
+
+/// Detect the current public addresses from the configured sources
+pub async fn detect(config: &Config) -> anyhow::Result<Addresses> {
+    let addresses: Addresses = combine_families(
+        detect_ipv4(config).await,
+        detect_ipv6(config).await,
+        config.require_both,
+    )?;
+
+    if let Some(ipv4) = addresses.ipv4 {
+        check_cgnat(ipv4, config.fail_on_cgnat)?;
+    }
+
+    Ok(addresses)
+}
+
+/// Detect the addresses, retrying up to `ip_detect_retries` times on failure
+pub async fn detect_with_retries(config: &Config) -> anyhow::Result<Addresses> {
+    let mut attempt: u32 = 0;
+    loop {
+        match detect(config).await {
+            Err(e) if attempt < config.ip_detect_retries => {
+                attempt = attempt.saturating_add(1);
+                log::warn!(
+                    "Failed to detect addresses, retrying in {:?} ({attempt}/{}): {e:#}",
+                    config.ip_detect_retry_delay,
+                    config.ip_detect_retries
+                );
+                tokio::time::sleep(config.ip_detect_retry_delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Error from [`update`]
+pub enum UpdateError {
+    /// Failed to detect the public addresses
+    Detection(anyhow::Error),
+    /// Failed to update records or save history
+    Update(anyhow::Error),
+}
+
+impl UpdateError {
+    fn inner(&self) -> &anyhow::Error {
+        match self {
+            UpdateError::Detection(e) | UpdateError::Update(e) => e,
+        }
+    }
+}
+
+impl fmt::Debug for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.inner(), f)
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl From<UpdateError> for anyhow::Error {
+    fn from(e: UpdateError) -> anyhow::Error {
+        match e {
+            UpdateError::Detection(e) | UpdateError::Update(e) => e,
+        }
+    }
+}
+
+/// Detect the public addresses and update records, filling `report` with
+/// what was done
+pub async fn update(
+    config: &Config,
+    clients: &Clients,
+    history: &mut History,
+    report: &mut RunReport,
+) -> Result<(), UpdateError> {
+    let detected: Addresses = detect_with_retries(config)
+        .await
+        .map_err(UpdateError::Detection)?;
+    report.ipv4 = detected.ipv4;
+    report.ipv6_prefix = detected.ipv6_prefix;
+    report.ipv6_address = detected.ipv6_address;
+
+    update_records(config, clients, history, detected, report)
+        .await
+        .map_err(UpdateError::Update)
+}
+
+async fn update_records(
+    config: &Config,
+    clients: &Clients,
+    history: &mut History,
+    detected: Addresses,
+    report: &mut RunReport,
+) -> anyhow::Result<()> {
+    let Addresses {
+        ipv4,
+        ipv6_prefix,
+        ipv6_address,
+    } = detected;
+
+    let unchanged_level: log::Level = if config.quiet_unchanged {
+        log::Level::Debug
+    } else {
+        log::Level::Info
+    };
+
+    let addresses: Addresses = if config.force {
+        log::warn!("Forcing update of all records to the detected addresses");
+        detected
+    } else {
+        Addresses {
+            ipv4: changed("IPv4", ipv4, history.ipv4, unchanged_level),
+            ipv6_prefix: prefix_changed(
+                ipv6_prefix,
+                history.ipv6_prefix,
+                config.ipv6_prefix_significant_len,
+                unchanged_level,
+            ),
+            ipv6_address: changed(
+                "IPv6 address",
+                ipv6_address,
+                history.ipv6_address,
+                unchanged_level,
+            ),
+        }
+    };
+
+    // with track_records every run compares the records to the detected addresses
+    let targets: Addresses = if config.track_records {
+        detected
+    } else {
+        addresses
+    };
+
+    if targets.is_empty() {
+        return Ok(());
+    }
+
+    let change: IpChange = IpChange {
+        old_ipv4: history.ipv4,
+        new_ipv4: ipv4,
+        old_ipv6_prefix: history.ipv6_prefix,
+        new_ipv6_prefix: ipv6_prefix,
+        old_ipv6_address: history.ipv6_address,
+        new_ipv6_address: ipv6_address,
+    };
+
+    let zone_updates: Vec<_> = config
+        .zones
+        .iter()
+        .map(|zone| {
+            let cache: Option<ZoneIds> = cached_zone_ids(zone, history);
+            verify_and_update_zone(config, clients, zone, cache, targets)
+        })
+        .collect();
+
+    let mut results: Vec<anyhow::Result<Option<ZoneIds>>> =
+        futures::future::join_all(zone_updates).await;
+
+    if config.track_records {
+        for ids in results.iter().flatten().flatten() {
+            track_changes(&mut history.record_content, &ids.changes);
+        }
+    }
+
+    // keep the previous addresses of a family that failed detection, and the
+    // published prefix while changes below the significant length are skipped
+    let detected: Addresses = Addresses {
+        ipv4: ipv4.or(history.ipv4.filter(|_| config.ipv4_enabled())),
+        ipv6_prefix: targets
+            .ipv6_prefix
+            .or(history.ipv6_prefix.filter(|_| config.ipv6_enabled())),
+        ipv6_address: ipv6_address.or(history
+            .ipv6_address
+            .filter(|_| config.ipv6_enabled() && config.full_ipv6_records())),
+    };
+
+    let recorded: anyhow::Result<()> = record_results(
+        history,
+        &config.zones,
+        &results,
+        (!config.dry_run).then_some(detected),
+    );
+
+    report.zones = config
+        .zones
+        .iter()
+        .zip(&mut results)
+        .map(|(zone, result)| ZoneReport {
+            zone: zone.display_name().to_string(),
+            records: match result {
+                Ok(Some(ids)) => std::mem::take(&mut ids.changes),
+                _ => Vec::new(),
+            },
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        })
+        .collect();
+
+    recorded?;
+
+    if config.dry_run {
+        log::info!("Dry run: not saving history");
+        return Ok(());
+    }
+
+    if let Some(history_path) = &config.history_path {
+        save_history(history_path, history).context("Failed to save history")?;
+    }
+
+    if let Some(url) = &config.notify_webhook
+        && !addresses.is_empty()
+    {
+        notify::webhook(&config.http_client, url, &change).await;
+    }
+
+    if let Some(notify) = &config.notify
+        && !addresses.is_empty()
+    {
+        notify::chat(&config.http_client, notify, &change).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cloudflare::Credentials, config::ConfigBuilder};
+    use std::{path::PathBuf, time::Duration};
+
+    fn existing(id: &str, content: DnsContent) -> HashMap<String, Vec<ExistingRecord>> {
+        HashMap::from([(
+            "www.example.com".to_string(),
+            vec![ExistingRecord {
+                id: id.to_string(),
+                content: Some(content),
+                proxied: None,
+            }],
+        )])
+    }
+
+    #[test]
+    fn batch_errors_per_record() {
+        let update = |name: &'static str| RecordChange::Update {
+            record_id: format!("{name}-id"),
+            previous: None,
+            params: UpdateDnsRecordParams {
+                ttl: None,
+                proxied: None,
+                comment: None,
+                name,
+                content: DnsContent::A {
+                    content: Ipv4Addr::new(192, 0, 2, 1),
+                },
+            },
+        };
+        let www: RecordChange<'_> = update("www.example.com");
+        let mail: RecordChange<'_> = update("mail.example.com");
+        let batch: [&RecordChange<'_>; 2] = [&www, &mail];
+
+        let e: ApiError = ApiError::Http(
+            reqwest::StatusCode::BAD_REQUEST,
+            serde_json::from_value(serde_json::json!([
+                {"code": 9005, "message": "Content is invalid", "source": {"pointer": "/puts/1/content"}},
+                {"code": 9006, "message": "puts[0]: TTL is invalid"},
+                {"code": 1004, "message": "DNS Validation Error"},
+                {"code": 9007, "message": "puts[7]: out of range"},
+            ]))
+            .unwrap(),
+        );
+
+        let (per_record, other) = batch_errors(&batch, &e);
+        let codes: Vec<Vec<u32>> = per_record
+            .iter()
+            .map(|messages| messages.iter().map(|m| m.code).collect())
+            .collect();
+        assert_eq!(codes, [vec![9006], vec![9005]]);
+        assert_eq!(
+            other.iter().map(|m| m.code).collect::<Vec<u32>>(),
+            [1004, 9007]
+        );
+
+        assert!(!is_not_found(&e));
+        assert!(is_not_found(&ApiError::Http(
+            reqwest::StatusCode::BAD_REQUEST,
+            serde_json::from_value(serde_json::json!([
+                {"code": RECORD_NOT_FOUND, "message": "Record does not exist."},
+            ]))
+            .unwrap(),
+        )));
+    }
+
+    #[test]
+    fn prefix_significant_len() {
+        let previous: Option<Ipv6Addr> = Some("2001:db8:aa00::".parse().unwrap());
+        // differs only in the low bits of the delegated /56
+        let rotated: Option<Ipv6Addr> = Some("2001:db8:aa01::".parse().unwrap());
+        let moved: Option<Ipv6Addr> = Some("2001:db8:ab00::".parse().unwrap());
+
+        assert_eq!(
+            prefix_changed(rotated, previous, Some(40), log::Level::Info),
+            None
+        );
+        assert_eq!(
+            prefix_changed(rotated, previous, Some(48), log::Level::Info),
+            rotated
+        );
+        assert_eq!(
+            prefix_changed(moved, previous, Some(40), log::Level::Info),
+            moved
+        );
+        assert_eq!(
+            prefix_changed(rotated, previous, None, log::Level::Info),
+            rotated
+        );
+        assert_eq!(
+            prefix_changed(previous, previous, Some(40), log::Level::Info),
+            None
+        );
+        assert_eq!(
+            prefix_changed(rotated, None, Some(40), log::Level::Info),
+            rotated
+        );
+    }
+
+    #[test]
+    fn record_results_zone_failure() {
+        let zones: Vec<ZoneConfig> = serde_json::from_str(
+            r#"[
+                {"name": "example.com", "records": []},
+                {"name": "example.org", "records": []}
+            ]"#,
+        )
+        .unwrap();
+
+        let previous_ipv4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+        let previous_prefix: Ipv6Addr = "2001:db8:1::".parse().unwrap();
+        let mut history: History = History {
+            ipv4: Some(previous_ipv4),
+            ipv6_prefix: Some(previous_prefix),
+            ..Default::default()
+        };
+
+        let results: Vec<anyhow::Result<Option<ZoneIds>>> = vec![
+            Ok(Some(ZoneIds {
+                zone: "zone-id".to_string(),
+                records: RecordIds::default(),
+                changes: Vec::new(),
+            })),
+            Err(anyhow::anyhow!("simulated failure")),
+        ];
+        let detected: Addresses = Addresses {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+            ipv6_prefix: Some("2001:db8:2::".parse().unwrap()),
+            ipv6_address: None,
+        };
+
+        assert!(record_results(&mut history, &zones, &results, Some(detected)).is_err());
+        assert_eq!(history.last_updated, None);
+        assert_eq!(history.ipv4, Some(previous_ipv4));
+        assert_eq!(history.ipv6_prefix, Some(previous_prefix));
+        assert_eq!(history.ipv6_address, None);
+        assert_eq!(history.zone_ids["example.com"], "zone-id");
+
+        // no records changed
+        let results: Vec<anyhow::Result<Option<ZoneIds>>> = vec![
+            Ok(Some(ZoneIds {
+                zone: "zone-id".to_string(),
+                records: RecordIds::default(),
+                changes: vec![],
+            })),
+            Ok(None),
+        ];
+        record_results(&mut history, &zones, &results, Some(detected)).unwrap();
+        assert_eq!(history.last_updated, None);
+        assert_eq!(history.ipv4, detected.ipv4);
+        assert_eq!(history.ipv6_prefix, detected.ipv6_prefix);
+
+        let results: Vec<anyhow::Result<Option<ZoneIds>>> = vec![
+            Ok(Some(ZoneIds {
+                zone: "zone-id".to_string(),
+                records: RecordIds::default(),
+                changes: vec![RecordReport {
+                    name: "www.example.com".to_string(),
+                    action: RecordAction::Update,
+                    previous: None,
+                    content: DnsContent::A {
+                        content: Ipv4Addr::new(192, 0, 2, 2),
+                    },
+                }],
+            })),
+            Ok(None),
+        ];
+        record_results(&mut history, &zones, &results, Some(detected)).unwrap();
+        assert!(history.last_updated.is_some());
+    }
+
+    #[test]
+    fn plan_changes_skips_matching_content() {
+        let config: ZoneConfig = serde_json::from_str(
+            r#"{
+                "name": "example.com",
+                "records": [
+                    {
+                        "name": "www.example.com",
+                        "suffix": "::1"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let ipv4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+        let prefix: Ipv6Addr = "2001:db8::".parse().unwrap();
+
+        let record_maps: RecordMaps = RecordMaps {
+            a: existing("a-id", DnsContent::A { content: ipv4 }),
+            aaaa: existing(
+                "aaaa-id",
+                DnsContent::AAAA {
+                    content: "2001:db8::2".parse().unwrap(),
+                },
+            ),
+            cname: HashMap::new(),
+        };
+
+        let addresses: Addresses = Addresses {
+            ipv4: Some(ipv4),
+            ipv6_prefix: Some(prefix),
+            ipv6_address: None,
+        };
+
+        let Plan {
+            changes, missing, ..
+        } = plan_changes(&config, &record_maps, addresses);
+        assert!(missing.is_empty());
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(
+            &changes[0],
+            RecordChange::Update { record_id, .. } if record_id == "aaaa-id"
+        ));
+        assert_eq!(
+            changes[0].content(),
+            &DnsContent::AAAA {
+                content: "2001:db8::1".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn plan_changes_single_family() {
+        let zone_config = |create_missing: bool| -> ZoneConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": "example.com",
+                "records": [
+                    {
+                        "name": "www.example.com",
+                        "suffix": "::1",
+                        "create_missing": create_missing
+                    }
+                ]
+            }))
+            .unwrap()
+        };
+
+        let zone: ZoneConfig = zone_config(false);
+        let create_zone: ZoneConfig = zone_config(true);
+
+        let ipv4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+        let prefix: Ipv6Addr = "2001:db8::".parse().unwrap();
+        let ipv4_only: Addresses = Addresses {
+            ipv4: Some(ipv4),
+            ..Default::default()
+        };
+        let ipv6_only: Addresses = Addresses {
+            ipv6_prefix: Some(prefix),
+            ..Default::default()
+        };
+        let both: Addresses = Addresses {
+            ipv4: Some(ipv4),
+            ipv6_prefix: Some(prefix),
+            ipv6_address: None,
+        };
+
+        let aaaa_only: RecordMaps = RecordMaps {
+            a: HashMap::new(),
+            aaaa: existing(
+                "aaaa-id",
+                DnsContent::AAAA {
+                    content: "2001:db8:1::1".parse().unwrap(),
+                },
+            ),
+            cname: HashMap::new(),
+        };
+        let a_only: RecordMaps = RecordMaps {
+            a: existing(
+                "a-id",
+                DnsContent::A {
+                    content: Ipv4Addr::new(192, 0, 2, 9),
+                },
+            ),
+            aaaa: HashMap::new(),
+            cname: HashMap::new(),
+        };
+
+        // only IPv4 changed, the AAAA only record is not an error
+        let plan: Plan = plan_changes(&zone, &aaaa_only, ipv4_only);
+        assert!(plan.missing.is_empty());
+        assert!(plan.changes.is_empty());
+
+        let plan: Plan = plan_changes(&zone, &aaaa_only, both);
+        assert!(plan.missing.is_empty());
+        assert_eq!(plan.changes.len(), 1);
+        assert!(matches!(
+            &plan.changes[0],
+            RecordChange::Update { record_id, .. } if record_id == "aaaa-id"
+        ));
+
+        let plan: Plan = plan_changes(&zone, &a_only, ipv6_only);
+        assert!(plan.missing.is_empty());
+        assert!(plan.changes.is_empty());
+
+        // create_missing manages both families
+        let plan: Plan = plan_changes(&create_zone, &aaaa_only, ipv4_only);
+        assert!(matches!(&plan.changes[..], [RecordChange::Create { .. }]));
+
+        // a record of neither family is still missing
+        let none: RecordMaps = RecordMaps {
+            a: HashMap::new(),
+            aaaa: HashMap::new(),
+            cname: HashMap::new(),
+        };
+        let plan: Plan = plan_changes(&zone, &none, ipv4_only);
+        assert_eq!(plan.missing, [("A", "www.example.com")]);
+    }
+
+    #[test]
+    fn plan_changes_proxied() {
+        let config = |proxied: &str| -> ZoneConfig {
+            serde_json::from_str(&format!(
+                r#"{{"name": "example.com", "records": [{{"name": "www.example.com"{proxied}}}]}}"#
+            ))
+            .unwrap()
+        };
+
+        let ipv4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+        let record_maps = |content: Ipv4Addr| -> RecordMaps {
+            RecordMaps {
+                a: HashMap::from([(
+                    "www.example.com".to_string(),
+                    vec![ExistingRecord {
+                        id: "a-id".to_string(),
+                        content: Some(DnsContent::A { content }),
+                        proxied: Some(false),
+                    }],
+                )]),
+                aaaa: HashMap::new(),
+                cname: HashMap::new(),
+            }
+        };
+        let addresses: Addresses = Addresses {
+            ipv4: Some(ipv4),
+            ..Default::default()
+        };
+        let proxied = |changes: &[RecordChange]| -> Vec<Option<bool>> {
+            changes
+                .iter()
+                .map(|change| match change {
+                    RecordChange::Update { params, .. } => params.proxied,
+                    RecordChange::Create { .. } => panic!("unexpected create"),
+                })
+                .collect()
+        };
+
+        // only the proxied flag differs
+        let config_proxied: ZoneConfig = config(r#", "proxied": true"#);
+        let plan: Plan = plan_changes(&config_proxied, &record_maps(ipv4), addresses);
+        assert_eq!(proxied(&plan.changes), [Some(true)]);
+
+        let config_unproxied: ZoneConfig = config(r#", "proxied": false"#);
+        let plan: Plan = plan_changes(&config_unproxied, &record_maps(ipv4), addresses);
+        assert!(plan.changes.is_empty());
+
+        // the live state is kept when proxied is not configured
+        let config_default: ZoneConfig = config("");
+        let plan: Plan = plan_changes(&config_default, &record_maps(ipv4), addresses);
+        assert!(plan.changes.is_empty());
+        let plan: Plan = plan_changes(
+            &config_default,
+            &record_maps(Ipv4Addr::new(192, 0, 2, 2)),
+            addresses,
+        );
+        assert_eq!(proxied(&plan.changes), [Some(false)]);
+    }
+
+    #[test]
+    fn plan_changes_cname() {
+        let config: ZoneConfig = serde_json::from_str(
+            r#"{
+                "name": "example.com",
+                "records": [
+                    {
+                        "name": "www.example.com",
+                        "target": "host.example.net"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let addresses: Addresses = Addresses {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ipv6_prefix: Some("2001:db8::".parse().unwrap()),
+            ipv6_address: None,
+        };
+
+        let record_maps: RecordMaps = RecordMaps {
+            a: HashMap::new(),
+            aaaa: HashMap::new(),
+            cname: existing(
+                "cname-id",
+                DnsContent::CNAME {
+                    content: "old.example.net".to_string(),
+                },
+            ),
+        };
+
+        let Plan {
+            changes, missing, ..
+        } = plan_changes(&config, &record_maps, addresses);
+        assert!(missing.is_empty());
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(
+            &changes[0],
+            RecordChange::Update { record_id, .. } if record_id == "cname-id"
+        ));
+        assert_eq!(
+            changes[0].content(),
+            &DnsContent::CNAME {
+                content: "host.example.net".to_string()
+            }
+        );
+
+        let record_maps: RecordMaps = RecordMaps {
+            a: HashMap::new(),
+            aaaa: HashMap::new(),
+            cname: existing(
+                "cname-id",
+                DnsContent::CNAME {
+                    content: "host.example.net".to_string(),
+                },
+            ),
+        };
+
+        let Plan {
+            changes, missing, ..
+        } = plan_changes(&config, &record_maps, addresses);
+        assert!(missing.is_empty());
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn plan_changes_zone_defaults() {
+        let config: ZoneConfig = serde_json::from_str(
+            r#"{
+                "name": "example.com",
+                "default_ttl": 300,
+                "default_proxied": true,
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    },
+                    {
+                        "name": "mail.example.com",
+                        "ttl": 60,
+                        "proxied": false
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let ipv4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+        let record_maps: RecordMaps = RecordMaps {
+            a: ["www.example.com", "mail.example.com"]
+                .into_iter()
+                .map(|name| {
+                    (
+                        name.to_string(),
+                        vec![ExistingRecord {
+                            id: format!("{name}-id"),
+                            content: None,
+                            proxied: None,
+                        }],
+                    )
+                })
+                .collect(),
+            aaaa: HashMap::new(),
+            cname: HashMap::new(),
+        };
+        let addresses: Addresses = Addresses {
+            ipv4: Some(ipv4),
+            ..Default::default()
+        };
+
+        let Plan {
+            changes, missing, ..
+        } = plan_changes(&config, &record_maps, addresses);
+        assert!(missing.is_empty());
+
+        let params: Vec<(&str, Option<u32>, Option<bool>)> = changes
+            .iter()
+            .map(|change| match change {
+                RecordChange::Update { params, .. } => (params.name, params.ttl, params.proxied),
+                RecordChange::Create { .. } => panic!("unexpected create"),
+            })
+            .collect();
+        assert_eq!(
+            params,
+            [
+                ("www.example.com", Some(300), Some(true)),
+                ("mail.example.com", Some(60), Some(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_changes_comment() {
+        let config: ZoneConfig = serde_json::from_str(
+            r#"{
+                "name": "example.com",
+                "records": [
+                    {
+                        "name": "www.example.com",
+                        "comment": "managed by cfddns",
+                        "create_missing": true
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let record_maps: RecordMaps = RecordMaps {
+            a: HashMap::new(),
+            aaaa: HashMap::new(),
+            cname: HashMap::new(),
+        };
+        let addresses: Addresses = Addresses {
+            ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            ..Default::default()
+        };
+
+        let Plan {
+            changes, missing, ..
+        } = plan_changes(&config, &record_maps, addresses);
+        assert!(missing.is_empty());
+
+        let RecordChange::Create { params } = &changes[0] else {
+            panic!("expected create");
+        };
+        assert_eq!(
+            serde_json::to_value(params).unwrap()["comment"],
+            "managed by cfddns"
+        );
+    }
+
+    #[tokio::test]
+    async fn detect_mode_race() {
+        use crate::config::ConfigBuilder;
+        use std::time::{Duration, Instant};
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("192.0.2.2\n")
+                    .set_delay(Duration::from_millis(500)),
+            )
+            .mount(&server)
+            .await;
+        let url: url::Url = server.uri().parse().unwrap();
+
+        let builder = |command: &str, mode: DetectMode| {
+            ConfigBuilder::new()
+                .a_http(url.clone())
+                .a_command(vec![command.to_string(), "192.0.2.1".to_string()])
+                .detect_mode(mode)
+                .build()
+                .unwrap()
+        };
+
+        // the command answers before the delayed HTTP service
+        let start: Instant = Instant::now();
+        let ipv4: Option<Ipv4Addr> = detect_ipv4(&builder("echo", DetectMode::Race))
+            .await
+            .unwrap();
+        assert_eq!(ipv4, Some(Ipv4Addr::new(192, 0, 2, 1)));
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // a failing source does not end the race
+        let ipv4: Option<Ipv4Addr> = detect_ipv4(&builder("false", DetectMode::Race))
+            .await
+            .unwrap();
+        assert_eq!(ipv4, Some(Ipv4Addr::new(192, 0, 2, 2)));
+
+        // HTTP takes precedence over commands
+        let ipv4: Option<Ipv4Addr> = detect_ipv4(&builder("echo", DetectMode::Precedence))
+            .await
+            .unwrap();
+        assert_eq!(ipv4, Some(Ipv4Addr::new(192, 0, 2, 2)));
+    }
+
+    #[test]
+    fn partial_detection_failure() {
+        let ipv4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+        let ipv6: Addresses = Addresses {
+            ipv4: None,
+            ipv6_prefix: Some("2001:db8:1::".parse().unwrap()),
+            ipv6_address: None,
+        };
+        let no_ipv6: Addresses = Addresses {
+            ipv4: None,
+            ipv6_prefix: None,
+            ipv6_address: None,
+        };
+
+        let addresses: Addresses = combine_families(Ok(Some(ipv4)), Ok(ipv6), false).unwrap();
+        assert_eq!(addresses.ipv4, Some(ipv4));
+        assert_eq!(addresses.ipv6_prefix, ipv6.ipv6_prefix);
+
+        let addresses: Addresses =
+            combine_families(Err(anyhow::anyhow!("unreachable")), Ok(ipv6), false).unwrap();
+        assert_eq!(addresses.ipv4, None);
+        assert_eq!(addresses.ipv6_prefix, ipv6.ipv6_prefix);
+
+        let addresses: Addresses =
+            combine_families(Ok(Some(ipv4)), Err(anyhow::anyhow!("unreachable")), false).unwrap();
+        assert_eq!(addresses.ipv4, Some(ipv4));
+        assert_eq!(addresses.ipv6_prefix, None);
+
+        // the other family has nothing to update
+        assert!(combine_families(Err(anyhow::anyhow!("unreachable")), Ok(no_ipv6), false).is_err());
+        assert!(combine_families(Ok(None), Err(anyhow::anyhow!("unreachable")), false).is_err());
+
+        assert!(
+            combine_families(
+                Err(anyhow::anyhow!("unreachable")),
+                Err(anyhow::anyhow!("unreachable")),
+                false
+            )
+            .is_err()
+        );
+
+        assert!(combine_families(Err(anyhow::anyhow!("unreachable")), Ok(ipv6), true).is_err());
+        assert!(
+            combine_families(Ok(Some(ipv4)), Err(anyhow::anyhow!("unreachable")), true).is_err()
+        );
+    }
+
+    #[test]
+    fn cgnat() {
+        let shared: Ipv4Addr = Ipv4Addr::new(100, 64, 1, 1);
+        let global: Ipv4Addr = Ipv4Addr::new(1, 1, 1, 1);
+
+        assert!(check_cgnat(shared, false).is_ok());
+        assert!(check_cgnat(shared, true).is_err());
+        assert!(check_cgnat(global, false).is_ok());
+        assert!(check_cgnat(global, true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn prune_dropped_records() {
+        use crate::cloudflare::Credentials;
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("DELETE"))
+            .and(matchers::path("/zones/zone-id/dns_records/old-a-id"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": {"id": "old-a-id"},
+                "result_info": null,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(matchers::method("DELETE"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let config: ZoneConfig = serde_json::from_str(
+            r#"{
+                "id": "zone-id",
+                "prune": true,
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let previous: RecordIds = RecordIds {
+            a: HashMap::from([
+                ("www.example.com".to_string(), vec!["www-a-id".to_string()]),
+                ("old.example.com".to_string(), vec!["old-a-id".to_string()]),
+            ]),
+            aaaa: HashMap::new(),
+            cname: HashMap::new(),
+        };
+        let mut records: RecordIds = RecordIds {
+            a: HashMap::from([("www.example.com".to_string(), vec!["www-a-id".to_string()])]),
+            aaaa: HashMap::new(),
+            cname: HashMap::new(),
+        };
+
+        let client: Client = Client::new(Credentials::UserAuthToken {
+            token: "token".to_string(),
+        })
+        .unwrap()
+        .with_api_base(&format!("{}/", server.uri()));
+
+        prune_records(&client, &config, "zone-id", &previous, &mut records, false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            records.a,
+            HashMap::from([("www.example.com".to_string(), vec!["www-a-id".to_string()])])
+        );
+    }
+
+    #[test]
+    fn record_cache_covers() {
+        let config: ZoneConfig = serde_json::from_str(
+            r#"{
+                "name": "example.com",
+                "records": [
+                    {
+                        "name": "www.example.com",
+                        "suffix": "::1"
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let ids: RecordIds = RecordIds {
+            a: HashMap::from([("www.example.com".to_string(), vec!["a-id".to_string()])]),
+            aaaa: HashMap::new(),
+            cname: HashMap::new(),
+        };
+        let maps: RecordMaps = RecordMaps::from_cache(&ids);
+
+        let ipv4: Addresses = Addresses {
+            ipv4: Some(Ipv4Addr::UNSPECIFIED),
+            ..Default::default()
+        };
+        let ipv6: Addresses = Addresses {
+            ipv6_prefix: Some(Ipv6Addr::UNSPECIFIED),
+            ..Default::default()
+        };
+
+        assert!(maps.covers(&config, ipv4));
+        assert!(!maps.covers(&config, ipv6));
+        assert_eq!(maps.ids(&config), ids);
+    }
+
+    #[tokio::test]
+    async fn detect_retries() {
+        let dir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
+        let attempted: PathBuf = dir.path().join("attempted");
+
+        // fails on the first attempt, as if the interface has no address yet
+        let command: Vec<String> = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!(
+                "[ -e {0} ] && echo 192.0.2.1 || {{ touch {0}; exit 1; }}",
+                attempted.display()
+            ),
+        ];
+        let config = |retries: u32| -> Config {
+            ConfigBuilder::new()
+                .a_command(command.clone())
+                .ip_detect_retries(retries)
+                .ip_detect_retry_delay(Duration::ZERO)
+                .build()
+                .unwrap()
+        };
+
+        assert!(detect_with_retries(&config(0)).await.is_err());
+        std::fs::remove_file(&attempted).unwrap();
+
+        let addresses: Addresses = detect_with_retries(&config(2)).await.unwrap();
+        assert_eq!(addresses.ipv4, Some(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[tokio::test]
+    async fn http_client_reused() {
+        use std::{
+            io::{Read as _, Write as _},
+            net::TcpListener,
+            sync::{
+                Arc,
+                atomic::{AtomicUsize, Ordering},
+            },
+        };
+
+        // counts connections, a reused client keeps its connection alive
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: url::Url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let connections: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let accepted: Arc<AtomicUsize> = Arc::clone(&connections);
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut request: [u8; 1024] = [0; 1024];
+                    while let Ok(len) = stream.read(&mut request)
+                        && len > 0
+                    {
+                        stream
+                            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\n192.0.2.1\n")
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let config: Config = ConfigBuilder::new().a_http(url).build().unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                detect(&config).await.unwrap().ipv4,
+                Some(Ipv4Addr::new(192, 0, 2, 1))
+            );
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    /// DNS server answering A queries with the address of the name in
+    /// `answers`
+    async fn mock_resolver(answers: HashMap<&'static str, Ipv4Addr>) -> SocketAddr {
+        use hickory_resolver::proto::{
+            op::Message,
+            rr::{RData, Record, RecordType, rdata::A},
+        };
+
+        let socket: tokio::net::UdpSocket =
+            tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf: [u8; 512] = [0; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let query: Message = Message::from_vec(&buf[..len]).unwrap();
+                let mut response: Message =
+                    Message::response(query.metadata.id, query.metadata.op_code);
+                for question in query.queries {
+                    let name: String = question.name().to_string();
+                    if question.query_type() == RecordType::A
+                        && let Some(ipv4) = answers.get(name.trim_end_matches('.'))
+                    {
+                        response.add_answer(Record::from_rdata(
+                            question.name().clone(),
+                            60,
+                            RData::A(A(*ipv4)),
+                        ));
+                    }
+                    response.add_query(question);
+                }
+                socket
+                    .send_to(&response.to_vec().unwrap(), peer)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn verify_via_dns() {
+        let zone = || -> ZoneConfig {
+            serde_json::from_str(
+                r#"{"name": "example.com", "records": [{"name": "www.example.com", "type": "A"}]}"#,
+            )
+            .unwrap()
+        };
+        let resolver: SocketAddr = mock_resolver(HashMap::from([
+            ("www.example.com", Ipv4Addr::new(192, 0, 2, 1)),
+            ("mail.example.com", Ipv4Addr::new(192, 0, 2, 9)),
+        ]))
+        .await;
+        let addresses = |ipv4: Ipv4Addr| -> Addresses {
+            Addresses {
+                ipv4: Some(ipv4),
+                ..Default::default()
+            }
+        };
+
+        let names = |records: Vec<RecordConfig>| -> Vec<String> {
+            records.into_iter().map(|record| record.name).collect()
+        };
+        assert!(
+            unresolved_records(resolver, &zone(), addresses(Ipv4Addr::new(192, 0, 2, 1)))
+                .await
+                .is_empty()
+        );
+        assert_eq!(
+            names(
+                unresolved_records(resolver, &zone(), addresses(Ipv4Addr::new(192, 0, 2, 2))).await
+            ),
+            ["www.example.com"]
+        );
+
+        // only the record that does not resolve is updated
+        let mixed: ZoneConfig = serde_json::from_str(
+            r#"{"name": "example.com", "records": [
+                {"name": "www.example.com", "type": "A"},
+                {"name": "mail.example.com", "type": "A"},
+                {"name": "ftp.example.com", "type": "A"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            names(
+                unresolved_records(resolver, &mixed, addresses(Ipv4Addr::new(192, 0, 2, 1))).await
+            ),
+            ["mail.example.com", "ftp.example.com"]
+        );
+
+        // the zone is skipped without API requests and the address is saved
+        let dir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
+        let config: Config = ConfigBuilder::new()
+            .a_command(vec!["echo".to_string(), "192.0.2.1".to_string()])
+            .zones(vec![zone()])
+            .history_path(dir.path().join("history.json"))
+            .verify_via_dns(true)
+            .verify_resolver(resolver)
+            .build()
+            .unwrap();
+        // the token is invalid, any API request would fail
+        let clients: Clients = Client::new(Credentials::UserAuthToken {
+            token: String::new(),
+        })
+        .unwrap()
+        .into();
+        let mut history: History = History::default();
+        update(&config, &clients, &mut history, &mut RunReport::default())
+            .await
+            .unwrap();
+        assert_eq!(history.ipv4, Some(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[tokio::test]
+    async fn zone_identifier_from_config() {
+        let config: ZoneConfig = serde_json::from_str(
+            r#"{
+                "name": "example.com",
+                "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                "records": []
+            }"#,
+        )
+        .unwrap();
+
+        // the token is invalid, any API request would fail
+        let api_client: Client = Client::new(Credentials::UserAuthToken {
+            token: String::new(),
+        })
+        .unwrap();
+
+        assert_eq!(
+            zone_identifier(&config, &api_client).await.unwrap(),
+            "023e105f4ecef8ad9ca31a8372d0c353"
+        );
+    }
+
+    #[test]
+    fn zone_lookup_names() {
+        assert_eq!(zone_lookup_name("example.com").unwrap(), "example.com");
+        assert_eq!(zone_lookup_name("example.com.").unwrap(), "example.com");
+        assert_eq!(
+            zone_lookup_name("ex\u{e4}mple.com").unwrap(),
+            "xn--exmple-cua.com"
+        );
+        assert_eq!(
+            zone_lookup_name("B\u{fc}cher.example.").unwrap(),
+            "xn--bcher-kva.example"
+        );
+    }
+
+    #[tokio::test]
+    async fn zone_id_idn() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/zones"))
+            .and(matchers::query_param("name", "xn--exmple-cua.com"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": [{"id": "zone-id", "name": "xn--exmple-cua.com"}],
+                "result_info": null,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client: Client = Client::new(Credentials::UserAuthToken {
+            token: "token".to_string(),
+        })
+        .unwrap()
+        .with_api_base(&format!("{}/", server.uri()));
+
+        assert_eq!(
+            zone_id("ex\u{e4}mple.com.", &client).await.unwrap(),
+            "zone-id"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::Context as _;
+use serde::Deserialize;
+use std::io::Write as _;
+
+/// Log output format
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// systemd journal
+    #[default]
+    Journal,
+    /// Newline delimited JSON on stderr
+    Json,
+    /// Human readable lines on stderr
+    Plain,
+}
+
+/// Logger writing to stderr
+struct StderrLogger {
+    json: bool,
+}
+
+fn format_json(record: &log::Record) -> String {
+    serde_json::json!({
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+fn format_plain(record: &log::Record) -> String {
+    format!("{:<5} {}", record.level(), record.args())
+}
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line: String = if self.json {
+            format_json(record)
+        } else {
+            format_plain(record)
+        };
+
+        // nowhere to report a failure to write to stderr
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the global logger for a log format
+pub fn install(format: LogFormat) -> anyhow::Result<()> {
+    match format {
+        LogFormat::Journal => systemd_journal_logger::JournalLog::new()
+            .context("Failed to create logger")?
+            .install()
+            .context("Failed to install logger"),
+        LogFormat::Json | LogFormat::Plain => log::set_boxed_logger(Box::new(StderrLogger {
+            json: format == LogFormat::Json,
+        }))
+        .context("Failed to install logger"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_record() {
+        let line: String = format_json(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("cfddns")
+                .args(format_args!("IPv4 changed from {} to {}", 1, 2))
+                .build(),
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "level": "WARN",
+                "target": "cfddns",
+                "message": "IPv4 changed from 1 to 2",
+            })
+        );
+    }
+
+    #[test]
+    fn plain_record() {
+        let line: String = format_plain(
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("IPv4 unchanged, skipping update"))
+                .build(),
+        );
+
+        assert_eq!(line, "INFO  IPv4 unchanged, skipping update");
+    }
+}
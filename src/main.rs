@@ -1,305 +1,245 @@
-#![feature(ip)]
 #![forbid(unsafe_code)]
 
-mod cloudflare;
-mod config;
-mod ip;
-
-use crate::cloudflare::{
-    Client, DnsContent, ListDnsRecordsParams, ListZonesParams, OrderDirection, SearchMatch, Status,
-    UpdateDnsRecordParams,
-};
 use anyhow::Context as _;
-use config::{Config, History, ZoneConfig, save_history};
-use ip::{http_get_ipv4, http_get_ipv6_prefix, interface_ipv4, interface_ipv6_prefix};
+use cfddns::{
+    Addresses, SourceProbe, UpdateError,
+    config::{Clients, Config, History, PrintIp, save_history},
+    detect_with_retries,
+    metrics::{Metrics, save_metrics},
+    notify, probe_sources,
+    report::{RunReport, save_report},
+    update,
+};
 use std::{
-    collections::HashMap,
     net::{Ipv4Addr, Ipv6Addr},
+    process::ExitCode,
 };
+use tokio::signal::unix::{Signal, SignalKind, signal};
+
+/// Exit code for failures outside of configuration, detection, and updates
+const EXIT_FAILURE: u8 = 1;
+/// Exit code for configuration errors
+const EXIT_CONFIG: u8 = 2;
+/// Exit code for IP detection failures
+const EXIT_DETECTION: u8 = 3;
+/// Exit code for Cloudflare update failures
+const EXIT_UPDATE: u8 = 4;
+
+struct Failure {
+    code: u8,
+    error: anyhow::Error,
+}
 
-async fn zone_id(name: &str, api_client: &Client) -> anyhow::Result<String> {
-    let params = ListZonesParams {
-        name: Some(name.to_string()),
-        status: Some(Status::Active),
-        search_match: Some(SearchMatch::All),
-        ..Default::default()
-    };
-
-    let response = api_client
-        .list_zones(&params)
-        .await
-        .context("Failed to list zones")?;
-
-    if response.result.len() > 1 {
-        anyhow::bail!("Multiple zones matching name {name}");
+impl Failure {
+    fn config(error: anyhow::Error) -> Failure {
+        Failure {
+            code: EXIT_CONFIG,
+            error,
+        }
     }
 
-    let id: String = response
-        .result
-        .first()
-        .with_context(|| format!("No zones matching name {name}"))?
-        .id
-        .clone();
-
-    Ok(id)
+    fn other(error: anyhow::Error) -> Failure {
+        Failure {
+            code: EXIT_FAILURE,
+            error,
+        }
+    }
 }
 
-struct RecordMaps {
-    a: HashMap<String, String>,
-    aaaa: HashMap<String, String>,
+impl From<UpdateError> for Failure {
+    fn from(e: UpdateError) -> Failure {
+        match e {
+            UpdateError::Detection(error) => Failure {
+                code: EXIT_DETECTION,
+                error,
+            },
+            UpdateError::Update(error) => Failure {
+                code: EXIT_UPDATE,
+                error,
+            },
+        }
+    }
 }
 
-async fn zone_record_map(zone_identifier: &str, api_client: &Client) -> anyhow::Result<RecordMaps> {
-    let mut a_record_id_map: HashMap<String, String> = HashMap::new();
-    let mut aaaa_record_id_map: HashMap<String, String> = HashMap::new();
+/// Update records, then write the report and metrics and ping the
+/// healthcheck URL with the result
+async fn update_and_ping(
+    config: &Config,
+    clients: &Clients,
+    history: &mut History,
+    metrics: &mut Metrics,
+) -> Result<(), UpdateError> {
+    let previous_ipv4: Option<Ipv4Addr> = history.ipv4;
+    let previous_ipv6_prefix: Option<Ipv6Addr> = history.ipv6_prefix;
+
+    let mut report: RunReport = RunReport::default();
+    let result: Result<(), UpdateError> = update(config, clients, history, &mut report).await;
+    report.finish(&result);
+
+    let ipv4_changed: bool = history.ipv4 != previous_ipv4;
+    let ipv6_changed: bool = history.ipv6_prefix != previous_ipv6_prefix;
+    let unchanged: bool = result.is_ok() && report.zones.iter().all(|zone| zone.records.is_empty());
+    let level: log::Level = if config.quiet_unchanged && unchanged {
+        log::Level::Debug
+    } else {
+        log::Level::Info
+    };
+    log::log!(level, "{}", report.summary(ipv4_changed, ipv6_changed));
 
-    let mut page: u32 = 1;
-    loop {
-        let params = ListDnsRecordsParams {
-            direction: Some(OrderDirection::Asc),
-            page: Some(page),
-            ..Default::default()
-        };
-
-        let response = api_client
-            .list_dns_records(zone_identifier, &params)
-            .await
-            .context("Failed to list existing DNS records")?;
-
-        let a_record_id_map_per_page: HashMap<String, String> = response
-            .result
-            .iter()
-            .filter(|record| matches!(record.content, DnsContent::A { content: _ }))
-            .map(|record| (record.name.clone(), record.id.clone()))
-            .collect();
-        a_record_id_map.extend(a_record_id_map_per_page);
-
-        let aaaa_record_id_map_per_page: HashMap<String, String> = response
-            .result
-            .iter()
-            .filter(|record| matches!(record.content, DnsContent::AAAA { content: _ }))
-            .map(|record| (record.name.clone(), record.id.clone()))
-            .collect();
-        aaaa_record_id_map.extend(aaaa_record_id_map_per_page);
-
-        if let Some(info) = response.result_info {
-            if info.total_pages == page {
-                break;
-            }
+    if let Some(path) = &config.report_path
+        && let Err(e) = save_report(path, &report)
+    {
+        log::error!("Failed to save report: {e:#}");
+    }
 
-            page = page.checked_add(1).context("Page number wrapped")?;
-        } else {
-            break;
+    if let Some(path) = &config.metrics_path {
+        metrics.record(&report, ipv4_changed, ipv6_changed);
+        if let Err(e) = save_metrics(path, metrics) {
+            log::error!("Failed to save metrics: {e:#}");
         }
     }
 
-    Ok(RecordMaps {
-        a: a_record_id_map,
-        aaaa: aaaa_record_id_map,
-    })
-}
-
-async fn update_zone(
-    api_client: &Client,
-    config: &ZoneConfig,
-    ipv4: Option<Ipv4Addr>,
-    ipv6_prefix: Option<Ipv6Addr>,
-) -> anyhow::Result<()> {
-    let zone_name: &str = config.name.as_str();
-
-    if config.records.is_empty() {
-        log::warn!("No records for zone '{zone_name}'");
-        return Ok(());
+    if let Some(url) = &config.healthcheck_url {
+        notify::healthcheck(&config.http_client, url, result.is_ok()).await;
     }
 
-    let zone_identifier = zone_id(zone_name, api_client)
-        .await
-        .with_context(|| format!("Failed to get zone identifer from zone name '{zone_name}'"))?;
-
-    let record_maps: RecordMaps = zone_record_map(zone_identifier.as_str(), api_client)
-        .await
-        .with_context(|| {
-            format!("Failed to list records for zone '{zone_name}' id '{zone_identifier}'")
-        })?;
-
-    let mut updates: Vec<(String, String, UpdateDnsRecordParams<'_>)> =
-        Vec::with_capacity(config.records.len());
-
-    let mut errors: u32 = 0;
-
-    for record_config in &config.records {
-        let record_name: &str = record_config.name.as_str();
-
-        if let Some(content) = ipv4 {
-            if let Some(record_id) = record_maps.a.get(record_name) {
-                log::debug!("Update {record_name} A to {content}");
-
-                updates.push((
-                    zone_identifier.clone(),
-                    record_id.clone(),
-                    UpdateDnsRecordParams {
-                        ttl: record_config.ttl,
-                        proxied: record_config.proxied,
-                        name: record_config.name.as_str(),
-                        content: DnsContent::A { content },
-                    },
-                ));
-            } else {
-                log::error!("No A record exists for {record_name}");
-                errors = errors.saturating_add(1);
-            }
-        }
+    result
+}
 
-        if let (Some(prefix), Some(suffix)) = (ipv6_prefix, &record_config.suffix) {
-            if let Some(record_id) = record_maps.aaaa.get(record_name) {
-                let content: Ipv6Addr = prefix | suffix;
-
-                log::debug!("Update {record_name} AAAA to {content}");
-
-                updates.push((
-                    zone_identifier.clone(),
-                    record_id.clone(),
-                    UpdateDnsRecordParams {
-                        ttl: record_config.ttl,
-                        proxied: record_config.proxied,
-                        name: record_config.name.as_str(),
-                        content: DnsContent::AAAA { content },
-                    },
-                ));
-            } else {
-                log::error!("No AAAA record exists for {record_name}");
-                errors = errors.saturating_add(1);
+/// Print the detected addresses to stdout
+fn print_addresses(addresses: &Addresses, prefix_len: u8, format: PrintIp) {
+    match format {
+        PrintIp::Lines => {
+            if let Some(ipv4) = addresses.ipv4 {
+                println!("{ipv4}");
+            }
+            if let Some(ipv6_prefix) = addresses.ipv6_prefix {
+                println!("{ipv6_prefix}/{prefix_len}");
+            }
+            if let Some(ipv6_address) = addresses.ipv6_address {
+                println!("{ipv6_address}");
             }
         }
+        PrintIp::Json => println!(
+            "{}",
+            serde_json::json!({
+                "ipv4": addresses.ipv4,
+                "ipv6_prefix": addresses.ipv6_prefix,
+                "ipv6_address": addresses.ipv6_address,
+            })
+        ),
     }
+}
 
-    let requests: Vec<_> = updates
-        .iter()
-        .map(|(zid, rid, params)| api_client.update_dns_record(zid, rid, params))
-        .collect();
-
-    let results: Vec<_> = futures::future::join_all(requests).await;
+/// Print the result of each address source to stdout
+///
+/// Fails if any source failed.
+async fn validate_ip_sources(config: &Config) -> Result<(), UpdateError> {
+    let probes: Vec<SourceProbe> = probe_sources(config).await;
+    if probes.is_empty() {
+        return Err(UpdateError::Detection(anyhow::anyhow!(
+            "No address sources configured"
+        )));
+    }
 
-    for result in results {
-        if let Err(e) = result {
-            log::error!("Failed to update record for zone '{zone_name}': {e:?}");
-            errors = errors.saturating_add(1);
+    let mut failures: usize = 0;
+    for probe in &probes {
+        match &probe.result {
+            Ok(ip) => println!("{}: {ip}", probe.source),
+            Err(e) => {
+                failures = failures.saturating_add(1);
+                println!("{}: error: {e:#}", probe.source);
+            }
         }
     }
 
-    if errors > 0 {
-        anyhow::bail!("Failed to update {errors} records");
+    if failures > 0 {
+        return Err(UpdateError::Detection(anyhow::anyhow!(
+            "{failures} of {} address sources failed",
+            probes.len()
+        )));
     }
 
     Ok(())
 }
 
-async fn inner() -> anyhow::Result<()> {
-    let config: Config = Config::from_args_os()?;
+async fn inner() -> Result<(), Failure> {
+    let mut config: Config = Config::from_args_os().map_err(Failure::config)?;
+
+    if config.validate_ip_sources {
+        return Ok(validate_ip_sources(&config).await?);
+    }
+
+    if let Some(format) = config.print_ip {
+        let addresses: Addresses = detect_with_retries(&config)
+            .await
+            .map_err(UpdateError::Detection)?;
+        print_addresses(&addresses, config.prefix_len, format);
+        return Ok(());
+    }
 
     if config.zones.is_empty() {
         log::warn!("No zones specified in configuration");
         return Ok(());
     }
 
-    let ipv4: Option<Ipv4Addr> = {
-        if let Some(iface) = config.a_interface {
-            Some(interface_ipv4(&iface)?)
-        } else if let Some(url) = config.a_http {
-            Some(http_get_ipv4(url).await?)
-        } else {
-            None
-        }
-    };
-
-    let ipv6_prefix: Option<Ipv6Addr> = {
-        if let Some(iface) = config.aaaa_interface {
-            Some(interface_ipv6_prefix(&iface)?)
-        } else if let Some(url) = config.aaaa_http {
-            Some(http_get_ipv6_prefix(url).await?)
-        } else {
-            None
-        }
-    };
-
-    if ipv4.is_none() && ipv6_prefix.is_none() {
+    if !config.ipv4_enabled() && !config.ipv6_enabled() {
         log::warn!("Both IPv4 and IPv6 disabled in configuration");
         return Ok(());
     }
 
-    let new_ipv4: Option<Ipv4Addr> = match (ipv4, config.history.ipv4) {
-        (None, _) => None,
-        (Some(ip), None) => {
-            log::warn!("Previous IPv4 unknown, updating to {ip}");
-            Some(ip)
-        }
-        (Some(ip), Some(prev)) => {
-            if ip == prev {
-                log::info!("IPv4 unchanged, skipping update");
-                None
-            } else {
-                log::warn!("IPv4 changed from {prev} to {ip}");
-                Some(ip)
-            }
-        }
-    };
+    let clients: Clients = config.cloudflare_clients().map_err(Failure::config)?;
+    let mut history: History = std::mem::take(&mut config.history);
+    let mut metrics: Metrics = Metrics::default();
 
-    let new_ipv6_prefix: Option<Ipv6Addr> = match (ipv6_prefix, config.history.ipv6_prefix) {
-        (None, _) => None,
-        (Some(prefix), None) => {
-            log::warn!("Previous IPv6 prefix unknown, updating to {prefix}");
-            Some(prefix)
-        }
-        (Some(prefix), Some(prev)) => {
-            if prefix == prev {
-                log::info!("IPv6 prefix unchanged, skipping update");
-                None
-            } else {
-                log::warn!("IPv6 prefix changed from {prev} to {prefix}");
-                Some(prefix)
-            }
-        }
+    let Some(interval_secs) = config.interval_secs else {
+        return Ok(update_and_ping(&config, &clients, &mut history, &mut metrics).await?);
     };
 
-    if new_ipv4.is_none() && new_ipv6_prefix.is_none() {
-        return Ok(());
-    }
+    // installed before the first update so signals received mid-update are
+    // handled once the update finishes
+    let mut sigterm: Signal = signal(SignalKind::terminate())
+        .context("Failed to install SIGTERM handler")
+        .map_err(Failure::other)?;
+    let mut sigint: Signal = signal(SignalKind::interrupt())
+        .context("Failed to install SIGINT handler")
+        .map_err(Failure::other)?;
 
-    let zone_updates: Vec<_> = config
-        .zones
-        .iter()
-        .map(|zone| update_zone(&config.cloudflare_client, zone, new_ipv4, new_ipv6_prefix))
-        .collect();
+    log::info!("Updating every {interval_secs} seconds");
 
-    let results: Vec<anyhow::Result<()>> = futures::future::join_all(zone_updates).await;
+    loop {
+        if let Err(e) = update_and_ping(&config, &clients, &mut history, &mut metrics).await {
+            log::error!("{e:#}");
+        }
 
-    let mut errors: u32 = 0;
-    for result in results {
-        if let Err(e) = result {
-            log::error!("Failed to update zone: {e:?}");
-            errors = errors.saturating_add(1);
+        tokio::select! {
+            _ = tokio::time::sleep(config.next_interval().unwrap_or_default()) => (),
+            _ = sigterm.recv() => break,
+            _ = sigint.recv() => break,
         }
     }
 
-    if errors > 0 {
-        anyhow::bail!("Failed to update {errors} zones");
+    log::info!("Shutting down");
+
+    if !config.dry_run
+        && let Some(history_path) = &config.history_path
+    {
+        save_history(history_path, &history)
+            .context("Failed to save history")
+            .map_err(UpdateError::Update)?;
     }
 
-    save_history(
-        &config.history_path,
-        History {
-            ipv4: new_ipv4,
-            ipv6_prefix: new_ipv6_prefix,
-        },
-    )
-    .context("Failed to save history")
+    Ok(())
 }
 
 #[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
-    let result = inner().await;
-    if let Err(e) = &result {
-        log::error!("{e:#}");
+async fn main() -> ExitCode {
+    match inner().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure { code, error }) => {
+            log::error!("{error:#}");
+            eprintln!("Error: {error:?}");
+            ExitCode::from(code)
+        }
     }
-    result
 }
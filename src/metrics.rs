@@ -0,0 +1,209 @@
+//! Prometheus metrics for the node_exporter textfile collector
+
+use crate::{config::write_atomic, report::RunReport};
+use anyhow::Context as _;
+use std::{
+    fmt::{self, Write as _},
+    path::Path,
+};
+
+/// Metrics accumulated across runs of a single process
+#[derive(Debug, Default)]
+pub struct Metrics {
+    last_run: Option<u64>,
+    last_success: Option<u64>,
+    runs_total: u64,
+    run_failures_total: u64,
+    zone_update_errors_total: u64,
+    records_updated_total: u64,
+    ipv4_changed: bool,
+    ipv6_changed: bool,
+}
+
+impl Metrics {
+    /// Add the outcome of a finished run
+    ///
+    /// `ipv4_changed` and `ipv6_changed` are true if the run changed the
+    /// address recorded in history.
+    pub fn record(&mut self, report: &RunReport, ipv4_changed: bool, ipv6_changed: bool) {
+        self.last_run = report.finished;
+        self.runs_total = self.runs_total.saturating_add(1);
+        if report.success {
+            self.last_success = report.finished;
+        } else {
+            self.run_failures_total = self.run_failures_total.saturating_add(1);
+        }
+
+        let zone_errors: usize = report
+            .zones
+            .iter()
+            .filter(|zone| zone.error.is_some())
+            .count();
+        let records_updated: usize = report.zones.iter().map(|zone| zone.records.len()).sum();
+        self.zone_update_errors_total = self
+            .zone_update_errors_total
+            .saturating_add(zone_errors as u64);
+        self.records_updated_total = self
+            .records_updated_total
+            .saturating_add(records_updated as u64);
+
+        self.ipv4_changed = ipv4_changed;
+        self.ipv6_changed = ipv6_changed;
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out: String = String::new();
+        // writing to a String is infallible
+        let _ = self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) -> fmt::Result {
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+            writeln!(out, "# HELP {name} {help}")?;
+            writeln!(out, "# TYPE {name} {kind}")?;
+            for (labels, value) in samples {
+                writeln!(out, "{name}{labels} {value}")?;
+            }
+            Ok::<(), fmt::Error>(())
+        };
+
+        if let Some(last_run) = self.last_run {
+            metric(
+                "cfddns_last_run_timestamp",
+                "gauge",
+                "Unix time of the last run",
+                &[("", last_run)],
+            )?;
+        }
+        if let Some(last_success) = self.last_success {
+            metric(
+                "cfddns_last_success_timestamp",
+                "gauge",
+                "Unix time of the last successful run",
+                &[("", last_success)],
+            )?;
+        }
+        metric(
+            "cfddns_runs_total",
+            "counter",
+            "Number of runs",
+            &[("", self.runs_total)],
+        )?;
+        metric(
+            "cfddns_run_failures_total",
+            "counter",
+            "Number of failed runs",
+            &[("", self.run_failures_total)],
+        )?;
+        metric(
+            "cfddns_zone_update_errors_total",
+            "counter",
+            "Number of zones that failed to update",
+            &[("", self.zone_update_errors_total)],
+        )?;
+        metric(
+            "cfddns_records_updated_total",
+            "counter",
+            "Number of records created or updated",
+            &[("", self.records_updated_total)],
+        )?;
+        metric(
+            "cfddns_ip_changed",
+            "gauge",
+            "1 if the last run changed the address",
+            &[
+                ("{family=\"v4\"}", u64::from(self.ipv4_changed)),
+                ("{family=\"v6\"}", u64::from(self.ipv6_changed)),
+            ],
+        )
+    }
+}
+
+/// Save metrics to a file
+pub fn save_metrics(path: &Path, metrics: &Metrics) -> anyhow::Result<()> {
+    write_atomic(path, metrics.render().as_bytes()).context("Failed to write metrics to file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cloudflare::DnsContent,
+        report::{RecordAction, RecordReport, ZoneReport},
+    };
+    use std::{collections::HashMap, path::PathBuf};
+
+    /// Parse samples of the text exposition format, keyed by name and labels
+    fn parse(text: &str) -> HashMap<String, u64> {
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, value) = line.rsplit_once(' ').unwrap();
+                (name.to_string(), value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn textfile() {
+        let record: RecordReport = RecordReport {
+            name: "www.example.com".to_string(),
+            action: RecordAction::Update,
+            previous: None,
+            content: DnsContent::A {
+                content: "192.0.2.2".parse().unwrap(),
+            },
+        };
+        let report: RunReport = RunReport {
+            finished: Some(1000),
+            success: false,
+            error: Some("Failed to update 1 zones".to_string()),
+            zones: vec![
+                ZoneReport {
+                    zone: "example.com".to_string(),
+                    records: vec![record],
+                    error: None,
+                },
+                ZoneReport {
+                    zone: "example.org".to_string(),
+                    records: Vec::new(),
+                    error: Some("simulated failure".to_string()),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let mut metrics: Metrics = Metrics::default();
+        metrics.record(&report, true, false);
+
+        let dir: tempfile::TempDir = tempfile::TempDir::new().unwrap();
+        let path: PathBuf = dir.path().join("cfddns.prom");
+        save_metrics(&path, &metrics).unwrap();
+        let samples = parse(&std::fs::read_to_string(&path).unwrap());
+
+        assert_eq!(samples["cfddns_last_run_timestamp"], 1000);
+        assert!(!samples.contains_key("cfddns_last_success_timestamp"));
+        assert_eq!(samples["cfddns_runs_total"], 1);
+        assert_eq!(samples["cfddns_run_failures_total"], 1);
+        assert_eq!(samples["cfddns_zone_update_errors_total"], 1);
+        assert_eq!(samples["cfddns_records_updated_total"], 1);
+        assert_eq!(samples["cfddns_ip_changed{family=\"v4\"}"], 1);
+        assert_eq!(samples["cfddns_ip_changed{family=\"v6\"}"], 0);
+
+        let report: RunReport = RunReport {
+            finished: Some(2000),
+            success: true,
+            ..Default::default()
+        };
+        metrics.record(&report, false, false);
+        let samples = parse(&metrics.render());
+
+        assert_eq!(samples["cfddns_last_success_timestamp"], 2000);
+        assert_eq!(samples["cfddns_runs_total"], 2);
+        assert_eq!(samples["cfddns_run_failures_total"], 1);
+        assert_eq!(samples["cfddns_zone_update_errors_total"], 1);
+        assert_eq!(samples["cfddns_ip_changed{family=\"v4\"}"], 0);
+    }
+}
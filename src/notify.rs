@@ -0,0 +1,306 @@
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Message sent to chat providers if no template is configured
+pub const DEFAULT_NOTIFY_TEMPLATE: &str = "cfddns: IPv4 changed from {old_ipv4} to {new_ipv4}, \
+    IPv6 prefix changed from {old_ipv6_prefix} to {new_ipv6_prefix}, \
+    IPv6 address changed from {old_ipv6_address} to {new_ipv6_address}";
+
+/// Chat provider receiving IP change notifications
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyKind {
+    Slack,
+    Discord,
+}
+
+/// Chat notification for public IP changes
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub kind: NotifyKind,
+    /// Incoming webhook URL of the provider
+    pub url: url::Url,
+    /// Message with `{old_ipv4}`, `{new_ipv4}`, `{old_ipv6_prefix}`,
+    /// `{new_ipv6_prefix}`, `{old_ipv6_address}`, and `{new_ipv6_address}`
+    /// placeholders, [`DEFAULT_NOTIFY_TEMPLATE`] if None
+    pub template: Option<String>,
+}
+
+/// Webhook payload for a public IP change
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct IpChange {
+    pub old_ipv4: Option<Ipv4Addr>,
+    pub new_ipv4: Option<Ipv4Addr>,
+    pub old_ipv6_prefix: Option<Ipv6Addr>,
+    pub new_ipv6_prefix: Option<Ipv6Addr>,
+    pub old_ipv6_address: Option<Ipv6Addr>,
+    pub new_ipv6_address: Option<Ipv6Addr>,
+}
+
+async fn post_webhook(
+    client: &reqwest::Client,
+    url: &url::Url,
+    change: &IpChange,
+) -> anyhow::Result<()> {
+    client
+        .post(url.clone())
+        .json(change)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to POST {url}"))?;
+
+    Ok(())
+}
+
+/// POST an IP change to a webhook
+///
+/// Failures are logged and otherwise ignored.
+pub async fn webhook(client: &reqwest::Client, url: &url::Url, change: &IpChange) {
+    if let Err(e) = post_webhook(client, url, change).await {
+        log::error!("Failed to send IP change notification: {e:#}");
+    }
+}
+
+/// Substitute the placeholders of a template, absent addresses become `none`
+fn render(template: &str, change: &IpChange) -> String {
+    fn or_none(ip: Option<impl std::fmt::Display>) -> String {
+        ip.map_or_else(|| "none".to_string(), |ip| ip.to_string())
+    }
+
+    template
+        .replace("{old_ipv4}", &or_none(change.old_ipv4))
+        .replace("{new_ipv4}", &or_none(change.new_ipv4))
+        .replace("{old_ipv6_prefix}", &or_none(change.old_ipv6_prefix))
+        .replace("{new_ipv6_prefix}", &or_none(change.new_ipv6_prefix))
+        .replace("{old_ipv6_address}", &or_none(change.old_ipv6_address))
+        .replace("{new_ipv6_address}", &or_none(change.new_ipv6_address))
+}
+
+/// Provider-compatible body for a message
+fn chat_payload(kind: NotifyKind, text: String) -> serde_json::Value {
+    match kind {
+        NotifyKind::Slack => serde_json::json!({ "text": text }),
+        NotifyKind::Discord => serde_json::json!({ "content": text }),
+    }
+}
+
+async fn post_chat(
+    client: &reqwest::Client,
+    notify: &NotifyConfig,
+    change: &IpChange,
+) -> anyhow::Result<()> {
+    let template: &str = notify
+        .template
+        .as_deref()
+        .unwrap_or(DEFAULT_NOTIFY_TEMPLATE);
+
+    client
+        .post(notify.url.clone())
+        .json(&chat_payload(notify.kind, render(template, change)))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to POST {}", notify.url))?;
+
+    Ok(())
+}
+
+/// Send an IP change to a Slack or Discord webhook
+///
+/// Failures are logged and otherwise ignored.
+pub async fn chat(client: &reqwest::Client, notify: &NotifyConfig, change: &IpChange) {
+    if let Err(e) = post_chat(client, notify, change).await {
+        log::error!("Failed to send IP change chat notification: {e:#}");
+    }
+}
+
+/// Healthchecks.io style URL to signal a failure
+fn fail_url(url: &url::Url) -> url::Url {
+    let mut url: url::Url = url.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().push("fail");
+    }
+    url
+}
+
+async fn get_healthcheck(client: &reqwest::Client, url: &url::Url) -> anyhow::Result<()> {
+    client
+        .get(url.clone())
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to GET {url}"))?;
+
+    Ok(())
+}
+
+/// Ping a healthcheck URL, or `<url>/fail` if the run failed
+///
+/// Failures are logged and otherwise ignored.
+pub async fn healthcheck(client: &reqwest::Client, url: &url::Url, success: bool) {
+    let url: url::Url = if success { url.clone() } else { fail_url(url) };
+
+    if let Err(e) = get_healthcheck(client, &url).await {
+        log::error!("Failed to ping healthcheck: {e:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+    #[tokio::test]
+    async fn webhook_payload() {
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/hook"))
+            .and(matchers::body_json(serde_json::json!({
+                "old_ipv4": "192.0.2.1",
+                "new_ipv4": "192.0.2.2",
+                "old_ipv6_prefix": null,
+                "new_ipv6_prefix": "2001:db8::",
+                "old_ipv6_address": null,
+                "new_ipv6_address": "2001:db8::1",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url: url::Url = format!("{}/hook", server.uri()).parse().unwrap();
+        let change: IpChange = IpChange {
+            old_ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            new_ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+            old_ipv6_prefix: None,
+            new_ipv6_prefix: Some("2001:db8::".parse().unwrap()),
+            old_ipv6_address: None,
+            new_ipv6_address: Some("2001:db8::1".parse().unwrap()),
+        };
+
+        post_webhook(&reqwest::Client::new(), &url, &change)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn webhook_failure() {
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let url: url::Url = server.uri().parse().unwrap();
+        let change: IpChange = IpChange {
+            old_ipv4: None,
+            new_ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+            old_ipv6_prefix: None,
+            new_ipv6_prefix: None,
+            old_ipv6_address: None,
+            new_ipv6_address: None,
+        };
+
+        assert!(
+            post_webhook(&reqwest::Client::new(), &url, &change)
+                .await
+                .is_err()
+        );
+
+        // errors are only logged
+        webhook(&reqwest::Client::new(), &url, &change).await;
+    }
+
+    #[tokio::test]
+    async fn chat_payloads() {
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/slack"))
+            .and(matchers::body_json(serde_json::json!({
+                "text": "IPv4 192.0.2.1 -> 192.0.2.2, IPv6 none -> 2001:db8::",
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/discord"))
+            .and(matchers::body_json(serde_json::json!({
+                "content": "cfddns: IPv4 changed from 192.0.2.1 to 192.0.2.2, \
+                    IPv6 prefix changed from none to 2001:db8::, \
+                    IPv6 address changed from 2001:db8::1 to 2001:db8::2",
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let change: IpChange = IpChange {
+            old_ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            new_ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+            old_ipv6_prefix: None,
+            new_ipv6_prefix: Some("2001:db8::".parse().unwrap()),
+            old_ipv6_address: Some("2001:db8::1".parse().unwrap()),
+            new_ipv6_address: Some("2001:db8::2".parse().unwrap()),
+        };
+        let client: reqwest::Client = reqwest::Client::new();
+
+        let slack: NotifyConfig = serde_json::from_value(serde_json::json!({
+            "kind": "slack",
+            "url": format!("{}/slack", server.uri()),
+            "template": "IPv4 {old_ipv4} -> {new_ipv4}, IPv6 {old_ipv6_prefix} -> {new_ipv6_prefix}",
+        }))
+        .unwrap();
+        post_chat(&client, &slack, &change).await.unwrap();
+
+        let discord: NotifyConfig = serde_json::from_value(serde_json::json!({
+            "kind": "discord",
+            "url": format!("{}/discord", server.uri()),
+        }))
+        .unwrap();
+        post_chat(&client, &discord, &change).await.unwrap();
+    }
+
+    #[test]
+    fn healthcheck_fail_url() {
+        let url: url::Url = "https://hc-ping.com/uuid".parse().unwrap();
+        assert_eq!(fail_url(&url).as_str(), "https://hc-ping.com/uuid/fail");
+
+        let url: url::Url = "https://hc-ping.com/uuid/".parse().unwrap();
+        assert_eq!(fail_url(&url).as_str(), "https://hc-ping.com/uuid/fail");
+    }
+
+    #[tokio::test]
+    async fn healthcheck_ping() {
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/uuid"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/uuid/fail"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url: url::Url = format!("{}/uuid", server.uri()).parse().unwrap();
+        let client: reqwest::Client = reqwest::Client::new();
+
+        healthcheck(&client, &url, true).await;
+        healthcheck(&client, &url, false).await;
+    }
+}
@@ -0,0 +1,153 @@
+//! Machine-readable summary of an update run
+
+use crate::{UpdateError, cloudflare::DnsContent, config::write_atomic};
+use anyhow::Context as _;
+use serde::Serialize;
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What a run did, written to `report_path`
+#[derive(Serialize, Debug, Default)]
+pub struct RunReport {
+    /// Unix time in seconds when the run finished
+    pub finished: Option<u64>,
+    pub success: bool,
+    pub error: Option<String>,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6_prefix: Option<Ipv6Addr>,
+    pub ipv6_address: Option<Ipv6Addr>,
+    /// Zones that were updated, empty if the addresses are unchanged
+    pub zones: Vec<ZoneReport>,
+}
+
+impl RunReport {
+    /// Record the outcome of the run
+    pub fn finish(&mut self, result: &Result<(), UpdateError>) {
+        self.finished = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|since_epoch| since_epoch.as_secs());
+        self.success = result.is_ok();
+        self.error = result.as_ref().err().map(|e| format!("{e:#}"));
+    }
+
+    /// One line summary of the run for logs
+    ///
+    /// `ipv4_changed` and `ipv6_changed` are true if the run changed the
+    /// address recorded in history.
+    pub fn summary(&self, ipv4_changed: bool, ipv6_changed: bool) -> String {
+        fn family(name: &str, ip: Option<impl fmt::Display>, changed: bool) -> String {
+            match ip {
+                Some(ip) if changed => format!("{name} {ip} (changed)"),
+                Some(_) => format!("{name} unchanged"),
+                None => format!("{name} none"),
+            }
+        }
+
+        let records: usize = self.zones.iter().map(|zone| zone.records.len()).sum();
+        let zone_errors: usize = self
+            .zones
+            .iter()
+            .filter(|zone| zone.error.is_some())
+            .count();
+        // failures outside of zones, such as detection, count as one error
+        let errors: usize = match zone_errors {
+            0 if self.error.is_some() => 1,
+            errors => errors,
+        };
+
+        format!(
+            "cfddns: {} zones, {records} records updated, {}, {}, {errors} errors",
+            self.zones.len(),
+            family("ipv4", self.ipv4, ipv4_changed),
+            family("ipv6", self.ipv6_prefix.or(self.ipv6_address), ipv6_changed),
+        )
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ZoneReport {
+    /// Zone name, or identifier if the zone has no name
+    pub zone: String,
+    /// Records created or updated in the zone
+    pub records: Vec<RecordReport>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordAction {
+    Create,
+    Update,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RecordReport {
+    pub name: String,
+    pub action: RecordAction,
+    /// Content before the update, None if unknown or created
+    pub previous: Option<DnsContent>,
+    pub content: DnsContent,
+}
+
+/// Save a report to a file
+pub fn save_report(path: &Path, report: &RunReport) -> anyhow::Result<()> {
+    let json: Vec<u8> = serde_json::to_vec_pretty(report).context("Failed to serialize report")?;
+    write_atomic(path, &json).context("Failed to write report to file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str) -> RecordReport {
+        RecordReport {
+            name: name.to_string(),
+            action: RecordAction::Update,
+            previous: None,
+            content: DnsContent::A {
+                content: "203.0.113.5".parse().unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let report: RunReport = RunReport {
+            success: true,
+            ipv4: Some("203.0.113.5".parse().unwrap()),
+            ipv6_prefix: Some("2001:db8::".parse().unwrap()),
+            zones: vec![
+                ZoneReport {
+                    zone: "example.com".to_string(),
+                    records: vec![record("www.example.com"), record("example.com")],
+                    error: None,
+                },
+                ZoneReport {
+                    zone: "example.org".to_string(),
+                    records: vec![record("www.example.org")],
+                    error: None,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            report.summary(true, false),
+            "cfddns: 2 zones, 3 records updated, ipv4 203.0.113.5 (changed), \
+            ipv6 unchanged, 0 errors"
+        );
+
+        let report: RunReport = RunReport {
+            error: Some("Failed to detect IPv4".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            report.summary(false, false),
+            "cfddns: 0 zones, 0 records updated, ipv4 none, ipv6 none, 1 errors"
+        );
+    }
+}
@@ -0,0 +1,533 @@
+//! End-to-end updates against a mock Cloudflare API
+
+use cfddns::{
+    UpdateError,
+    cloudflare::DnsContent,
+    config::{Clients, Config, ConfigBuilder, History, ZoneConfig},
+    report::{RecordReport, RunReport},
+    update,
+};
+use std::collections::HashMap;
+use tempfile::TempDir;
+use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+const RECORD_ID: &str = "372e67954025e0ba6aaa6d586b9e0b59";
+
+/// Mock Cloudflare API with a single zone
+struct MockCloudflare {
+    server: MockServer,
+    dir: TempDir,
+}
+
+impl MockCloudflare {
+    async fn start() -> MockCloudflare {
+        let server: MockServer = MockServer::start().await;
+
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/client/v4/zones"))
+            .and(matchers::query_param("name", "example.com"))
+            .and(matchers::header("authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": [{"id": ZONE_ID, "name": "example.com"}],
+                "result_info": null,
+            })))
+            .mount(&server)
+            .await;
+
+        let dir: TempDir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("token"), "token\n").unwrap();
+
+        MockCloudflare { server, dir }
+    }
+
+    /// Stub listing the zone's DNS records
+    async fn records(&self, records: serde_json::Value) {
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path(format!(
+                "/client/v4/zones/{ZONE_ID}/dns_records"
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": records,
+                "result_info": null,
+            })))
+            .expect(1)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Expect `count` updates of the record to `content`
+    async fn expect_update(&self, content: &str, count: u64) {
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path(format!(
+                "/client/v4/zones/{ZONE_ID}/dns_records/{RECORD_ID}"
+            )))
+            .and(matchers::body_partial_json(serde_json::json!({
+                "name": "www.example.com",
+                "type": "A",
+                "content": content,
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": {
+                    "id": RECORD_ID,
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": content
+                },
+                "result_info": null,
+            })))
+            .expect(count)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Configuration updating `records` to `ipv4`
+    fn config(&self, ipv4: &str, records: &[&str]) -> Config {
+        let records: Vec<serde_json::Value> = records
+            .iter()
+            .map(|name| serde_json::json!({"name": name}))
+            .collect();
+        let zones: Vec<ZoneConfig> = serde_json::from_value(serde_json::json!([
+            {"name": "example.com", "records": records}
+        ]))
+        .unwrap();
+
+        ConfigBuilder::new()
+            .a_command(vec!["echo".to_string(), ipv4.to_string()])
+            .zones(zones)
+            .history_path(self.dir.path().join("history.json"))
+            .token_path(self.dir.path().join("token"))
+            .cloudflare_base_url(format!("{}/client/v4/", self.server.uri()).parse().unwrap())
+            .build()
+            .unwrap()
+    }
+
+    async fn update(&self, ipv4: &str, records: &[&str]) -> (Result<(), UpdateError>, RunReport) {
+        let config: Config = self.config(ipv4, records);
+        let clients: Clients = config.cloudflare_clients().unwrap();
+        let mut report: RunReport = RunReport::default();
+        let result = update(&config, &clients, &mut History::default(), &mut report).await;
+        (result, report)
+    }
+}
+
+fn record(content: &str) -> serde_json::Value {
+    serde_json::json!([{
+        "id": RECORD_ID,
+        "name": "www.example.com",
+        "type": "A",
+        "content": content
+    }])
+}
+
+#[tokio::test]
+async fn unchanged_record() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare.records(record("192.0.2.1")).await;
+    cloudflare.expect_update("192.0.2.1", 0).await;
+
+    let (result, report) = cloudflare.update("192.0.2.1", &["www.example.com"]).await;
+    result.unwrap();
+    assert!(report.zones[0].records.is_empty());
+}
+
+#[tokio::test]
+async fn changed_record() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare.records(record("192.0.2.1")).await;
+    cloudflare.expect_update("192.0.2.2", 1).await;
+
+    let (result, report) = cloudflare.update("192.0.2.2", &["www.example.com"]).await;
+    result.unwrap();
+    assert_eq!(report.zones[0].records.len(), 1);
+    assert_eq!(report.zones[0].records[0].name, "www.example.com");
+}
+
+#[tokio::test]
+async fn without_history_path() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare.records(record("192.0.2.1")).await;
+    cloudflare.expect_update("192.0.2.2", 1).await;
+
+    let mut config: Config = cloudflare.config("192.0.2.2", &["www.example.com"]);
+    config.history_path = None;
+    let clients: Clients = config.cloudflare_clients().unwrap();
+
+    let mut history: History = History::default();
+    let mut report: RunReport = RunReport::default();
+    update(&config, &clients, &mut history, &mut report)
+        .await
+        .unwrap();
+
+    // history is kept in memory, only the token is in the directory
+    assert_eq!(history.ipv4, Some("192.0.2.2".parse().unwrap()));
+    let files: Vec<std::ffi::OsString> = std::fs::read_dir(cloudflare.dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files, ["token"]);
+}
+
+#[tokio::test]
+async fn prefix_drift_below_significant_len() {
+    const AAAA_RECORD_ID: &str = "9a7806061c88ada191ed06f989cc3dac";
+
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare
+        .records(serde_json::json!([
+            {
+                "id": RECORD_ID,
+                "name": "www.example.com",
+                "type": "A",
+                "content": "192.0.2.1"
+            },
+            {
+                "id": AAAA_RECORD_ID,
+                "name": "www.example.com",
+                "type": "AAAA",
+                "content": "2001:db8:0:100::1"
+            }
+        ]))
+        .await;
+    cloudflare.expect_update("192.0.2.2", 1).await;
+    cloudflare.expect_update("192.0.2.3", 1).await;
+
+    Mock::given(matchers::method("PUT"))
+        .and(matchers::path(format!(
+            "/client/v4/zones/{ZONE_ID}/dns_records/{AAAA_RECORD_ID}"
+        )))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&cloudflare.server)
+        .await;
+
+    let published: std::net::Ipv6Addr = "2001:db8:0:100::".parse().unwrap();
+    let mut history: History = History {
+        ipv4: Some("192.0.2.1".parse().unwrap()),
+        ipv6_prefix: Some(published),
+        ..Default::default()
+    };
+
+    // each run changes the IPv4 address and the prefix below the /56
+    for (ipv4, prefix) in [
+        ("192.0.2.2", "2001:db8:0:101::"),
+        ("192.0.2.3", "2001:db8:0:1ff::"),
+    ] {
+        let zones: Vec<ZoneConfig> = serde_json::from_value(serde_json::json!([
+            {"name": "example.com", "records": [{"name": "www.example.com", "suffix": "::1"}]}
+        ]))
+        .unwrap();
+        let config: Config = ConfigBuilder::new()
+            .a_command(vec!["echo".to_string(), ipv4.to_string()])
+            .aaaa_command(vec!["echo".to_string(), prefix.to_string()])
+            .ipv6_prefix_significant_len(56)
+            .zones(zones)
+            .history_path(cloudflare.dir.path().join("history.json"))
+            .token_path(cloudflare.dir.path().join("token"))
+            .cloudflare_base_url(
+                format!("{}/client/v4/", cloudflare.server.uri())
+                    .parse()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let clients: Clients = config.cloudflare_clients().unwrap();
+
+        let mut report: RunReport = RunReport::default();
+        update(&config, &clients, &mut history, &mut report)
+            .await
+            .unwrap();
+
+        assert_eq!(history.ipv4, Some(ipv4.parse().unwrap()));
+        assert_eq!(history.ipv6_prefix, Some(published));
+    }
+}
+
+#[tokio::test]
+async fn notify_ipv6_address_change() {
+    const AAAA_RECORD_ID: &str = "9a7806061c88ada191ed06f989cc3dac";
+
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare
+        .records(serde_json::json!([{
+            "id": AAAA_RECORD_ID,
+            "name": "www.example.com",
+            "type": "AAAA",
+            "content": "2001:db8::1"
+        }]))
+        .await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/ipv6"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("2001:db8::2"))
+        .mount(&cloudflare.server)
+        .await;
+
+    Mock::given(matchers::method("PUT"))
+        .and(matchers::path(format!(
+            "/client/v4/zones/{ZONE_ID}/dns_records/{AAAA_RECORD_ID}"
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": {
+                "id": AAAA_RECORD_ID,
+                "name": "www.example.com",
+                "type": "AAAA",
+                "content": "2001:db8::2"
+            },
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&cloudflare.server)
+        .await;
+
+    Mock::given(matchers::method("POST"))
+        .and(matchers::path("/hook"))
+        .and(matchers::body_partial_json(serde_json::json!({
+            "old_ipv6_address": "2001:db8::1",
+            "new_ipv6_address": "2001:db8::2",
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&cloudflare.server)
+        .await;
+
+    let zones: Vec<ZoneConfig> = serde_json::from_value(serde_json::json!([
+        {"name": "example.com", "records": [{"name": "www.example.com", "full_ipv6": true}]}
+    ]))
+    .unwrap();
+    let config: Config = ConfigBuilder::new()
+        .aaaa_http(format!("{}/ipv6", cloudflare.server.uri()).parse().unwrap())
+        .zones(zones)
+        .notify_webhook(format!("{}/hook", cloudflare.server.uri()).parse().unwrap())
+        .history_path(cloudflare.dir.path().join("history.json"))
+        .token_path(cloudflare.dir.path().join("token"))
+        .cloudflare_base_url(
+            format!("{}/client/v4/", cloudflare.server.uri())
+                .parse()
+                .unwrap(),
+        )
+        .build()
+        .unwrap();
+    let clients: Clients = config.cloudflare_clients().unwrap();
+
+    // only the address within the prefix changed
+    let mut history: History = History {
+        ipv6_prefix: Some("2001:db8::".parse().unwrap()),
+        ipv6_address: Some("2001:db8::1".parse().unwrap()),
+        ..Default::default()
+    };
+    let mut report: RunReport = RunReport::default();
+    update(&config, &clients, &mut history, &mut report)
+        .await
+        .unwrap();
+
+    assert_eq!(history.ipv6_address, Some("2001:db8::2".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn missing_record() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare.records(record("192.0.2.1")).await;
+    cloudflare.expect_update("192.0.2.2", 1).await;
+
+    Mock::given(matchers::method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&cloudflare.server)
+        .await;
+
+    let (result, report) = cloudflare
+        .update("192.0.2.2", &["www.example.com", "mail.example.com"])
+        .await;
+    assert_eq!(result.unwrap_err().to_string(), "Failed to update 1 zones");
+    assert_eq!(
+        report.zones[0].error.as_deref(),
+        Some("Failed to update 1 records")
+    );
+}
+
+#[tokio::test]
+async fn empty_zone() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare.records(serde_json::json!([])).await;
+
+    let (result, report) = cloudflare
+        .update("192.0.2.2", &["www.example.com", "mail.example.com"])
+        .await;
+    assert_eq!(result.unwrap_err().to_string(), "Failed to update 1 zones");
+    assert_eq!(
+        report.zones[0].error.as_deref(),
+        Some(
+            "Zone 'example.com' has none of the 2 records to update, \
+            set create_missing to create them"
+        )
+    );
+}
+
+#[tokio::test]
+async fn out_of_band_edit() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare.records(record("192.0.2.9")).await;
+    cloudflare.expect_update("192.0.2.2", 1).await;
+
+    let mut config: Config = cloudflare.config("192.0.2.2", &["www.example.com"]);
+    config.track_records = true;
+    let clients: Clients = config.cloudflare_clients().unwrap();
+
+    let pushed: DnsContent = DnsContent::A {
+        content: "192.0.2.2".parse().unwrap(),
+    };
+    let mut history: History = History {
+        ipv4: Some("192.0.2.2".parse().unwrap()),
+        record_content: HashMap::from([("www.example.com".to_string(), vec![pushed.clone()])]),
+        ..Default::default()
+    };
+
+    let mut report: RunReport = RunReport::default();
+    update(&config, &clients, &mut history, &mut report)
+        .await
+        .unwrap();
+
+    let restored: &RecordReport = &report.zones[0].records[0];
+    assert_eq!(
+        restored.previous,
+        Some(DnsContent::A {
+            content: "192.0.2.9".parse().unwrap()
+        })
+    );
+    assert_eq!(restored.content, pushed);
+    assert_eq!(history.record_content["www.example.com"], [pushed]);
+}
+
+#[tokio::test]
+async fn mixed_case_name() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare
+        .records(serde_json::json!([{
+            "id": RECORD_ID,
+            "name": "WWW.example.com",
+            "type": "A",
+            "content": "192.0.2.1"
+        }]))
+        .await;
+    cloudflare.expect_update("192.0.2.2", 1).await;
+
+    let (result, report) = cloudflare.update("192.0.2.2", &["WWW.Example.com."]).await;
+    result.unwrap();
+    assert_eq!(report.zones[0].records[0].name, "www.example.com");
+}
+
+#[tokio::test]
+async fn apex_record() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    cloudflare
+        .records(serde_json::json!([
+            {
+                "id": "apex-id",
+                "name": "example.com",
+                "type": "A",
+                "content": "192.0.2.1"
+            },
+            {
+                "id": RECORD_ID,
+                "name": "www.example.com",
+                "type": "A",
+                "content": "192.0.2.2"
+            }
+        ]))
+        .await;
+
+    Mock::given(matchers::method("PUT"))
+        .and(matchers::path(format!(
+            "/client/v4/zones/{ZONE_ID}/dns_records/apex-id"
+        )))
+        .and(matchers::body_partial_json(serde_json::json!({
+            "name": "example.com",
+            "content": "192.0.2.2",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": {
+                "id": "apex-id",
+                "name": "example.com",
+                "type": "A",
+                "content": "192.0.2.2"
+            },
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&cloudflare.server)
+        .await;
+
+    let (result, report) = cloudflare.update("192.0.2.2", &["@"]).await;
+    result.unwrap();
+    assert_eq!(report.zones[0].records[0].name, "example.com");
+}
+
+#[tokio::test]
+async fn record_name_list() {
+    let cloudflare: MockCloudflare = MockCloudflare::start().await;
+    let names: [&str; 3] = ["www.example.com", "mail.example.com", "vpn.example.com"];
+
+    let records: Vec<serde_json::Value> = names
+        .iter()
+        .map(|name| {
+            serde_json::json!({
+                "id": format!("{name}-id"),
+                "name": name,
+                "type": "A",
+                "content": "192.0.2.1"
+            })
+        })
+        .collect();
+    cloudflare.records(serde_json::json!(records)).await;
+
+    for name in names {
+        Mock::given(matchers::method("PUT"))
+            .and(matchers::path(format!(
+                "/client/v4/zones/{ZONE_ID}/dns_records/{name}-id"
+            )))
+            .and(matchers::body_partial_json(serde_json::json!({
+                "name": name,
+                "content": "192.0.2.2",
+                "ttl": 300,
+                "proxied": true,
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "result": {
+                    "id": format!("{name}-id"),
+                    "name": name,
+                    "type": "A",
+                    "content": "192.0.2.2"
+                },
+                "result_info": null,
+            })))
+            .expect(1)
+            .mount(&cloudflare.server)
+            .await;
+    }
+
+    let zones: Vec<ZoneConfig> = serde_json::from_value(serde_json::json!([{
+        "name": "example.com",
+        "records": [{"name": names, "ttl": 300, "proxied": true}]
+    }]))
+    .unwrap();
+    let config: Config = ConfigBuilder::new()
+        .a_command(vec!["echo".to_string(), "192.0.2.2".to_string()])
+        .zones(zones)
+        .history_path(cloudflare.dir.path().join("history.json"))
+        .token_path(cloudflare.dir.path().join("token"))
+        .cloudflare_base_url(
+            format!("{}/client/v4/", cloudflare.server.uri())
+                .parse()
+                .unwrap(),
+        )
+        .build()
+        .unwrap();
+    let clients: Clients = config.cloudflare_clients().unwrap();
+
+    let mut report: RunReport = RunReport::default();
+    update(&config, &clients, &mut History::default(), &mut report)
+        .await
+        .unwrap();
+    assert_eq!(report.zones[0].records.len(), 3);
+}
@@ -1,33 +1,57 @@
 use assert_cmd::{Command, cargo::cargo_bin_cmd};
+use predicates::prelude::*;
 use std::io::Write;
-use tempfile::NamedTempFile;
+use tempfile::{NamedTempFile, TempDir};
 
 fn main_bin() -> Command {
-    cargo_bin_cmd!()
+    let mut cmd: Command = cargo_bin_cmd!();
+    // tests compare stderr, which would include a backtrace of errors
+    cmd.env_remove("RUST_BACKTRACE")
+        .env_remove("RUST_LIB_BACKTRACE");
+    cmd
 }
 
 #[test]
 fn no_config_file() {
-    main_bin().assert().stderr(
-        predicates::str::is_match("usage: \\S+cfddns \\[config-file\\.json\\]\n")
-            .unwrap()
-            .count(1),
+    main_bin().env_remove("CFDDNS_CONFIG").assert().stderr(
+        predicates::str::is_match(
+            "usage: \\S+cfddns \\[--version\\] \\[--list-interfaces\\] \\[--dry-run\\] \\[--check\\] \\[--force\\] \\[--once\\] \\[--print-ip\\[=json\\]\\] \\[--validate-ip-sources\\] \\[config-file\\.json\\]\n",
+        )
+        .unwrap()
+        .count(1),
     );
 }
 
+#[test]
+fn version() {
+    for arg in ["--version", "-V"] {
+        main_bin().arg(arg).assert().code(0).stdout(
+            predicates::str::is_match(format!(
+                "^cfddns {}( \\([0-9a-f]+(-dirty)?\\))?\n$",
+                env!("CARGO_PKG_VERSION").replace('.', "\\.")
+            ))
+            .unwrap(),
+        );
+    }
+}
+
 #[test]
 fn bad_config_file() {
     let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
     config_file.write_all(&[0xFF]).unwrap();
     config_file.flush().unwrap();
 
-    main_bin().args([config_file.path()]).assert().stderr(
-        r#"Error: Failed to deserialize config file
+    main_bin()
+        .args([config_file.path()])
+        .assert()
+        .code(2)
+        .stderr(
+            r#"Error: Failed to deserialize config file
 
 Caused by:
     expected value at line 1 column 1
 "#,
-    );
+        );
 
     config_file.close().unwrap();
 }
@@ -53,7 +77,7 @@ fn deny_unknown_fields() {
             r#"Error: Failed to deserialize config file
 
 Caused by:
-    unknown field `some_extra_field`, expected one of"#,
+    some_extra_field: unknown field `some_extra_field`, expected one of"#,
         ));
 
     config_file.close().unwrap();
@@ -64,8 +88,13 @@ fn no_client_secret() {
     const MOCK_CONFIG: &str = r#"{
         "a_interface": "bond-wan",
         "aaaa_interface": "br-lan",
-        "zones": [],
-        "history_path": "",
+        "zones": [
+            {
+                "name": "myzone",
+                "records": []
+            }
+        ],
+        "history_path": "/tmp/rmme_no_client_secret",
         "log_level": "off"
     }"#;
 
@@ -73,7 +102,7 @@ fn no_client_secret() {
     config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
     config_file.flush().unwrap();
 
-    main_bin().args([config_file.path()]).assert().stderr(
+    main_bin().args([config_file.path()]).env_remove("CLOUDFLARE_TOKEN").assert().stderr(
         r#"Error: Failed to read cloudflare API token from environment variable 'CLOUDFLARE_TOKEN'
 
 Caused by:
@@ -107,6 +136,132 @@ fn no_zones_early_return() {
     config_file.close().unwrap();
 }
 
+#[test]
+fn config_from_env() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "zones": [],
+        "history_path": "/tmp/rmme_config_from_env",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .env("CFDDNS_CONFIG", config_file.path())
+        .env("CLOUDFLARE_TOKEN", "AAA")
+        .assert()
+        .code(0);
+
+    // the argument takes precedence over the environment variable
+    main_bin()
+        .args([config_file.path()])
+        .env("CFDDNS_CONFIG", "/nonexistent/cfddns.json")
+        .env("CLOUDFLARE_TOKEN", "AAA")
+        .assert()
+        .code(0);
+
+    main_bin()
+        .env("CFDDNS_CONFIG", "/nonexistent/cfddns.json")
+        .env("CLOUDFLARE_TOKEN", "AAA")
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("/nonexistent/cfddns.json"));
+
+    config_file.close().unwrap();
+}
+
+#[tokio::test]
+async fn dry_run_flag() {
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/client/v4/zones/zone-id/dns_records"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [
+                {
+                    "id": "record-id",
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                }
+            ],
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    for method in ["PUT", "POST", "PATCH", "DELETE"] {
+        Mock::given(matchers::method(method))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+    }
+
+    let history_dir: TempDir = TempDir::new().unwrap();
+    let history_path: std::path::PathBuf = history_dir.path().join("history.json");
+    let config: serde_json::Value = serde_json::json!({
+        "a_command": ["echo", "192.0.2.2"],
+        "zones": [
+            {
+                "id": "zone-id",
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": history_path,
+        "cloudflare_base_url": format!("{}/client/v4", server.uri()),
+        "log_level": "off"
+    });
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    serde_json::to_writer(&mut config_file, &config).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .arg(config_file.path())
+        .arg("--dry-run")
+        .env("CLOUDFLARE_TOKEN", "AAA")
+        .assert()
+        .code(0);
+
+    assert!(!history_path.exists());
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn no_zones_without_client_secret() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "aaaa_interface": "br-lan",
+        "zones": [],
+        "history_path": "/tmp/rmme_no_zones_without_client_secret",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .env_remove("CLOUDFLARE_TOKEN")
+        .assert()
+        .code(0);
+
+    config_file.close().unwrap();
+}
+
 #[test]
 fn no_ipv4_or_ipv6_early_return() {
     const MOCK_CONFIG: &str = r#"{
@@ -158,14 +313,930 @@ fn missing_history() {
         .args([config_file.path()])
         .env("CLOUDFLARE_TOKEN", "AAA")
         .assert()
+        .code(2)
+        .stderr("Error: history_path must not be empty\n");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn zone_without_name_or_id() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "aaaa_interface": "br-lan",
+        "zones": [
+            {
+                "records": []
+            }
+        ],
+        "history_path": "/tmp/rmme_zone_without_name_or_id",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .assert()
+        .stderr("Error: Each zone requires a name or an id\n");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn invalid_prefix_len() {
+    const MOCK_CONFIG: &str = r#"{
+        "aaaa_interface": "br-lan",
+        "prefix_len": 129,
+        "zones": [],
+        "history_path": "/tmp/rmme_invalid_prefix_len",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .assert()
+        .stderr("Error: prefix_len must be between 1 and 128, got 129\n");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn zero_interval() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "interval_secs": 0,
+        "zones": [],
+        "history_path": "/tmp/rmme_zero_interval",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .assert()
+        .code(2)
+        .stderr("Error: interval_secs must be at least 1\n");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn config_from_stdin() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "zones": [],
+        "history_path": "/tmp/rmme_config_from_stdin",
+        "log_level": "warn",
+        "log_format": "plain"
+    }"#;
+
+    main_bin()
+        .arg("-")
+        .write_stdin(MOCK_CONFIG)
+        .assert()
+        .code(0)
+        .stderr(predicates::str::ends_with(
+            "WARN  No zones specified in configuration\n",
+        ));
+
+    main_bin()
+        .arg("-")
+        .write_stdin("{")
+        .assert()
+        .code(2)
+        .stderr(predicates::str::starts_with(
+            "Error: Failed to deserialize config file",
+        ));
+}
+
+#[test]
+fn check_valid_config() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "zones": [
+            {
+                "name": "myzone",
+                "records": [
+                    {
+                        "name": "myzone.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": "/tmp/rmme_check_valid_config",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .arg("--check")
+        .arg(config_file.path())
+        .env_remove("CLOUDFLARE_TOKEN")
+        .assert()
+        .code(0)
+        .stdout("config OK\n");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn proxied_record_with_ttl() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "zones": [
+            {
+                "name": "myzone",
+                "records": [
+                    {
+                        "name": "myzone.com",
+                        "proxied": true,
+                        "ttl": 300
+                    },
+                    {
+                        "name": "www.myzone.com",
+                        "proxied": true,
+                        "ttl": "auto"
+                    }
+                ]
+            }
+        ],
+        "history_path": "/tmp/rmme_proxied_record_with_ttl",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .arg("--check")
+        .arg(config_file.path())
+        .env_remove("RUST_LOG")
+        .assert()
+        .code(0)
+        .stdout("config OK\n")
+        .stderr("warning: Record 'myzone.com' is proxied, Cloudflare ignores its TTL of 300\n");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn check_zone_without_records() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "zones": [
+            {
+                "name": "myzone",
+                "records": []
+            }
+        ],
+        "history_path": "/tmp/rmme_check_zone_without_records",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .arg("--check")
+        .arg(config_file.path())
+        .assert()
+        .code(2)
+        .stderr("Error: Zone 'myzone' has no records\n");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn lock_held() {
+    let lock_file: NamedTempFile = NamedTempFile::new().unwrap();
+    lock_file.as_file().lock().unwrap();
+
+    let mock_config: String = format!(
+        r#"{{
+            "a_interface": "bond-wan",
+            "zones": [
+                {{
+                    "name": "myzone",
+                    "records": []
+                }}
+            ],
+            "history_path": "/tmp/rmme_lock_held",
+            "lock_path": "{}",
+            "log_level": "off"
+        }}"#,
+        lock_file.path().display()
+    );
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(mock_config.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    // without the lock this fails due to the missing token
+    main_bin()
+        .args([config_file.path()])
+        .env_remove("CLOUDFLARE_TOKEN")
+        .assert()
+        .code(0);
+
+    lock_file.as_file().unlock().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .env_remove("CLOUDFLARE_TOKEN")
+        .assert()
+        .code(2);
+
+    config_file.close().unwrap();
+    lock_file.close().unwrap();
+}
+
+#[test]
+fn token_from_systemd_credential() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "cfddns-test-missing",
+        "zones": [
+            {
+                "name": "myzone",
+                "records": []
+            }
+        ],
+        "history_path": "/tmp/rmme_token_from_systemd_credential",
+        "credential_name": "cloudflare-token",
+        "log_level": "off"
+    }"#;
+
+    let credentials_dir: TempDir = TempDir::new().unwrap();
+    std::fs::write(credentials_dir.path().join("cloudflare-token"), "AAA").unwrap();
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    // fails on the missing interface after reading the token
+    main_bin()
+        .args([config_file.path()])
+        .env_remove("CLOUDFLARE_TOKEN")
+        .env("CREDENTIALS_DIRECTORY", credentials_dir.path())
+        .assert()
+        .code(3)
+        .stderr(predicates::str::contains("cloudflare API token").not());
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn json_log_format() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "zones": [],
+        "history_path": "/tmp/rmme_json_log_format",
+        "log_level": "warn",
+        "log_format": "json"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    let output = main_bin().args([config_file.path()]).output().unwrap();
+    assert!(output.status.success());
+
+    let stderr: String = String::from_utf8(output.stderr).unwrap();
+    let line: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(line["level"], "WARN");
+    assert_eq!(line["message"], "No zones specified in configuration");
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn rust_log_override() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "bond-wan",
+        "zones": [],
+        "history_path": "/tmp/rmme_rust_log_override",
+        "log_level": "off",
+        "log_format": "plain"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .env_remove("RUST_LOG")
+        .assert()
+        .code(0)
+        .stderr("");
+
+    main_bin()
+        .args([config_file.path()])
+        .env("RUST_LOG", "warn")
+        .assert()
+        .code(0)
+        .stderr("WARN  No zones specified in configuration\n");
+
+    main_bin()
+        .args([config_file.path()])
+        .env("RUST_LOG", "info,reqwest=warn")
+        .assert()
+        .code(0)
+        .stderr("WARN  No zones specified in configuration\n");
+
+    main_bin()
+        .args([config_file.path()])
+        .env("RUST_LOG", "cfddns=loud")
+        .assert()
+        .code(0)
+        .stderr("");
+
+    config_file.close().unwrap();
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file
+        .write_all(MOCK_CONFIG.replace(r#""off""#, r#""warn""#).as_bytes())
+        .unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .env("RUST_LOG", "cfddns=loud")
+        .assert()
+        .code(0)
+        .stderr(
+            "WARN  Ignoring environment variable 'RUST_LOG', using log_level from the \
+            configuration file: Invalid log level in 'cfddns=loud'\n\
+            WARN  No zones specified in configuration\n",
+        );
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn zone_update_failure_exit_code() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_command": ["echo", "192.0.2.1"],
+        "zones": [
+            {
+                "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": "/tmp/rmme_zone_update_failure_exit_code",
+        "max_retries": 0,
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    // the token is invalid, listing records fails
+    main_bin()
+        .args([config_file.path()])
+        .env("CLOUDFLARE_TOKEN", "AAA")
+        .assert()
+        .code(4)
+        .stderr(predicates::str::starts_with(
+            "Error: Failed to update 1 zones",
+        ));
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn sigterm_clean_shutdown() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, ExitStatus, Stdio};
+    use std::time::{Duration, Instant};
+
+    let history_dir: TempDir = TempDir::new().unwrap();
+    let history_path: std::path::PathBuf = history_dir.path().join("history.json");
+
+    // the proxy refuses connections so each update fails quickly
+    let config: String = format!(
+        r#"{{
+            "a_command": ["echo", "192.0.2.1"],
+            "zones": [
+                {{
+                    "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                    "records": [
+                        {{
+                            "name": "www.example.com"
+                        }}
+                    ]
+                }}
+            ],
+            "history_path": "{}",
+            "interval_secs": 3600,
+            "max_retries": 0,
+            "proxy": "http://127.0.0.1:1",
+            "log_level": "info",
+            "log_format": "plain"
+        }}"#,
+        history_path.display()
+    );
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(config.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    let mut child: Child = std::process::Command::new(env!("CARGO_BIN_EXE_cfddns"))
+        .arg(config_file.path())
+        .env("CLOUDFLARE_TOKEN", "AAA")
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // signal handlers are installed before this is logged
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    for line in stderr.lines() {
+        if line.unwrap().contains("Updating every 3600 seconds") {
+            break;
+        }
+    }
+
+    let kill: ExitStatus = std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    let start: Instant = Instant::now();
+    let status: ExitStatus = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("cfddns did not exit after SIGTERM");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    assert!(status.success(), "{status:?}");
+    assert!(history_path.exists());
+
+    config_file.close().unwrap();
+}
+
+#[tokio::test]
+async fn cloudflare_base_url() {
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/client/v4/zones/zone-id/dns_records"))
+        .and(matchers::header("authorization", "Bearer token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [
+                {
+                    "id": "record-id",
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                }
+            ],
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(matchers::method("PUT"))
+        .and(matchers::path(
+            "/client/v4/zones/zone-id/dns_records/record-id",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": {
+                "id": "record-id",
+                "name": "www.example.com",
+                "type": "A",
+                "content": "192.0.2.2"
+            },
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let history_dir: TempDir = TempDir::new().unwrap();
+    let config: serde_json::Value = serde_json::json!({
+        "a_command": ["echo", "192.0.2.2"],
+        "zones": [
+            {
+                "id": "zone-id",
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": history_dir.path().join("history.json"),
+        "cloudflare_base_url": format!("{}/client/v4", server.uri()),
+        "log_level": "off"
+    });
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    serde_json::to_writer(&mut config_file, &config).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args([config_file.path()])
+        .env("CLOUDFLARE_TOKEN", "token")
+        .assert()
+        .code(0);
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn once_flag_overrides_interval() {
+    let history_dir: TempDir = TempDir::new().unwrap();
+
+    // the proxy refuses connections so the update fails quickly
+    let config: serde_json::Value = serde_json::json!({
+        "a_command": ["echo", "192.0.2.1"],
+        "zones": [
+            {
+                "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": history_dir.path().join("history.json"),
+        "interval_secs": 3600,
+        "max_retries": 0,
+        "proxy": "http://127.0.0.1:1",
+        "log_level": "info",
+        "log_format": "plain"
+    });
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    serde_json::to_writer(&mut config_file, &config).unwrap();
+    config_file.flush().unwrap();
+
+    // a single failed update exits with the update failure code
+    main_bin()
+        .args([
+            std::ffi::OsStr::new("--once"),
+            config_file.path().as_os_str(),
+        ])
+        .env("CLOUDFLARE_TOKEN", "AAA")
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .code(4)
+        .stderr(predicates::str::contains("Updating every").not());
+
+    config_file.close().unwrap();
+}
+
+#[tokio::test]
+async fn print_ip() {
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/ipv4"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.1\n"))
+        .mount(&server)
+        .await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/ipv6"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("2001:db8:1:2::1234\n"))
+        .mount(&server)
+        .await;
+
+    // Cloudflare is unreachable, printing addresses must not contact it
+    let history_dir: TempDir = TempDir::new().unwrap();
+    let history_path: std::path::PathBuf = history_dir.path().join("history.json");
+    let config: serde_json::Value = serde_json::json!({
+        "a_http": format!("{}/ipv4", server.uri()),
+        "aaaa_http": format!("{}/ipv6", server.uri()),
+        "zones": [
+            {
+                "id": "zone-id",
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": history_path,
+        "cloudflare_base_url": "http://127.0.0.1:1/client/v4",
+        "log_level": "off"
+    });
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    serde_json::to_writer(&mut config_file, &config).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args(["--print-ip".as_ref(), config_file.path().as_os_str()])
+        .assert()
+        .code(0)
+        .stdout("192.0.2.1\n2001:db8:1:2::/64\n");
+
+    let output = main_bin()
+        .args(["--print-ip=json".as_ref(), config_file.path().as_os_str()])
+        .assert()
+        .code(0)
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&output).unwrap(),
+        serde_json::json!({
+            "ipv4": "192.0.2.1",
+            "ipv6_prefix": "2001:db8:1:2::",
+            "ipv6_address": null,
+        })
+    );
+
+    assert!(!history_path.exists());
+    config_file.close().unwrap();
+}
+
+#[test]
+fn list_interfaces() {
+    main_bin()
+        .arg("--list-interfaces")
+        .env_remove("CFDDNS_CONFIG")
+        .assert()
+        .code(0)
+        .stdout(
+            predicates::str::is_match("(?m)^lo: ipv4 none, ipv6 prefix none$")
+                .unwrap()
+                .count(1),
+        );
+}
+
+#[test]
+fn quiet_unchanged() {
+    let history_dir: TempDir = TempDir::new().unwrap();
+    let history_path: std::path::PathBuf = history_dir.path().join("history.json");
+    std::fs::write(
+        &history_path,
+        r#"{"ipv4": "192.0.2.1", "ipv6_prefix": null}"#,
+    )
+    .unwrap();
+
+    // the address is unchanged, Cloudflare is never contacted
+    let config = |quiet_unchanged: bool| -> NamedTempFile {
+        let config: serde_json::Value = serde_json::json!({
+            "a_command": ["echo", "192.0.2.1"],
+            "zones": [
+                {
+                    "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                    "records": [
+                        {
+                            "name": "www.example.com"
+                        }
+                    ]
+                }
+            ],
+            "history_path": history_path,
+            "cloudflare_base_url": "http://127.0.0.1:1/client/v4",
+            "quiet_unchanged": quiet_unchanged,
+            "log_level": "info",
+            "log_format": "plain"
+        });
+        let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+        serde_json::to_writer(&mut config_file, &config).unwrap();
+        config_file.flush().unwrap();
+        config_file
+    };
+
+    let config_file: NamedTempFile = config(false);
+    main_bin()
+        .arg(config_file.path())
+        .env("CLOUDFLARE_TOKEN", "token")
+        .env_remove("RUST_LOG")
+        .assert()
+        .code(0)
+        .stderr(predicates::str::contains(
+            "INFO  IPv4 unchanged, skipping update\n",
+        ))
+        .stderr(predicates::str::contains("ipv4 unchanged"));
+
+    let config_file: NamedTempFile = config(true);
+    main_bin()
+        .arg(config_file.path())
+        .env("CLOUDFLARE_TOKEN", "token")
+        .env_remove("RUST_LOG")
+        .assert()
+        .code(0)
+        .stderr(predicates::str::contains("unchanged").not());
+}
+
+#[test]
+fn nested_error_path() {
+    let config: serde_json::Value = serde_json::json!({
+        "a_command": ["echo", "192.0.2.1"],
+        "zones": [
+            {
+                "name": "example.com",
+                "records": [{"name": "www.example.com"}]
+            },
+            {
+                "name": "example.org",
+                "records": [
+                    {"name": "www.example.org"},
+                    {"name": "mail.example.org", "proxied": "yes"}
+                ]
+            }
+        ],
+        "history_path": "/tmp/rmme_nested_error_path",
+        "log_level": "off"
+    });
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    serde_json::to_writer(&mut config_file, &config).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .arg(config_file.path())
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains(
+            "Caused by:\n    zones[1].records[1].proxied: invalid type: string \"yes\", expected a boolean",
+        ));
+
+    config_file.close().unwrap();
+}
+
+#[test]
+fn null_history_path() {
+    let dir: TempDir = TempDir::new().unwrap();
+
+    // detection fails after the configuration is loaded
+    let config: serde_json::Value = serde_json::json!({
+        "a_command": ["false"],
+        "zones": [
+            {
+                "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": null,
+        "log_level": "off"
+    });
+    let config_path: std::path::PathBuf = dir.path().join("cfddns.json");
+    std::fs::write(&config_path, config.to_string()).unwrap();
+
+    main_bin()
+        .arg(&config_path)
+        .current_dir(dir.path())
+        .env("CLOUDFLARE_TOKEN", "token")
+        .assert()
+        .code(3);
+
+    let files: Vec<std::ffi::OsString> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files, ["cfddns.json"]);
+}
+
+#[test]
+fn env_substitution() {
+    const MOCK_CONFIG: &str = r#"{
+        "a_interface": "${CFDDNS_TEST_WAN_IFACE}",
+        "zones": [
+            {
+                "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                "records": [
+                    {
+                        "name": "www.example.com"
+                    }
+                ]
+            }
+        ],
+        "history_path": "/tmp/rmme_env_substitution",
+        "log_level": "off"
+    }"#;
+
+    let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+    config_file.write_all(MOCK_CONFIG.as_bytes()).unwrap();
+    config_file.flush().unwrap();
+
+    main_bin()
+        .args(["--print-ip".as_ref(), config_file.path().as_os_str()])
+        .env("CFDDNS_TEST_WAN_IFACE", "cfddns-test0")
+        .assert()
+        .code(3)
+        .stderr(predicates::str::contains(
+            "Interface 'cfddns-test0' not found",
+        ));
+
+    main_bin()
+        .args([config_file.path()])
+        .env_remove("CFDDNS_TEST_WAN_IFACE")
+        .assert()
+        .code(2)
         .stderr(
-            r#"Error: Failed to create initial history file at ''
+            r#"Error: Failed to deserialize config file
 
 Caused by:
-    0: Failed to open history file at '' for writing
-    1: No such file or directory (os error 2)
+    Environment variable 'CFDDNS_TEST_WAN_IFACE' referenced in config file is not set
 "#,
         );
 
     config_file.close().unwrap();
 }
+
+#[tokio::test]
+async fn validate_ip_sources() {
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/ip"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("192.0.2.1\n"))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    // no token is configured, Cloudflare is never contacted
+    let config = |a_command: &[&str]| -> NamedTempFile {
+        let config: serde_json::Value = serde_json::json!({
+            "a_http": format!("{}/ip", server.uri()),
+            "a_command": a_command,
+            "zones": [
+                {
+                    "id": "023e105f4ecef8ad9ca31a8372d0c353",
+                    "records": [
+                        {
+                            "name": "www.example.com"
+                        }
+                    ]
+                }
+            ],
+            "history_path": "/tmp/rmme_validate_ip_sources",
+            "log_level": "off"
+        });
+        let mut config_file: NamedTempFile = NamedTempFile::new().unwrap();
+        serde_json::to_writer(&mut config_file, &config).unwrap();
+        config_file.flush().unwrap();
+        config_file
+    };
+
+    let config_file: NamedTempFile = config(&["echo", "192.0.2.2"]);
+    main_bin()
+        .args([
+            "--validate-ip-sources".as_ref(),
+            config_file.path().as_os_str(),
+        ])
+        .env_remove("CLOUDFLARE_TOKEN")
+        .assert()
+        .code(0)
+        .stdout(format!(
+            "a_http {}/ip: 192.0.2.1\na_command echo 192.0.2.2: 192.0.2.2\n",
+            server.uri()
+        ));
+
+    let config_file: NamedTempFile = config(&["false"]);
+    main_bin()
+        .args([
+            "--validate-ip-sources".as_ref(),
+            config_file.path().as_os_str(),
+        ])
+        .env_remove("CLOUDFLARE_TOKEN")
+        .assert()
+        .code(3)
+        .stdout(predicates::str::contains("a_command false: error: "))
+        .stderr("Error: 1 of 2 address sources failed\n");
+}
@@ -0,0 +1,479 @@
+use cfddns::{
+    Addresses, RecordMaps, ZoneIds,
+    cloudflare::{Client, Credentials},
+    config::{Clients, Config, ConfigBuilder, History, RecordIds, ZoneConfig},
+    report::{RunReport, save_report},
+    update, update_zone, zone_record_map,
+};
+use std::{collections::HashMap, net::Ipv4Addr};
+use tempfile::TempDir;
+use wiremock::{Mock, MockServer, ResponseTemplate, matchers};
+
+fn mock_client(server: &MockServer) -> Client {
+    Client::new(Credentials::UserAuthToken {
+        token: "token".to_string(),
+    })
+    .unwrap()
+    .with_api_base(&format!("{}/", server.uri()))
+}
+
+#[tokio::test]
+async fn update_zone_updates_changed_record() {
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/zones/zone-id/dns_records"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [
+                {
+                    "id": "record-id",
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                }
+            ],
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(matchers::method("PUT"))
+        .and(matchers::path("/zones/zone-id/dns_records/record-id"))
+        .and(matchers::body_partial_json(serde_json::json!({
+            "name": "www.example.com",
+            "type": "A",
+            "content": "192.0.2.2"
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": {
+                "id": "record-id",
+                "name": "www.example.com",
+                "type": "A",
+                "content": "192.0.2.2"
+            },
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let zone: ZoneConfig = serde_json::from_str(
+        r#"{
+            "id": "zone-id",
+            "records": [
+                {
+                    "name": "www.example.com"
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let client: Client = mock_client(&server);
+
+    let addresses: Addresses = Addresses {
+        ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+        ..Default::default()
+    };
+
+    let ids: ZoneIds = update_zone(&client, &zone, None, addresses, false, false)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(ids.zone, "zone-id");
+    assert_eq!(ids.records.a["www.example.com"], ["record-id"]);
+    assert!(ids.records.aaaa.is_empty());
+}
+
+#[tokio::test]
+async fn record_map_keeps_records_with_the_same_name() {
+    let server: MockServer = MockServer::start().await;
+
+    let record = |id: &str, content: &str| -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "name": "www.example.com",
+            "type": "A",
+            "content": content
+        })
+    };
+    let result_info = |page: u32| -> serde_json::Value {
+        serde_json::json!({
+            "page": page,
+            "per_page": 2,
+            "total_pages": 2,
+            "count": 2,
+            "total_count": 3
+        })
+    };
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/zones/zone-id/dns_records"))
+        .and(matchers::query_param("page", "1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [record("first-id", "192.0.2.1"), record("second-id", "192.0.2.2")],
+            "result_info": result_info(1),
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // the second record shifted onto the next page while listing
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/zones/zone-id/dns_records"))
+        .and(matchers::query_param("page", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [record("second-id", "192.0.2.2"), record("third-id", "192.0.2.3")],
+            "result_info": result_info(2),
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client: Client = mock_client(&server);
+
+    let maps: RecordMaps = zone_record_map("zone-id", &client).await.unwrap();
+
+    let ids: Vec<&str> = maps.a["www.example.com"]
+        .iter()
+        .map(|record| record.id.as_str())
+        .collect();
+    assert_eq!(ids, ["first-id", "second-id", "third-id"]);
+}
+
+#[tokio::test]
+async fn force_update_with_matching_history() {
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("PUT"))
+        .and(matchers::path("/zones/zone-id/dns_records/record-id"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": {
+                "id": "record-id",
+                "name": "www.example.com",
+                "type": "A",
+                "content": "192.0.2.1"
+            },
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let zones = || -> Vec<ZoneConfig> {
+        serde_json::from_str(r#"[{"id": "zone-id", "records": [{"name": "www.example.com"}]}]"#)
+            .unwrap()
+    };
+
+    let history = || History {
+        ipv4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+        record_ids: HashMap::from([(
+            "zone-id".to_string(),
+            RecordIds {
+                a: HashMap::from([("www.example.com".to_string(), vec!["record-id".to_string()])]),
+                ..Default::default()
+            },
+        )]),
+        last_updated: Some(1),
+        ..Default::default()
+    };
+
+    let history_dir: TempDir = TempDir::new().unwrap();
+    let builder = || {
+        ConfigBuilder::new()
+            .a_command(vec!["echo".to_string(), "192.0.2.1".to_string()])
+            .zones(zones())
+            .history_path(history_dir.path().join("history.json"))
+    };
+
+    let clients: Clients = mock_client(&server).into();
+
+    // unchanged address, no requests
+    let config: Config = builder().build().unwrap();
+    let mut unchanged: History = history();
+    update(&config, &clients, &mut unchanged, &mut RunReport::default())
+        .await
+        .unwrap();
+    assert_eq!(unchanged.last_updated, Some(1));
+
+    let config: Config = builder().force(true).build().unwrap();
+    let mut forced: History = history();
+    update(&config, &clients, &mut forced, &mut RunReport::default())
+        .await
+        .unwrap();
+    assert!(forced.last_updated > Some(1));
+
+    let saved: History = serde_json::from_str(
+        &std::fs::read_to_string(config.history_path.as_ref().unwrap()).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(saved.last_updated, forced.last_updated);
+}
+
+#[tokio::test]
+async fn update_writes_report() {
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/zones/zone-id/dns_records"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [
+                {
+                    "id": "record-id",
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                }
+            ],
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(matchers::method("PUT"))
+        .and(matchers::path("/zones/zone-id/dns_records/record-id"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": {
+                "id": "record-id",
+                "name": "www.example.com",
+                "type": "A",
+                "content": "192.0.2.2"
+            },
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let zones: Vec<ZoneConfig> =
+        serde_json::from_str(r#"[{"id": "zone-id", "records": [{"name": "www.example.com"}]}]"#)
+            .unwrap();
+
+    let dir: TempDir = TempDir::new().unwrap();
+    let config: Config = ConfigBuilder::new()
+        .a_command(vec!["echo".to_string(), "192.0.2.2".to_string()])
+        .zones(zones)
+        .history_path(dir.path().join("history.json"))
+        .report_path(dir.path().join("report.json"))
+        .build()
+        .unwrap();
+
+    let clients: Clients = mock_client(&server).into();
+
+    let mut report: RunReport = RunReport::default();
+    let result = update(&config, &clients, &mut History::default(), &mut report).await;
+    report.finish(&result);
+    result.unwrap();
+
+    let report_path = config.report_path.as_ref().unwrap();
+    save_report(report_path, &report).unwrap();
+
+    let mut saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report_path).unwrap()).unwrap();
+    assert!(saved["finished"].as_u64().is_some());
+    saved["finished"] = serde_json::Value::Null;
+
+    assert_eq!(
+        saved,
+        serde_json::json!({
+            "finished": null,
+            "success": true,
+            "error": null,
+            "ipv4": "192.0.2.2",
+            "ipv6_prefix": null,
+            "ipv6_address": null,
+            "zones": [
+                {
+                    "zone": "zone-id",
+                    "records": [
+                        {
+                            "name": "www.example.com",
+                            "action": "update",
+                            "previous": {"type": "A", "content": "192.0.2.1"},
+                            "content": {"type": "A", "content": "192.0.2.2"}
+                        }
+                    ],
+                    "error": null
+                }
+            ]
+        })
+    );
+}
+
+#[tokio::test]
+async fn update_zone_batches_updates() {
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/zones/zone-id/dns_records"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [
+                {
+                    "id": "www-id",
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                },
+                {
+                    "id": "mail-id",
+                    "name": "mail.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                }
+            ],
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(matchers::method("POST"))
+        .and(matchers::path("/zones/zone-id/dns_records/batch"))
+        .and(matchers::body_partial_json(serde_json::json!({
+            "puts": [
+                {
+                    "id": "www-id",
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": "192.0.2.2"
+                },
+                {
+                    "id": "mail-id",
+                    "name": "mail.example.com",
+                    "type": "A",
+                    "content": "192.0.2.2"
+                }
+            ]
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": {
+                "puts": [
+                    {
+                        "id": "www-id",
+                        "name": "www.example.com",
+                        "type": "A",
+                        "content": "192.0.2.2"
+                    },
+                    {
+                        "id": "mail-id",
+                        "name": "mail.example.com",
+                        "type": "A",
+                        "content": "192.0.2.2"
+                    }
+                ]
+            },
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let zone: ZoneConfig = serde_json::from_str(
+        r#"{
+            "id": "zone-id",
+            "records": [
+                {
+                    "name": "www.example.com"
+                },
+                {
+                    "name": "mail.example.com"
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let client: Client = mock_client(&server);
+
+    let addresses: Addresses = Addresses {
+        ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+        ..Default::default()
+    };
+
+    let ids: ZoneIds = update_zone(&client, &zone, None, addresses, false, false)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(ids.records.a["www.example.com"], ["www-id"]);
+    assert_eq!(ids.records.a["mail.example.com"], ["mail-id"]);
+    assert_eq!(ids.changes.len(), 2);
+}
+
+#[tokio::test]
+async fn update_zone_batch_failure() {
+    let server: MockServer = MockServer::start().await;
+
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/zones/zone-id/dns_records"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "result": [
+                {
+                    "id": "www-id",
+                    "name": "www.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                },
+                {
+                    "id": "mail-id",
+                    "name": "mail.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1"
+                }
+            ],
+            "result_info": null,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(matchers::method("POST"))
+        .and(matchers::path("/zones/zone-id/dns_records/batch"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+            "result": null,
+            "success": false,
+            "errors": [{"code": 9005, "message": "Content for A record is invalid."}],
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let zone: ZoneConfig = serde_json::from_str(
+        r#"{"id": "zone-id", "records": [{"name": "www.example.com"}, {"name": "mail.example.com"}]}"#,
+    )
+    .unwrap();
+
+    let client: Client = mock_client(&server);
+
+    let addresses: Addresses = Addresses {
+        ipv4: Some(Ipv4Addr::new(192, 0, 2, 2)),
+        ..Default::default()
+    };
+
+    let e: anyhow::Error = update_zone(&client, &zone, None, addresses, false, false)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(e.to_string(), "Failed to update 2 records");
+
+    // a failure unrelated to the identifiers does not refresh cached identifiers
+    let cache: ZoneIds = ZoneIds {
+        zone: "zone-id".to_string(),
+        records: RecordIds {
+            a: HashMap::from([
+                ("www.example.com".to_string(), vec!["www-id".to_string()]),
+                ("mail.example.com".to_string(), vec!["mail-id".to_string()]),
+            ]),
+            ..Default::default()
+        },
+        changes: Vec::new(),
+    };
+    let e: anyhow::Error = update_zone(&client, &zone, Some(cache), addresses, false, false)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(e.to_string(), "Failed to update 2 records");
+}